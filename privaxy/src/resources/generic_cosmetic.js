@@ -0,0 +1,115 @@
+// Hides elements added to the page after it was loaded that match generic cosmetic filters.
+// Generic filters are looked up by class and id, so new class and id names are collected and
+// sent in batches to `endpoint`, which Privaxy answers on every site.
+(function (options) {
+    'use strict';
+
+    const BATCH_DELAY_MS = 100;
+
+    // Names the page was already filtered for, along with the pending ones.
+    const knownClasses = new Set(options.classes);
+    const knownIds = new Set(options.ids);
+    let pendingClasses = new Set();
+    let pendingIds = new Set();
+    let timer = null;
+
+    const appliedSelectors = new Set();
+    const style = document.createElement('style');
+    (document.head || document.documentElement).appendChild(style);
+
+    function collect(element) {
+        if (element.id && !knownIds.has(element.id)) {
+            knownIds.add(element.id);
+            pendingIds.add(element.id);
+        }
+
+        for (const name of element.classList) {
+            if (!knownClasses.has(name)) {
+                knownClasses.add(name);
+                pendingClasses.add(name);
+            }
+        }
+    }
+
+    function collectTree(root) {
+        collect(root);
+
+        for (const element of root.querySelectorAll('[id], [class]')) {
+            collect(element);
+        }
+    }
+
+    function schedule() {
+        if (timer === null && (pendingClasses.size > 0 || pendingIds.size > 0)) {
+            timer = setTimeout(send, BATCH_DELAY_MS);
+        }
+    }
+
+    function send() {
+        timer = null;
+
+        const body = JSON.stringify({
+            url: window.location.href,
+            classes: Array.from(pendingClasses),
+            ids: Array.from(pendingIds),
+        });
+        pendingClasses = new Set();
+        pendingIds = new Set();
+
+        fetch(options.endpoint, {
+            method: 'POST',
+            headers: { 'Content-Type': 'application/json' },
+            body,
+            credentials: 'omit',
+            cache: 'no-store',
+        })
+            .then(response => (response.ok ? response.json() : []))
+            .then(apply)
+            .catch(() => {});
+    }
+
+    function apply(selectors) {
+        for (const selector of selectors) {
+            if (appliedSelectors.has(selector)) {
+                continue;
+            }
+            appliedSelectors.add(selector);
+
+            // Rules are inserted one by one so that an invalid selector doesn't void the others.
+            try {
+                style.sheet.insertRule(
+                    `${selector} { display: none !important; }`,
+                    style.sheet.cssRules.length,
+                );
+            } catch (err) {
+                console.debug(`Privaxy: ignoring generic selector ${selector}`, err);
+            }
+        }
+    }
+
+    // Catches what scripts added before this one ran.
+    collectTree(document.documentElement);
+    schedule();
+
+    new MutationObserver(records => {
+        for (const record of records) {
+            if (record.type === 'attributes') {
+                collect(record.target);
+                continue;
+            }
+
+            for (const node of record.addedNodes) {
+                if (node.nodeType === Node.ELEMENT_NODE) {
+                    collectTree(node);
+                }
+            }
+        }
+
+        schedule();
+    }).observe(document.documentElement, {
+        childList: true,
+        subtree: true,
+        attributes: true,
+        attributeFilter: ['class', 'id'],
+    });
+})
@@ -0,0 +1,339 @@
+// Applies procedural cosmetic filters, whose selectors aren't valid CSS, by evaluating them
+// against the live DOM. Called with a list of `{ selector, style }` rules, elements matched by
+// rules without a style are hidden.
+(function (rules) {
+    'use strict';
+
+    const OPERATORS = [
+        '-abp-contains',
+        '-abp-has',
+        'has-text',
+        'has',
+        'matches-css',
+        'upward',
+        'xpath',
+    ];
+    const SCOPE_ATTRIBUTE = 'data-privaxy-scope';
+
+    // Reads the parenthesized argument starting at `start`, returns it along with the index
+    // following the closing parenthesis.
+    function readArgument(selector, start) {
+        let depth = 1;
+        let quote = null;
+
+        for (let i = start; i < selector.length; i++) {
+            const c = selector[i];
+
+            if (c === '\\') {
+                i++;
+            } else if (quote !== null) {
+                if (c === quote) {
+                    quote = null;
+                }
+            } else if (c === '"' || c === "'") {
+                quote = c;
+            } else if (c === '(') {
+                depth++;
+            } else if (c === ')' && --depth === 0) {
+                return [selector.slice(start, i), i + 1];
+            }
+        }
+
+        throw new Error(`Unbalanced parentheses in ${selector}`);
+    }
+
+    // Splits a selector into CSS parts and procedural operators, evaluated in order.
+    function parse(selector) {
+        const tasks = [];
+        let css = '';
+        let quote = null;
+        let bracketDepth = 0;
+
+        for (let i = 0; i < selector.length; i++) {
+            const c = selector[i];
+
+            if (quote === null && bracketDepth === 0 && c === ':') {
+                const operator = OPERATORS.find(
+                    operator => selector.startsWith(`${operator}(`, i + 1),
+                );
+
+                if (operator !== undefined) {
+                    const [argument, next] = readArgument(
+                        selector,
+                        i + operator.length + 2,
+                    );
+
+                    if (css.trim() !== '') {
+                        tasks.push({ css });
+                    }
+                    css = '';
+                    tasks.push({ operator, argument: argument.trim() });
+                    i = next - 1;
+                    continue;
+                }
+            }
+
+            if (c === '\\') {
+                css += c + (selector[i + 1] || '');
+                i++;
+                continue;
+            }
+
+            if (quote !== null) {
+                if (c === quote) {
+                    quote = null;
+                }
+            } else if (c === '"' || c === "'") {
+                quote = c;
+            } else if (c === '[') {
+                bracketDepth++;
+            } else if (c === ']') {
+                bracketDepth--;
+            }
+
+            css += c;
+        }
+
+        if (css.trim() !== '') {
+            tasks.push({ css });
+        }
+
+        for (const task of tasks) {
+            if (task.operator === 'has' || task.operator === '-abp-has') {
+                task.tasks = parse(task.argument);
+            } else if (task.operator === 'upward' && !/^\d+$/.test(task.argument)) {
+                task.tasks = parse(task.argument);
+            } else if (
+                task.operator === 'has-text' ||
+                task.operator === '-abp-contains'
+            ) {
+                task.pattern = toPattern(task.argument);
+            } else if (task.operator === 'matches-css') {
+                const separator = task.argument.indexOf(':');
+                task.property = task.argument.slice(0, separator).trim();
+                task.pattern = toPattern(task.argument.slice(separator + 1).trim());
+            }
+        }
+
+        return tasks;
+    }
+
+    function toPattern(argument) {
+        const regex = /^\/(.+)\/([imsu]*)$/.exec(argument);
+
+        if (regex !== null) {
+            return new RegExp(regex[1], regex[2]);
+        }
+
+        return {
+            test: text => text.includes(argument.replace(/^(["'])(.*)\1$/, '$2')),
+        };
+    }
+
+    // Queries CSS relative to `nodes`, a leading combinator applies to the nodes themselves.
+    function queryRelative(nodes, css) {
+        const trimmed = css.trim();
+        const isRelative = /^[>+~]/.test(trimmed) || /^\s/.test(css);
+        const results = [];
+
+        for (const node of nodes) {
+            if (!isRelative) {
+                if (node.matches(trimmed)) {
+                    results.push(node);
+                }
+                continue;
+            }
+
+            node.setAttribute(SCOPE_ATTRIBUTE, '');
+            try {
+                results.push(
+                    ...document.querySelectorAll(`[${SCOPE_ATTRIBUTE}] ${trimmed}`),
+                );
+            } finally {
+                node.removeAttribute(SCOPE_ATTRIBUTE);
+            }
+        }
+
+        return results;
+    }
+
+    // Evaluates tasks starting from `nodes`, or from the whole document when `nodes` is null.
+    function evaluate(tasks, nodes) {
+        for (const task of tasks) {
+            if (task.css !== undefined) {
+                nodes =
+                    nodes === null
+                        ? Array.from(document.querySelectorAll(task.css))
+                        : queryRelative(nodes, task.css);
+            } else {
+                nodes = applyOperator(
+                    task,
+                    nodes === null ? Array.from(document.querySelectorAll('*')) : nodes,
+                );
+            }
+
+            if (nodes.length === 0) {
+                break;
+            }
+        }
+
+        return Array.from(new Set(nodes));
+    }
+
+    function applyOperator(task, nodes) {
+        switch (task.operator) {
+            case 'has':
+            case '-abp-has':
+                return nodes.filter(node => {
+                    const relativeTasks = task.tasks.slice();
+
+                    // Plain selectors look for descendants.
+                    if (relativeTasks[0] && relativeTasks[0].css !== undefined) {
+                        relativeTasks[0] = { css: ` ${relativeTasks[0].css}` };
+                    } else {
+                        relativeTasks.unshift({ css: ' *' });
+                    }
+
+                    return evaluate(relativeTasks, [node]).length > 0;
+                });
+            case 'has-text':
+            case '-abp-contains':
+                return nodes.filter(node => task.pattern.test(node.textContent));
+            case 'matches-css':
+                return nodes.filter(node =>
+                    task.pattern.test(
+                        window.getComputedStyle(node).getPropertyValue(task.property),
+                    ),
+                );
+            case 'upward':
+                return nodes
+                    .map(node => {
+                        if (task.tasks === undefined) {
+                            let ancestor = node;
+                            for (
+                                let i = 0;
+                                ancestor !== null && i < Number(task.argument);
+                                i++
+                            ) {
+                                ancestor = ancestor.parentElement;
+                            }
+                            return ancestor;
+                        }
+
+                        let ancestor = node.parentElement;
+                        while (
+                            ancestor !== null &&
+                            evaluate(task.tasks, [ancestor]).length === 0
+                        ) {
+                            ancestor = ancestor.parentElement;
+                        }
+                        return ancestor;
+                    })
+                    .filter(node => node !== null);
+            case 'xpath': {
+                const results = [];
+
+                for (const node of nodes) {
+                    const snapshot = document.evaluate(
+                        task.argument,
+                        node,
+                        null,
+                        XPathResult.ORDERED_NODE_SNAPSHOT_TYPE,
+                        null,
+                    );
+
+                    for (let i = 0; i < snapshot.snapshotLength; i++) {
+                        const result = snapshot.snapshotItem(i);
+                        if (result.nodeType === Node.ELEMENT_NODE) {
+                            results.push(result);
+                        }
+                    }
+                }
+
+                return results;
+            }
+        }
+
+        return [];
+    }
+
+    function applyStyle(node, style) {
+        if (style === null) {
+            node.style.setProperty('display', 'none', 'important');
+            return;
+        }
+
+        for (const declaration of style.split(';')) {
+            const separator = declaration.indexOf(':');
+            if (separator === -1) {
+                continue;
+            }
+
+            const property = declaration.slice(0, separator).trim();
+            let value = declaration.slice(separator + 1).trim();
+            const important = /!important$/.test(value);
+            value = value.replace(/\s*!important$/, '');
+
+            node.style.setProperty(property, value, important ? 'important' : '');
+        }
+    }
+
+    const compiledRules = [];
+
+    for (const rule of rules) {
+        try {
+            compiledRules.push({
+                tasks: parse(rule.selector),
+                style: rule.style,
+                applied: new WeakSet(),
+            });
+        } catch (err) {
+            console.debug(`Privaxy: ignoring procedural filter ${rule.selector}`, err);
+        }
+    }
+
+    function run() {
+        for (const rule of compiledRules) {
+            let nodes;
+
+            try {
+                nodes = evaluate(rule.tasks, null);
+            } catch (err) {
+                continue;
+            }
+
+            for (const node of nodes) {
+                if (!rule.applied.has(node)) {
+                    rule.applied.add(node);
+                    applyStyle(node, rule.style);
+                }
+            }
+        }
+    }
+
+    let scheduled = false;
+
+    function schedule() {
+        if (scheduled) {
+            return;
+        }
+        scheduled = true;
+
+        window.requestAnimationFrame(() => {
+            scheduled = false;
+            run();
+        });
+    }
+
+    run();
+
+    if (document.readyState === 'loading') {
+        document.addEventListener('DOMContentLoaded', run, { once: true });
+    }
+
+    new MutationObserver(schedule).observe(document.documentElement, {
+        childList: true,
+        subtree: true,
+        characterData: true,
+    });
+})
@@ -0,0 +1,410 @@
+//! Translates AdGuard specific cosmetic rules to the uBlock Origin syntax adblock-rust understands.
+//!
+//! - `#%#//scriptlet(...)` scriptlet calls become `##+js(...)` ones, using the uBlock Origin
+//! scriptlet that AdGuard's is compatible with
+//!
+//! - `#$#` CSS injections become `:style()` rules
+//!
+//! - `#?#` extended selectors become procedural ones
+//!
+//! Other AdGuard specific cosmetic rules, such as JavaScript injections, can't be translated.
+use std::borrow::Cow;
+
+/// AdGuard scriptlets and the uBlock Origin scriptlets taking the same arguments.
+const SCRIPTLETS: &[(&str, &str)] = &[
+    ("abort-current-inline-script", "abort-current-script.js"),
+    ("abort-on-property-read", "abort-on-property-read.js"),
+    ("abort-on-property-write", "abort-on-property-write.js"),
+    ("abort-on-stack-trace", "abort-on-stack-trace.js"),
+    ("adjust-setInterval", "adjust-setInterval.js"),
+    ("adjust-setTimeout", "adjust-setTimeout.js"),
+    ("call-nothrow", "call-nothrow.js"),
+    ("close-window", "close-window.js"),
+    ("disable-newtab-links", "disable-newtab-links.js"),
+    ("evaldata-prune", "evaldata-prune.js"),
+    ("href-sanitizer", "href-sanitizer.js"),
+    ("json-prune", "json-prune.js"),
+    ("json-prune-fetch-response", "json-prune-fetch-response.js"),
+    ("json-prune-xhr-response", "json-prune-xhr-response.js"),
+    ("m3u-prune", "m3u-prune.js"),
+    ("nowebrtc", "nowebrtc.js"),
+    ("prevent-addEventListener", "addEventListener-defuser.js"),
+    ("prevent-eval-if", "noeval-if.js"),
+    ("prevent-fetch", "prevent-fetch.js"),
+    ("prevent-refresh", "prevent-refresh.js"),
+    (
+        "prevent-requestAnimationFrame",
+        "no-requestAnimationFrame-if.js",
+    ),
+    ("prevent-setInterval", "no-setInterval-if.js"),
+    ("prevent-setTimeout", "no-setTimeout-if.js"),
+    ("prevent-window-open", "no-window-open-if.js"),
+    ("prevent-xhr", "no-xhr-if.js"),
+    ("remove-attr", "remove-attr.js"),
+    ("remove-class", "remove-class.js"),
+    ("remove-cookie", "remove-cookie.js"),
+    ("remove-node-text", "remove-node-text.js"),
+    ("set-attr", "set-attr.js"),
+    ("set-constant", "set-constant.js"),
+    ("set-cookie", "set-cookie.js"),
+    ("set-cookie-reload", "set-cookie-reload.js"),
+    ("set-local-storage-item", "set-local-storage-item.js"),
+    ("set-session-storage-item", "set-session-storage-item.js"),
+    ("spoof-css", "spoof-css.js"),
+    ("xml-prune", "xml-prune.js"),
+];
+
+/// AdGuard extended selector pseudo-classes and their uBlock Origin procedural equivalents.
+const EXTENDED_PSEUDO_CLASSES: &[(&str, &str)] = &[
+    (":-abp-has(", ":has("),
+    (":-abp-contains(", ":has-text("),
+    (":contains(", ":has-text("),
+    (":if-not(", ":not("),
+    (":matches-property(", ":matches-prop("),
+    (":nth-ancestor(", ":upward("),
+];
+
+/// Separators of AdGuard specific cosmetic rules.
+const SEPARATORS: &[&str] = &["#%#", "#@%#", "#$#", "#@$#", "#$?#", "#@$?#", "#?#", "#@?#"];
+
+/// Separators of the cosmetic rules every blocker understands.
+const STANDARD_SEPARATORS: &[&str] = &["##", "#@#"];
+
+enum RuleTranslation {
+    /// The rule doesn't use AdGuard specific syntax.
+    Unchanged,
+    Translated(String),
+    Untranslatable,
+}
+
+pub(crate) struct TranslatedFilterList<'a> {
+    /// The filter list, left untouched when it has no AdGuard specific rules.
+    pub content: Cow<'a, str>,
+    /// AdGuard specific rules which were left out of the list as they can't be translated.
+    pub untranslatable_rules: Vec<&'a str>,
+}
+
+pub(crate) fn translate_filter_list(content: &str) -> TranslatedFilterList<'_> {
+    let mut untranslatable_rules = Vec::new();
+
+    if !SEPARATORS
+        .iter()
+        .any(|separator| content.contains(separator))
+    {
+        return TranslatedFilterList {
+            content: Cow::Borrowed(content),
+            untranslatable_rules,
+        };
+    }
+
+    let mut translated = String::with_capacity(content.len());
+
+    for line in content.lines() {
+        match translate_rule(line.trim()) {
+            RuleTranslation::Unchanged => translated.push_str(line),
+            RuleTranslation::Translated(rule) => translated.push_str(&rule),
+            RuleTranslation::Untranslatable => {
+                untranslatable_rules.push(line.trim());
+                continue;
+            }
+        }
+
+        translated.push('\n');
+    }
+
+    TranslatedFilterList {
+        content: Cow::Owned(translated),
+        untranslatable_rules,
+    }
+}
+
+fn translate_rule(rule: &str) -> RuleTranslation {
+    if rule.starts_with('!') {
+        return RuleTranslation::Unchanged;
+    }
+
+    // The first separator splits the rule, the selector may contain others.
+    let (position, separator) = match SEPARATORS
+        .iter()
+        .chain(STANDARD_SEPARATORS)
+        .filter_map(|separator| rule.find(separator).map(|position| (position, *separator)))
+        .min_by_key(|(position, _separator)| *position)
+    {
+        Some(split_rule) if !STANDARD_SEPARATORS.contains(&split_rule.1) => split_rule,
+        _ => return RuleTranslation::Unchanged,
+    };
+    let (domains, body) = (&rule[..position], &rule[position + separator.len()..]);
+
+    // Rules restricted to paths or urls use modifiers uBlock Origin doesn't have.
+    if domains.starts_with("[$") {
+        return RuleTranslation::Untranslatable;
+    }
+
+    let is_exception = separator.contains('@');
+    let ublock_separator = if is_exception { "#@#" } else { "##" };
+
+    let translated_body = match separator {
+        "#%#" | "#@%#" => translate_scriptlet(body),
+        "#$#" | "#@$#" => translate_css_injection(body),
+        "#?#" | "#@?#" => translate_extended_selector(body),
+        _ => None,
+    };
+
+    match translated_body {
+        Some(translated_body) => {
+            RuleTranslation::Translated(format!("{domains}{ublock_separator}{translated_body}"))
+        }
+        None => RuleTranslation::Untranslatable,
+    }
+}
+
+/// `//scriptlet('name', 'arg1', 'arg2')` becomes `+js(name, arg1, arg2)`.
+fn translate_scriptlet(body: &str) -> Option<String> {
+    let call = body.strip_prefix("//scriptlet(")?.strip_suffix(')')?;
+    let mut arguments = parse_scriptlet_arguments(call)?.into_iter();
+
+    let name = arguments.next()?;
+    let ublock_name = match name.strip_prefix("ubo-") {
+        // AdGuard can run uBlock Origin scriptlets by name as well.
+        Some(ublock_name) if ublock_name.ends_with(".js") => ublock_name.to_string(),
+        Some(ublock_name) => format!("{ublock_name}.js"),
+        None => SCRIPTLETS
+            .iter()
+            .find(|(adguard_name, _ublock_name)| *adguard_name == name)
+            .map(|(_adguard_name, ublock_name)| ublock_name.to_string())?,
+    };
+
+    let call = std::iter::once(ublock_name)
+        .chain(arguments.map(|argument| argument.replace(',', "\\,")))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Some(format!("+js({call})"))
+}
+
+/// Parses comma separated, single or double quoted, arguments.
+fn parse_scriptlet_arguments(call: &str) -> Option<Vec<String>> {
+    let mut arguments = Vec::new();
+    let mut chars = call.trim().chars().peekable();
+
+    while let Some(quote) = chars.next() {
+        if quote != '\'' && quote != '"' {
+            return None;
+        }
+
+        let mut argument = String::new();
+
+        loop {
+            match chars.next()? {
+                '\\' => match chars.next()? {
+                    escaped if escaped == quote => argument.push(escaped),
+                    escaped => {
+                        argument.push('\\');
+                        argument.push(escaped);
+                    }
+                },
+                c if c == quote => break,
+                c => argument.push(c),
+            }
+        }
+
+        arguments.push(argument);
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        match chars.next() {
+            Some(',') => while chars.next_if(|c| c.is_whitespace()).is_some() {},
+            Some(_) => return None,
+            None => break,
+        }
+    }
+
+    Some(arguments)
+}
+
+/// `selector { declarations }` becomes `selector:style(declarations)`.
+fn translate_css_injection(body: &str) -> Option<String> {
+    // Media queries have no uBlock Origin equivalent.
+    if body.starts_with('@') {
+        return None;
+    }
+
+    let (selector, declarations) = body.trim_end().strip_suffix('}')?.rsplit_once('{')?;
+    let (selector, declarations) = (selector.trim(), declarations.trim());
+
+    // AdGuard removes elements with `remove: true`, which isn't a style.
+    if selector.is_empty() || declarations.contains("remove:") {
+        return None;
+    }
+
+    Some(format!("{selector}:style({declarations})"))
+}
+
+fn translate_extended_selector(body: &str) -> Option<String> {
+    // Extended selectors given as attributes, such as `[-ext-has="..."]`.
+    if body.contains("[-ext-") {
+        return None;
+    }
+
+    Some(
+        EXTENDED_PSEUDO_CLASSES
+            .iter()
+            .fold(body.to_string(), |selector, (adguard, ublock)| {
+                selector.replace(adguard, ublock)
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translate(rule: &str) -> Option<String> {
+        match translate_rule(rule) {
+            RuleTranslation::Unchanged => Some(rule.to_string()),
+            RuleTranslation::Translated(rule) => Some(rule),
+            RuleTranslation::Untranslatable => None,
+        }
+    }
+
+    #[test]
+    fn translates_scriptlets() {
+        assert_eq!(
+            translate("example.com#%#//scriptlet('set-constant', 'ads', 'false')").as_deref(),
+            Some("example.com##+js(set-constant.js, ads, false)")
+        );
+        assert_eq!(
+            translate(r#"example.com#%#//scriptlet("prevent-setTimeout", "ad,banner")"#).as_deref(),
+            Some(r"example.com##+js(no-setTimeout-if.js, ad\,banner)")
+        );
+        assert_eq!(translate("#%#//scriptlet('unknown-scriptlet')"), None);
+    }
+
+    #[test]
+    fn unescapes_quotes_in_scriptlet_arguments() {
+        assert_eq!(
+            translate(r"example.com#%#//scriptlet('remove-node-text', '#text', 'it\'s an ad')")
+                .as_deref(),
+            Some("example.com##+js(remove-node-text.js, #text, it's an ad)")
+        );
+        assert_eq!(
+            translate(r#"example.com#%#//scriptlet("set-attr", "a", "title", "\"x\"")"#).as_deref(),
+            Some(r#"example.com##+js(set-attr.js, a, title, "x")"#)
+        );
+        assert_eq!(
+            translate(r"example.com#%#//scriptlet('set-constant', 'a\.b', '1')").as_deref(),
+            Some(r"example.com##+js(set-constant.js, a\.b, 1)")
+        );
+        assert_eq!(
+            translate("example.com#%#//scriptlet('set-constant', 'unterminated)"),
+            None
+        );
+    }
+
+    #[test]
+    fn runs_ublock_scriptlets_by_name() {
+        assert_eq!(
+            translate("example.com#%#//scriptlet('ubo-nowebrtc')").as_deref(),
+            Some("example.com##+js(nowebrtc.js)")
+        );
+        assert_eq!(
+            translate("example.com#%#//scriptlet('ubo-aopr.js', 'ads')").as_deref(),
+            Some("example.com##+js(aopr.js, ads)")
+        );
+    }
+
+    #[test]
+    fn translates_exceptions() {
+        assert_eq!(
+            translate("example.com#@%#//scriptlet('set-constant', 'ads', 'false')").as_deref(),
+            Some("example.com#@#+js(set-constant.js, ads, false)")
+        );
+        assert_eq!(
+            translate("example.com#@$#.ad { display: none !important; }").as_deref(),
+            Some("example.com#@#.ad:style(display: none !important;)")
+        );
+        assert_eq!(
+            translate("example.com#@?#div:-abp-has(.ad)").as_deref(),
+            Some("example.com#@#div:has(.ad)")
+        );
+    }
+
+    #[test]
+    fn translates_css_injections() {
+        assert_eq!(
+            translate("example.com#$#body { overflow: auto !important; }").as_deref(),
+            Some("example.com##body:style(overflow: auto !important;)")
+        );
+        assert_eq!(translate("example.com#$#.ad { remove: true; }"), None);
+        assert_eq!(
+            translate("example.com#$#@media (min-width: 800px) { .ad { display: none; } }"),
+            None
+        );
+    }
+
+    #[test]
+    fn translates_extended_selectors() {
+        assert_eq!(
+            translate("example.com#?#div:contains(Sponsored):nth-ancestor(2)").as_deref(),
+            Some("example.com##div:has-text(Sponsored):upward(2)")
+        );
+        assert_eq!(translate(r#"example.com#?#div[-ext-has=".ad"]"#), None);
+    }
+
+    #[test]
+    fn leaves_path_restricted_rules_out() {
+        assert_eq!(
+            translate("[$path=/page]example.com#%#//scriptlet('nowebrtc')"),
+            None
+        );
+        assert_eq!(
+            translate("[$path=/page]example.com#$#.ad { display: none; }"),
+            None
+        );
+    }
+
+    #[test]
+    fn splits_rules_on_their_first_separator() {
+        // `#@%#` and `#$?#` are not `#%#` and `#$#` rules.
+        assert_eq!(
+            translate("example.com#@%#//scriptlet('nowebrtc')").as_deref(),
+            Some("example.com#@#+js(nowebrtc.js)")
+        );
+        assert_eq!(
+            translate("example.com#$?#div:has(.ad) { display: none; }"),
+            None
+        );
+        assert_eq!(
+            translate(r##"example.com#$#div[title="#?#"] { display: none; }"##).as_deref(),
+            Some(r##"example.com##div[title="#?#"]:style(display: none;)"##)
+        );
+        assert_eq!(
+            translate(r##"example.com##div[title="#%#"]"##).as_deref(),
+            Some(r##"example.com##div[title="#%#"]"##)
+        );
+    }
+
+    #[test]
+    fn leaves_lists_without_adguard_rules_borrowed() {
+        let translated = translate_filter_list("||ads.example.com^\nexample.com##.ad\n! comment\n");
+
+        assert!(matches!(translated.content, Cow::Borrowed(_)));
+        assert!(translated.untranslatable_rules.is_empty());
+    }
+
+    #[test]
+    fn translates_lists() {
+        let translated = translate_filter_list(
+            "||ads.example.com^\n! #%# comment\nexample.com#%#//scriptlet('nowebrtc')\nexample.com#%#window.ads = false;\n",
+        );
+
+        assert_eq!(
+            translated.content,
+            "||ads.example.com^\n! #%# comment\nexample.com##+js(nowebrtc.js)\n"
+        );
+        assert_eq!(
+            translated.untranslatable_rules,
+            vec!["example.com#%#window.ads = false;"]
+        );
+    }
+}
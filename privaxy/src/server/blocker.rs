@@ -1,32 +1,438 @@
 #![allow(clippy::unnecessary_operation)]
 #![allow(clippy::let_unit_value)]
-use crate::blocker_utils::{
-    build_resource_from_file_contents, read_redirectable_resource_mapping, read_template_resources,
-};
+use crate::adguard;
+use crate::blocker_utils::assemble_resources;
+use crate::configuration::{FailMode, FilterListContent};
+use crate::proxy::allowlist::AllowlistStore;
 use adblock::blocker::BlockerResult as AdblockerBlockerResult;
 use adblock::lists::FilterSet;
 use adblock::request::Request;
 use adblock::resources::Resource;
 use adblock::Engine;
+use chrono::{DateTime, Utc};
 use crossbeam_channel::{Receiver, Sender};
 use include_dir::{include_dir, Dir};
 use lazy_static::lazy_static;
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+use std::thread;
+use std::time::Instant;
+use thiserror::Error;
 use tokio::sync::oneshot;
 
 pub type AdblockRequestChannel = Sender<BlockerRequest>;
 
-#[derive(Debug, Clone)]
-pub struct BlockingDisabledStore(pub Arc<RwLock<bool>>);
+/// Number of network blocking decisions remembered by `AdblockRequester`.
+const DECISION_CACHE_SIZE: usize = 1_024;
+
+/// Number of separately locked parts the decision cache is split into, so that requests
+/// seldom wait on each other to look decisions up.
+const DECISION_CACHE_SHARDS: usize = 16;
+
+/// Request type proxied requests are checked with, as the proxy can't tell what they are for.
+pub(crate) const NETWORK_REQUEST_TYPE: &str = "other";
+
+/// Request types understood by the blocking engine.
+const REQUEST_TYPES: &[&str] = &[
+    "beacon",
+    "csp_report",
+    "document",
+    "font",
+    "image",
+    "imageset",
+    "main_frame",
+    "media",
+    "object",
+    "object_subrequest",
+    "other",
+    "ping",
+    "script",
+    "stylesheet",
+    "sub_frame",
+    "subdocument",
+    "websocket",
+    "xhr",
+    "xmlhttprequest",
+];
+
+/// Pseudo-classes of procedural cosmetic filters. Their selectors aren't valid CSS and are
+/// evaluated against the live DOM by the procedural runtime instead.
+const PROCEDURAL_OPERATORS: &[&str] = &[
+    ":-abp-contains(",
+    ":-abp-has(",
+    ":has(",
+    ":has-text(",
+    ":matches-css(",
+    ":upward(",
+    ":xpath(",
+];
+
+pub(crate) fn parse_request_type(request_type: &str) -> Result<&'static str, BlockerError> {
+    REQUEST_TYPES
+        .iter()
+        .find(|known_request_type| **known_request_type == request_type)
+        .copied()
+        .ok_or_else(|| {
+            BlockerError::InvalidRequest(format!("unknown request type `{request_type}`"))
+        })
+}
+
+/// Whether requests of this type load a page, which cosmetic filters apply to.
+pub(crate) fn is_document_request_type(request_type: &str) -> bool {
+    matches!(request_type, "document" | "main_frame")
+}
+
+/// Engine shared by all the blocker threads. Replacing the inner `Arc` swaps the engine
+/// for every thread at once, while checks in flight finish with the engine they started with.
+pub type SharedEngine = Arc<RwLock<Arc<AttributedEngine>>>;
+
+/// A blocking engine along with the filter lists its rules come from.
+pub struct AttributedEngine {
+    engine: Engine,
+    attribution: FilterAttribution,
+}
+
+impl AttributedEngine {
+    fn check_network_request(&self, request: &Request) -> NetworkBlockerResult {
+        let blocker_result = self.engine.check_network_request(request);
+
+        let filter_list = blocker_result
+            .filter
+            .as_deref()
+            .and_then(|filter| self.attribution.network_list_name(filter))
+            .map(str::to_string);
+
+        NetworkBlockerResult {
+            filter_list,
+            ..blocker_result.into()
+        }
+    }
+}
+
+/// Maps network rules and cosmetic selectors to the name of the filter list they come from.
+/// Rules are keyed by their hash so that filter lists aren't kept in memory a second time.
+#[derive(Debug, Default)]
+struct FilterAttribution {
+    list_names: Vec<String>,
+    lists_by_rule: HashMap<u64, usize>,
+}
+
+impl FilterAttribution {
+    fn new(filter_lists: &[FilterListContent]) -> Self {
+        let mut attribution = Self::default();
+
+        for (index, filter_list) in filter_lists.iter().enumerate() {
+            attribution.list_names.push(filter_list.name.clone());
+
+            for line in filter_list.content.lines().map(str::trim) {
+                if is_comment(line) {
+                    continue;
+                }
+
+                // Rules found in several lists are attributed to the first one.
+                let keys = std::iter::once(line).chain(cosmetic_selector(line));
+                for key in keys {
+                    attribution
+                        .lists_by_rule
+                        .entry(rule_hash(key))
+                        .or_insert(index);
+                }
+            }
+        }
+
+        attribution
+    }
+
+    fn list_name(&self, rule: &str) -> Option<&str> {
+        self.lists_by_rule
+            .get(&rule_hash(rule))
+            .map(|&index| self.list_names[index].as_str())
+    }
+
+    fn network_list_name(&self, filter: &str) -> Option<&str> {
+        // Rules merged together by the engine optimizer are reported joined by ` <+> `.
+        self.list_name(filter)
+            .or_else(|| filter.split(" <+> ").find_map(|rule| self.list_name(rule)))
+    }
+
+    /// Filter lists the selectors come from, without duplicates.
+    fn cosmetic_list_names<'a>(&self, selectors: impl Iterator<Item = &'a String>) -> Vec<String> {
+        let mut list_names = Vec::new();
+
+        for list_name in selectors.filter_map(|selector| self.list_name(selector)) {
+            if !list_names.iter().any(|name| name == list_name) {
+                list_names.push(list_name.to_string());
+            }
+        }
+
+        list_names
+    }
+}
+
+/// What a pause of blocking applies to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PauseScope {
+    Everything,
+    /// Requests made by a single client.
+    Client(IpAddr),
+    /// Requests made from a site or one of its subdomains.
+    Site(String),
+}
+
+impl PauseScope {
+    fn applies_to(&self, client: IpAddr, site: &str) -> bool {
+        match self {
+            PauseScope::Everything => true,
+            PauseScope::Client(paused_client) => *paused_client == client,
+            PauseScope::Site(paused_site) => {
+                site == paused_site
+                    || site
+                        .strip_suffix(paused_site.as_str())
+                        .is_some_and(|subdomain| subdomain.ends_with('.'))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Pause {
+    pub id: u64,
+    pub scope: PauseScope,
+    /// When blocking resumes by itself, pauses without one last until they are removed.
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl Pause {
+    fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.until.is_none_or(|until| until > now)
+    }
+}
+
+/// Pauses along with the last id handed out, so that the ids of removed pauses are
+/// never reused.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Pauses {
+    last_id: u64,
+    pauses: Vec<Pause>,
+    /// Bumped on every change, to tell which of two saves is the most recent.
+    #[serde(skip)]
+    version: u64,
+}
+
+impl Pauses {
+    fn add(&mut self, scope: PauseScope, duration: Option<chrono::Duration>) -> Pause {
+        self.last_id += 1;
+
+        let pause = Pause {
+            id: self.last_id,
+            scope,
+            until: duration.map(|duration| Utc::now() + duration),
+        };
+
+        self.pauses.push(pause.clone());
+        pause
+    }
+}
+
+/// Pauses of blocking, saved to disk so that they outlive restarts.
+#[derive(Debug, Clone, Default)]
+pub struct BlockingDisabledStore {
+    pauses: Arc<RwLock<Pauses>>,
+    path: Option<PathBuf>,
+    /// Version of the pauses the file holds.
+    saved_version: Arc<tokio::sync::Mutex<u64>>,
+}
 
 impl BlockingDisabledStore {
+    /// Loads the pauses saved at `path`, which changes are saved to from then on.
+    pub fn load(path: PathBuf) -> Self {
+        let pauses = match std::fs::read(&path) {
+            Ok(pauses) => serde_json::from_slice::<Pauses>(&pauses).unwrap_or_else(|err| {
+                log::warn!("Ignoring invalid pauses file {path:?}: {err}");
+                Pauses::default()
+            }),
+            Err(_err) => Pauses::default(),
+        };
+
+        Self {
+            pauses: Arc::new(RwLock::new(pauses)),
+            path: Some(path),
+            saved_version: Arc::default(),
+        }
+    }
+
+    /// Whether blocking is enabled, scoped pauses aside.
     pub fn is_enabled(&self) -> bool {
-        !*self.0.read().unwrap()
+        let now = Utc::now();
+
+        !self
+            .pauses
+            .read()
+            .unwrap()
+            .pauses
+            .iter()
+            .any(|pause| pause.scope == PauseScope::Everything && pause.is_active(now))
     }
 
-    pub fn set(&self, enabled: bool) {
-        *self.0.write().unwrap() = !enabled
+    /// Whether blocking applies to requests made by `client` from `site`.
+    pub fn is_enabled_for(&self, client: IpAddr, site: &str) -> bool {
+        let now = Utc::now();
+
+        !self
+            .pauses
+            .read()
+            .unwrap()
+            .pauses
+            .iter()
+            .any(|pause| pause.is_active(now) && pause.scope.applies_to(client, site))
+    }
+
+    /// Enables blocking, or pauses it for everything until it is enabled again.
+    pub async fn set(&self, enabled: bool) {
+        self.update(|pauses| {
+            pauses
+                .pauses
+                .retain(|pause| pause.scope != PauseScope::Everything);
+
+            if !enabled {
+                pauses.add(PauseScope::Everything, None);
+            }
+        })
+        .await
+    }
+
+    pub fn pauses(&self) -> Vec<Pause> {
+        let now = Utc::now();
+
+        self.pauses
+            .read()
+            .unwrap()
+            .pauses
+            .iter()
+            .filter(|pause| pause.is_active(now))
+            .cloned()
+            .collect()
+    }
+
+    /// Pauses blocking for `scope`, during `duration` or until the pause is removed.
+    pub async fn pause(&self, scope: PauseScope, duration: Option<chrono::Duration>) -> Pause {
+        self.update(|pauses| pauses.add(scope, duration)).await
+    }
+
+    /// Removes a pause, returning whether it existed.
+    pub async fn resume(&self, id: u64) -> bool {
+        self.update(|pauses| {
+            let count = pauses.pauses.len();
+            pauses.pauses.retain(|pause| pause.id != id);
+            pauses.pauses.len() != count
+        })
+        .await
+    }
+
+    async fn update<T>(&self, update: impl FnOnce(&mut Pauses) -> T) -> T {
+        let (result, version, serialized) = {
+            let mut pauses = self.pauses.write().unwrap();
+
+            let now = Utc::now();
+            pauses.pauses.retain(|pause| pause.is_active(now));
+            let result = update(&mut pauses);
+            pauses.version += 1;
+
+            (result, pauses.version, serde_json::to_vec(&*pauses))
+        };
+
+        if let Some(path) = &self.path {
+            // Saves can finish out of order, the file is never overwritten with older pauses.
+            let mut saved_version = self.saved_version.lock().await;
+
+            if *saved_version < version {
+                let result = match serialized {
+                    Ok(serialized) => tokio::fs::write(path, serialized).await,
+                    Err(err) => Err(err.into()),
+                };
+
+                match result {
+                    Ok(()) => *saved_version = version,
+                    Err(err) => log::error!("Unable to save pauses to {path:?}: {err}"),
+                }
+            }
+        }
+
+        result
+    }
+}
+
+/// Figures about the last engine that was built.
+#[derive(Debug, Clone, Serialize)]
+pub struct EngineBuildStats {
+    pub filter_lists: usize,
+    pub network_rules: usize,
+    pub cosmetic_rules: usize,
+    pub resources: usize,
+    /// Number of AdGuard specific rules left out of each filter list as they can't be translated.
+    pub untranslatable_rules: BTreeMap<String, usize>,
+    pub build_duration_ms: u128,
+    pub built_at: DateTime<Utc>,
+    /// Whether the engine was loaded from the on-disk cache instead of being built.
+    pub from_cache: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EngineStatsStore {
+    stats: Arc<RwLock<Option<EngineBuildStats>>>,
+    /// Bumped every time an engine is swapped in.
+    generation: Arc<AtomicU64>,
+}
+
+impl EngineStatsStore {
+    pub fn get(&self) -> Option<EngineBuildStats> {
+        self.stats.read().unwrap().clone()
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    fn set(&self, stats: EngineBuildStats) {
+        *self.stats.write().unwrap() = Some(stats);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Outcome of a network request check.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NetworkBlockerResult {
+    pub matched: bool,
+    pub important: bool,
+    pub redirect: Option<String>,
+    pub rewritten_url: Option<String>,
+    pub exception: Option<String>,
+    pub filter: Option<String>,
+    /// Filter list `filter` comes from.
+    pub filter_list: Option<String>,
+}
+
+impl From<AdblockerBlockerResult> for NetworkBlockerResult {
+    fn from(blocker_result: AdblockerBlockerResult) -> Self {
+        Self {
+            matched: blocker_result.matched,
+            important: blocker_result.important,
+            redirect: blocker_result.redirect,
+            rewritten_url: blocker_result.rewritten_url,
+            exception: blocker_result.exception,
+            filter: blocker_result.filter,
+            filter_list: None,
+        }
     }
 }
 
@@ -41,26 +447,66 @@ pub struct CosmeticRequest {
 pub struct NetworkUrl {
     url: String,
     referer: String,
+    request_type: &'static str,
 }
 
 #[derive(Debug)]
 pub enum RequestKind {
-    Url(NetworkUrl),
+    /// Network requests, checked at once.
+    Urls(Vec<NetworkUrl>),
     Cosmetic(CosmeticRequest),
-    ReplaceEngine(Vec<String>),
+    /// Only looks up generic selectors, for elements added to a page after it was loaded.
+    GenericCosmetic(CosmeticRequest),
+    ReplaceEngine(EngineSources),
+}
+
+/// What a blocking engine is built from.
+#[derive(Debug)]
+pub struct EngineSources {
+    pub filters: Vec<FilterListContent>,
+    /// uBlock Origin resources, used by redirect and scriptlet rules.
+    pub resources: Vec<Resource>,
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum BlockerError {
+    #[error("blocking engine is unavailable")]
+    Unavailable,
+    #[error("unable to check request: {0}")]
+    InvalidRequest(String),
 }
 
 #[derive(Debug)]
 pub enum BlockerResult {
-    Network(adblock::blocker::BlockerResult),
+    /// Outcome of each of the checked network requests, in order.
+    Network(Vec<Result<NetworkBlockerResult, BlockerError>>),
     Cosmetic(CosmeticBlockerResult),
+    GenericCosmetic(Vec<String>),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
+pub struct ProceduralRule {
+    pub selector: String,
+    /// Style applied to matched elements, which are hidden when there is none.
+    pub style: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct CosmeticBlockerResult {
     pub hidden_selectors: Vec<String>,
     pub style_selectors: HashMap<String, Vec<String>>,
+    /// Rules with procedural selectors, kept out of `hidden_selectors` and `style_selectors`.
+    pub procedural_rules: Vec<ProceduralRule>,
     pub injected_script: Option<String>,
+    /// Filter lists the hidden and styled selectors come from.
+    pub filter_lists: Vec<String>,
+    /// Whether generic cosmetic filters are disabled for the page.
+    pub generichide: bool,
+}
+
+/// Blocker threads answer each request kind with its own result, any other one is a bug.
+fn log_unexpected_result(blocker_result: &BlockerResult) {
+    log::error!("Unexpected answer from the blocker: {blocker_result:?}");
 }
 
 pub struct BlockerRequest {
@@ -69,73 +515,188 @@ pub struct BlockerRequest {
 }
 
 pub struct Blocker {
-    pub sender: Sender<BlockerRequest>,
     receiver: Receiver<BlockerRequest>,
-    engine: Engine,
+    engine: SharedEngine,
     blocking_disabled: BlockingDisabledStore,
+    engine_build_sender: Sender<EngineSources>,
 }
 
 lazy_static! {
     static ref ADBLOCKING_RESOURCES: Vec<Resource> = {
-        let mut resources =
-            read_template_resources(include_str!("../resources/vendor/ublock/scriptlets.js"));
-
         static WEB_ACCESSIBLE_RESOURCES: Dir = include_dir!(
             "$CARGO_MANIFEST_DIR/src/resources/vendor/ublock/web_accessible_resources/"
         );
 
-        let resource_properties = read_redirectable_resource_mapping(include_str!(
-            "../resources/vendor/ublock/redirect-resources.js"
-        ));
-
-        resources.extend(resource_properties.iter().filter_map(|resource_info| {
-            WEB_ACCESSIBLE_RESOURCES
-                .get_file(&resource_info.name)
-                .map(|resource| {
-                    build_resource_from_file_contents(resource.contents(), resource_info)
-                })
-        }));
-
-        resources
+        assemble_resources(
+            include_str!("../resources/vendor/ublock/scriptlets.js"),
+            include_str!("../resources/vendor/ublock/redirect-resources.js"),
+            |name| {
+                WEB_ACCESSIBLE_RESOURCES
+                    .get_file(name)
+                    .map(|file| file.contents())
+            },
+        )
+        .expect("embedded resources are valid")
     };
 }
 
+/// Resources vendored with Privaxy, used when no other copy is configured or can be loaded.
+pub(crate) fn embedded_resources() -> Vec<Resource> {
+    ADBLOCKING_RESOURCES.clone()
+}
+
 impl Blocker {
     pub fn new(
-        sender: Sender<BlockerRequest>,
         receiver: Receiver<BlockerRequest>,
+        engine: SharedEngine,
         blocking_disabled: BlockingDisabledStore,
+        engine_build_sender: Sender<EngineSources>,
     ) -> Self {
         Self {
-            sender,
             receiver,
-            engine: Engine::new(true),
+            engine,
             blocking_disabled,
+            engine_build_sender,
         }
     }
 
-    pub fn handle_requests(mut self) {
+    /// Starts `workers` threads answering requests sent to `receiver`, sharing a single engine.
+    /// As the channel is multi-consumer, each request is picked up by whichever thread is idle.
+    ///
+    /// Engines are built on a thread of their own so that checks keep being answered
+    /// with the previous engine in the meantime.
+    pub fn start_pool(
+        workers: usize,
+        receiver: Receiver<BlockerRequest>,
+        blocking_disabled: BlockingDisabledStore,
+        engine_stats: EngineStatsStore,
+    ) {
+        let engine: SharedEngine = Arc::new(RwLock::new(Arc::new(AttributedEngine {
+            engine: Engine::new(true),
+            attribution: FilterAttribution::default(),
+        })));
+        let (engine_build_sender, engine_build_receiver) = crossbeam_channel::unbounded();
+
+        let shared_engine = engine.clone();
+        let engine_cache_path = crate::configuration::get_engine_cache_file();
+        thread::Builder::new()
+            .name("blocker-builder".to_string())
+            .spawn(move || {
+                Self::build_engines(
+                    engine_build_receiver,
+                    shared_engine,
+                    engine_stats,
+                    engine_cache_path,
+                )
+            })
+            .unwrap();
+
+        for worker in 0..workers {
+            let blocker = Self::new(
+                receiver.clone(),
+                engine.clone(),
+                blocking_disabled.clone(),
+                engine_build_sender.clone(),
+            );
+
+            thread::Builder::new()
+                .name(format!("blocker-{worker}"))
+                .spawn(move || blocker.supervise())
+                .unwrap();
+        }
+
+        log::info!("Started {workers} blocker threads");
+    }
+
+    fn build_engines(
+        engine_build_receiver: Receiver<EngineSources>,
+        engine: SharedEngine,
+        engine_stats: EngineStatsStore,
+        engine_cache_path: PathBuf,
+    ) {
+        let mut current_fingerprint = None;
+
+        while let Ok(mut sources) = engine_build_receiver.recv() {
+            // Building takes a while, only the most recent sources are worth building
+            // when several replacements piled up in the meantime.
+            if let Some(latest_sources) = engine_build_receiver.try_iter().last() {
+                sources = latest_sources;
+            }
+
+            let fingerprint = filters_fingerprint(&sources.filters);
+            let resources_fingerprint = resources_fingerprint(&sources.resources);
+
+            if current_fingerprint == Some((fingerprint, resources_fingerprint)) {
+                log::debug!(
+                    "Filters and resources did not change, keeping the current blocking engine"
+                );
+                continue;
+            }
+
+            log::debug!("Configuring blocking engine.");
+
+            let (attributed_engine, stats) = match panic::catch_unwind(|| {
+                build_engine(sources, &fingerprint, &engine_cache_path)
+            }) {
+                Ok(built) => built,
+                Err(_panic) => {
+                    log::error!("Failed to build the blocking engine, keeping the current one");
+                    continue;
+                }
+            };
+
+            log::info!(
+                "{} blocking engine from {} filter lists ({} network rules, {} cosmetic rules, {} resources) in {}ms",
+                if stats.from_cache { "Loaded" } else { "Built" },
+                stats.filter_lists,
+                stats.network_rules,
+                stats.cosmetic_rules,
+                stats.resources,
+                stats.build_duration_ms
+            );
+
+            *engine.write().unwrap() = Arc::new(attributed_engine);
+            engine_stats.set(stats);
+            current_fingerprint = Some((fingerprint, resources_fingerprint));
+        }
+    }
+
+    fn current_engine(&self) -> Arc<AttributedEngine> {
+        self.engine.read().unwrap().clone()
+    }
+
+    /// Answers requests until the channel is closed. A panic while answering a request only
+    /// loses that request, whose requester gets `BlockerError::Unavailable`, before the thread
+    /// goes back to answering the next ones.
+    fn supervise(self) {
+        while panic::catch_unwind(AssertUnwindSafe(|| self.handle_requests())).is_err() {
+            log::error!(
+                "Blocker thread {} panicked, restarting it",
+                thread::current().name().unwrap_or_default()
+            );
+        }
+    }
+
+    pub fn handle_requests(&self) {
         while let Ok(request) = self.receiver.recv() {
             match request.kind {
                 RequestKind::Cosmetic(cosmetic_request) => {
                     if !self.blocking_disabled.is_enabled() {
-                        let _ = request.respond_to.send(BlockerResult::Cosmetic(
-                            CosmeticBlockerResult {
-                                hidden_selectors: Vec::new(),
-                                style_selectors: HashMap::new(),
-                                injected_script: None,
-                            },
-                        ));
+                        let _ = request
+                            .respond_to
+                            .send(BlockerResult::Cosmetic(CosmeticBlockerResult::default()));
                         continue;
                     }
 
+                    let attributed_engine = self.current_engine();
+                    let engine = &attributed_engine.engine;
+
                     let mut hidden_selectors = Vec::new();
-                    let url_specific_resources = self
-                        .engine
-                        .url_cosmetic_resources(cosmetic_request.url.as_str());
+                    let url_specific_resources =
+                        engine.url_cosmetic_resources(cosmetic_request.url.as_str());
 
                     if !url_specific_resources.generichide {
-                        let generic_selectors = self.engine.hidden_class_id_selectors(
+                        let generic_selectors = engine.hidden_class_id_selectors(
                             &cosmetic_request.classes,
                             &cosmetic_request.ids,
                             &url_specific_resources.exceptions,
@@ -152,83 +713,525 @@ impl Blocker {
                         None
                     };
 
+                    let filter_lists = attributed_engine.attribution.cosmetic_list_names(
+                        hidden_selectors
+                            .iter()
+                            .chain(url_specific_resources.style_selectors.keys()),
+                    );
+
+                    let (procedural_hidden_selectors, hidden_selectors): (Vec<_>, Vec<_>) =
+                        hidden_selectors
+                            .into_iter()
+                            .partition(|selector| is_procedural_selector(selector));
+                    let (procedural_style_selectors, style_selectors): (
+                        HashMap<_, _>,
+                        HashMap<_, _>,
+                    ) = url_specific_resources
+                        .style_selectors
+                        .into_iter()
+                        .partition(|(selector, _style)| is_procedural_selector(selector));
+
+                    let procedural_rules =
+                        procedural_hidden_selectors
+                            .into_iter()
+                            .map(|selector| ProceduralRule {
+                                selector,
+                                style: None,
+                            })
+                            .chain(procedural_style_selectors.into_iter().map(
+                                |(selector, style)| ProceduralRule {
+                                    selector,
+                                    style: Some(style.join(";")),
+                                },
+                            ))
+                            .collect();
+
                     let _ =
                         request
                             .respond_to
                             .send(BlockerResult::Cosmetic(CosmeticBlockerResult {
                                 hidden_selectors,
-                                style_selectors: url_specific_resources.style_selectors,
+                                style_selectors,
+                                procedural_rules,
                                 injected_script,
+                                filter_lists,
+                                generichide: url_specific_resources.generichide,
                             }));
                 }
-                RequestKind::Url(network_url) => {
-                    if !self.blocking_disabled.is_enabled() {
-                        let _ = request.respond_to.send(BlockerResult::Network(
-                            AdblockerBlockerResult {
-                                matched: false,
-                                important: false,
-                                redirect: None,
-                                exception: None,
-                                filter: None,
-                                rewritten_url: None,
-                            },
-                        ));
-                        continue;
+                RequestKind::GenericCosmetic(cosmetic_request) => {
+                    let mut hidden_selectors = Vec::new();
+
+                    if self.blocking_disabled.is_enabled() {
+                        let attributed_engine = self.current_engine();
+                        let engine = &attributed_engine.engine;
+                        let url_specific_resources =
+                            engine.url_cosmetic_resources(cosmetic_request.url.as_str());
+
+                        if !url_specific_resources.generichide {
+                            hidden_selectors = engine.hidden_class_id_selectors(
+                                &cosmetic_request.classes,
+                                &cosmetic_request.ids,
+                                &url_specific_resources.exceptions,
+                            );
+                        }
                     }
 
-                    let req = Request::new(
-                        network_url.url.as_str(),
-                        network_url.referer.as_str(),
-                        "other",
-                    )
-                    .unwrap();
-                    let blocker_result = self.engine.check_network_request(&req);
+                    // Procedural selectors are only applied to the page as it was loaded.
+                    hidden_selectors.retain(|selector| !is_procedural_selector(selector));
 
                     let _ = request
                         .respond_to
-                        .send(BlockerResult::Network(blocker_result));
+                        .send(BlockerResult::GenericCosmetic(hidden_selectors));
                 }
-                RequestKind::ReplaceEngine(filters) => {
-                    log::debug!("Configuring blocking engine.");
-
-                    let mut filter_set = FilterSet::new(true);
+                RequestKind::Urls(network_urls) => {
+                    let is_enabled = self.blocking_disabled.is_enabled();
+                    let engine = self.current_engine();
 
-                    for filter in filters {
-                        filter_set
-                            .add_filter_list(&filter, adblock::lists::ParseOptions::default());
-                    }
+                    let blocker_results = network_urls
+                        .iter()
+                        .map(|network_url| {
+                            if !is_enabled {
+                                return Ok(NetworkBlockerResult::default());
+                            }
 
-                    let mut adblock_engine = Engine::from_filter_set(filter_set, true);
-                    adblock_engine.use_resources(ADBLOCKING_RESOURCES.clone());
+                            Request::new(
+                                network_url.url.as_str(),
+                                network_url.referer.as_str(),
+                                network_url.request_type,
+                            )
+                            .map(|req| engine.check_network_request(&req))
+                            .map_err(|err| {
+                                BlockerError::InvalidRequest(format!(
+                                    "{}: {:?}",
+                                    network_url.url, err
+                                ))
+                            })
+                        })
+                        .collect();
 
-                    self.engine = adblock_engine;
+                    let _ = request
+                        .respond_to
+                        .send(BlockerResult::Network(blocker_results));
+                }
+                RequestKind::ReplaceEngine(sources) => {
+                    let _ = self.engine_build_sender.send(sources);
                 }
             }
         }
     }
 }
 
+/// Whether a trimmed filter list line is a comment or a header rather than a rule.
+fn is_comment(line: &str) -> bool {
+    line.is_empty() || line.starts_with('!') || line.starts_with('[')
+}
+
+/// Whether a filter list line holds a cosmetic rule, using the same separators as adblock-rust.
+fn is_cosmetic_rule(line: &str) -> bool {
+    ["##", "#@#", "#?#", "#@?#", "#$#", "#@$#"]
+        .iter()
+        .any(|separator| line.contains(separator))
+}
+
+/// Selector hidden or styled by a cosmetic rule, as the engine reports it.
+fn cosmetic_selector(line: &str) -> Option<&str> {
+    let (_domains, selector) = line.split_once("##").or_else(|| line.split_once("#?#"))?;
+
+    // Scriptlets don't hide anything.
+    if selector.starts_with("+js(") {
+        return None;
+    }
+
+    Some(
+        selector
+            .split_once(":style(")
+            .map_or(selector, |(selector, _style)| selector),
+    )
+}
+
+fn is_procedural_selector(selector: &str) -> bool {
+    PROCEDURAL_OPERATORS
+        .iter()
+        .any(|operator| selector.contains(operator))
+}
+
+fn rule_hash(rule: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    rule.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Identifies the filters an engine is built from. Serialized engines are only
+/// compatible with the version of Privaxy that produced them, which is part of it too.
+fn filters_fingerprint(filters: &[FilterListContent]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(env!("CARGO_PKG_VERSION"));
+
+    for filter in filters {
+        for part in [&filter.name, &filter.content] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+    }
+
+    hasher.finalize().into()
+}
+
+/// Resources are not part of the serialized engine, so they don't need to be part of the
+/// fingerprint of cached engines. They still decide whether an engine needs replacing.
+fn resources_fingerprint(resources: &[Resource]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+
+    for resource in resources {
+        resource.name.hash(&mut hasher);
+        resource.content.hash(&mut hasher);
+    }
+
+    hasher.finish()
+}
+
+/// The cache file holds the fingerprint of the filters followed by the serialized engine.
+fn load_cached_engine(engine_cache_path: &Path, fingerprint: &[u8; 32]) -> Option<Engine> {
+    let cache = std::fs::read(engine_cache_path).ok()?;
+
+    if cache.len() < fingerprint.len() {
+        return None;
+    }
+
+    let (cached_fingerprint, serialized_engine) = cache.split_at(fingerprint.len());
+
+    if cached_fingerprint != fingerprint {
+        log::debug!("Filters changed since the blocking engine was cached");
+        return None;
+    }
+
+    let mut engine = Engine::new(true);
+
+    match engine.deserialize(serialized_engine) {
+        Ok(()) => Some(engine),
+        Err(err) => {
+            log::warn!("Unable to load the cached blocking engine: {:?}", err);
+            None
+        }
+    }
+}
+
+fn save_engine_cache(engine_cache_path: &Path, fingerprint: &[u8; 32], engine: &Engine) {
+    let serialized_engine = match engine.serialize_raw() {
+        Ok(serialized_engine) => serialized_engine,
+        Err(err) => {
+            log::warn!("Unable to serialize the blocking engine: {:?}", err);
+            return;
+        }
+    };
+
+    // Written to a temporary file first so that a crash can't leave a truncated cache behind.
+    let temporary_path = engine_cache_path.with_extension("tmp");
+
+    let result = std::fs::write(
+        &temporary_path,
+        [&fingerprint[..], &serialized_engine].concat(),
+    )
+    .and_then(|()| std::fs::rename(&temporary_path, engine_cache_path));
+
+    if let Err(err) = result {
+        log::warn!("Unable to cache the blocking engine: {err}");
+    }
+}
+
+fn build_engine(
+    mut sources: EngineSources,
+    fingerprint: &[u8; 32],
+    engine_cache_path: &Path,
+) -> (AttributedEngine, EngineBuildStats) {
+    let started_at = Instant::now();
+    let mut untranslatable_rules = BTreeMap::new();
+
+    // adblock-rust drops or misparses AdGuard specific rules.
+    for filter in &mut sources.filters {
+        let translated = adguard::translate_filter_list(&filter.content);
+
+        if !translated.untranslatable_rules.is_empty() {
+            log::warn!(
+                "Left out {} rules of {} using AdGuard syntax that can't be translated",
+                translated.untranslatable_rules.len(),
+                filter.name
+            );
+
+            for rule in &translated.untranslatable_rules {
+                log::debug!("Untranslatable rule of {}: {rule}", filter.name);
+            }
+
+            untranslatable_rules.insert(filter.name.clone(), translated.untranslatable_rules.len());
+        }
+
+        if let Cow::Owned(content) = translated.content {
+            filter.content = content;
+        }
+    }
+
+    let filters = &sources.filters;
+    let resources = sources.resources.len();
+
+    let mut network_rules = 0;
+    let mut cosmetic_rules = 0;
+
+    for filter in filters {
+        for line in filter.content.lines().map(str::trim) {
+            if is_comment(line) {
+                continue;
+            }
+
+            if is_cosmetic_rule(line) {
+                cosmetic_rules += 1;
+            } else {
+                network_rules += 1;
+            }
+        }
+    }
+
+    let cached_engine = load_cached_engine(engine_cache_path, fingerprint);
+    let from_cache = cached_engine.is_some();
+
+    let mut adblock_engine = cached_engine.unwrap_or_else(|| {
+        let mut filter_set = FilterSet::new(true);
+
+        for filter in filters {
+            filter_set.add_filter_list(&filter.content, adblock::lists::ParseOptions::default());
+        }
+
+        let adblock_engine = Engine::from_filter_set(filter_set, true);
+        save_engine_cache(engine_cache_path, fingerprint, &adblock_engine);
+
+        adblock_engine
+    });
+
+    // Resources are not part of the serialized engine.
+    adblock_engine.use_resources(sources.resources);
+
+    let attributed_engine = AttributedEngine {
+        engine: adblock_engine,
+        attribution: FilterAttribution::new(filters),
+    };
+
+    let stats = EngineBuildStats {
+        filter_lists: filters.len(),
+        network_rules,
+        cosmetic_rules,
+        resources,
+        untranslatable_rules,
+        build_duration_ms: started_at.elapsed().as_millis(),
+        built_at: Utc::now(),
+        from_cache,
+    };
+
+    (attributed_engine, stats)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct DecisionKey {
+    url: String,
+    source_hostname: String,
+    request_type: &'static str,
+}
+
+impl DecisionKey {
+    fn new(url: &str, referer: &str, request_type: &'static str) -> Self {
+        let source_hostname = url::Url::parse(referer)
+            .ok()
+            .and_then(|referer| referer.host_str().map(str::to_string))
+            .unwrap_or_else(|| referer.to_string());
+
+        Self {
+            url: url.to_string(),
+            source_hostname,
+            request_type,
+        }
+    }
+}
+
+/// Part of the decision cache, evicting its least recently used decision when full.
+/// They only hold for the engine and the blocking state they were made with, the shard
+/// is emptied whenever one of them changes.
+#[derive(Debug, Default)]
+struct DecisionCacheShard {
+    /// Decisions along with when they were last used.
+    decisions: HashMap<DecisionKey, (NetworkBlockerResult, u64)>,
+    /// Keys of the decisions, by when they were last used.
+    uses: BTreeMap<u64, DecisionKey>,
+    last_use: u64,
+    engine_generation: u64,
+    blocking_enabled: bool,
+}
+
+impl DecisionCacheShard {
+    fn sync(&mut self, engine_generation: u64, blocking_enabled: bool) {
+        if !self.is_synced_with(engine_generation, blocking_enabled) {
+            self.decisions.clear();
+            self.uses.clear();
+            self.engine_generation = engine_generation;
+            self.blocking_enabled = blocking_enabled;
+        }
+    }
+
+    fn is_synced_with(&self, engine_generation: u64, blocking_enabled: bool) -> bool {
+        self.engine_generation == engine_generation && self.blocking_enabled == blocking_enabled
+    }
+
+    fn get(&mut self, key: &DecisionKey) -> Option<NetworkBlockerResult> {
+        let (result, last_use) = self.decisions.get_mut(key)?;
+
+        self.uses.remove(last_use);
+        self.last_use += 1;
+        *last_use = self.last_use;
+        self.uses.insert(self.last_use, key.clone());
+
+        Some(result.clone())
+    }
+
+    fn insert(&mut self, key: DecisionKey, result: NetworkBlockerResult) {
+        self.last_use += 1;
+
+        if let Some((_, last_use)) = self.decisions.insert(key.clone(), (result, self.last_use)) {
+            self.uses.remove(&last_use);
+        }
+        self.uses.insert(self.last_use, key);
+
+        if self.decisions.len() > DECISION_CACHE_SIZE / DECISION_CACHE_SHARDS {
+            if let Some((_, least_recently_used)) = self.uses.pop_first() {
+                self.decisions.remove(&least_recently_used);
+            }
+        }
+    }
+}
+
+/// Recently made network blocking decisions, spread over shards by key.
+#[derive(Debug, Default)]
+struct DecisionCache {
+    shards: [Mutex<DecisionCacheShard>; DECISION_CACHE_SHARDS],
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DecisionCache {
+    fn shard(&self, key: &DecisionKey) -> MutexGuard<'_, DecisionCacheShard> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+
+        self.shards[hasher.finish() as usize % DECISION_CACHE_SHARDS]
+            .lock()
+            .unwrap()
+    }
+
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().decisions.len())
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DecisionCacheStats {
+    pub entries: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BlockerStats {
+    /// Statistics about the engine currently in use, `None` until the first one is built.
+    pub engine: Option<EngineBuildStats>,
+    pub decision_cache: DecisionCacheStats,
+}
+
+/// Sends requests to the blocker threads, each request being answered by the first idle one.
 #[derive(Debug, Clone)]
 pub(crate) struct AdblockRequester {
     adblock_request_channel: AdblockRequestChannel,
+    engine_stats: EngineStatsStore,
+    blocking_disabled: BlockingDisabledStore,
+    allowlist: AllowlistStore,
+    decision_cache: Arc<DecisionCache>,
+    fail_mode: FailMode,
 }
 
 impl AdblockRequester {
-    pub(crate) fn new(adblock_request_channel: AdblockRequestChannel) -> Self {
+    pub(crate) fn new(
+        adblock_request_channel: AdblockRequestChannel,
+        engine_stats: EngineStatsStore,
+        blocking_disabled: BlockingDisabledStore,
+        allowlist: AllowlistStore,
+        fail_mode: FailMode,
+    ) -> Self {
         Self {
             adblock_request_channel,
+            engine_stats,
+            blocking_disabled,
+            allowlist,
+            decision_cache: Arc::new(DecisionCache::default()),
+            fail_mode,
         }
     }
 
-    pub(crate) async fn replace_engine(&self, filters: Vec<String>) {
-        let (sender, _receiver) = oneshot::channel();
+    pub(crate) fn stats(&self) -> BlockerStats {
+        let hits = self.decision_cache.hits.load(Ordering::Relaxed);
+        let misses = self.decision_cache.misses.load(Ordering::Relaxed);
+        let lookups = hits + misses;
+
+        BlockerStats {
+            engine: self.engine_stats.get(),
+            decision_cache: DecisionCacheStats {
+                entries: self.decision_cache.len(),
+                capacity: DECISION_CACHE_SIZE,
+                hits,
+                misses,
+                hit_rate: if lookups == 0 {
+                    0.0
+                } else {
+                    hits as f64 / lookups as f64
+                },
+            },
+        }
+    }
+
+    /// Whether blocking applies to requests made by `client` from `site`, it doesn't when
+    /// blocking is paused for them or when `site` is allowlisted.
+    pub(crate) fn is_blocking_enabled_for(&self, client: IpAddr, site: &str) -> bool {
+        self.blocking_disabled.is_enabled_for(client, site) && !self.allowlist.contains(site)
+    }
+
+    /// Sends a request to the blocker threads and waits for its answer.
+    async fn request(&self, kind: RequestKind) -> Result<BlockerResult, BlockerError> {
+        let (sender, receiver) = oneshot::channel();
 
         self.adblock_request_channel
             .send(BlockerRequest {
                 respond_to: sender,
-                kind: RequestKind::ReplaceEngine(filters),
+                kind,
             })
-            .unwrap();
+            .map_err(|_| BlockerError::Unavailable)?;
+
+        // The sender is dropped without answering when the blocker thread panicked.
+        receiver.await.map_err(|_| BlockerError::Unavailable)
+    }
+
+    pub(crate) async fn replace_engine(&self, sources: EngineSources) {
+        let (sender, _receiver) = oneshot::channel();
+
+        let result = self.adblock_request_channel.send(BlockerRequest {
+            respond_to: sender,
+            kind: RequestKind::ReplaceEngine(sources),
+        });
+
+        if result.is_err() {
+            log::error!(
+                "Unable to replace the blocking engine: {}",
+                BlockerError::Unavailable
+            );
+        }
     }
 
     pub(crate) async fn get_cosmetic_response(
@@ -237,47 +1240,210 @@ impl AdblockRequester {
         ids: Vec<String>,
         classes: Vec<String>,
     ) -> CosmeticBlockerResult {
-        let (sender, receiver) = oneshot::channel();
-
-        self.adblock_request_channel
-            .send(BlockerRequest {
-                respond_to: sender,
-                kind: RequestKind::Cosmetic(CosmeticRequest { url, ids, classes }),
-            })
-            .unwrap();
+        match self
+            .request(RequestKind::Cosmetic(CosmeticRequest { url, ids, classes }))
+            .await
+        {
+            Ok(BlockerResult::Cosmetic(blocker_result)) => blocker_result,
+            Ok(blocker_result) => {
+                log_unexpected_result(&blocker_result);
+                CosmeticBlockerResult::default()
+            }
+            // `fail_mode` only governs requests, pages are left untouched.
+            Err(err) => {
+                log::debug!("Unable to get cosmetic filters: {err}");
+                CosmeticBlockerResult::default()
+            }
+        }
+    }
 
-        match receiver.await {
-            Ok(blocker_result) => match blocker_result {
-                BlockerResult::Cosmetic(blocker_result) => blocker_result,
-                BlockerResult::Network(_) => unreachable!(),
-            },
-            Err(_err) => unreachable!(),
+    /// Generic hidden selectors matching classes and ids that appeared in a page once loaded.
+    pub(crate) async fn get_generic_cosmetic_selectors(
+        &self,
+        url: String,
+        ids: Vec<String>,
+        classes: Vec<String>,
+    ) -> Vec<String> {
+        match self
+            .request(RequestKind::GenericCosmetic(CosmeticRequest {
+                url,
+                ids,
+                classes,
+            }))
+            .await
+        {
+            Ok(BlockerResult::GenericCosmetic(hidden_selectors)) => hidden_selectors,
+            Ok(blocker_result) => {
+                log_unexpected_result(&blocker_result);
+                Vec::new()
+            }
+            Err(err) => {
+                log::debug!("Unable to get generic cosmetic filters: {err}");
+                Vec::new()
+            }
         }
     }
 
+    /// Checks whether a network request is to be blocked. Requests the blocking engine
+    /// is unavailable for are blocked or let through depending on `fail_mode`.
     pub(crate) async fn is_network_url_blocked(
         &self,
         network_url: String,
         referer: String,
-    ) -> (bool, adblock::blocker::BlockerResult) {
-        let (sender, receiver) = oneshot::channel();
+        request_type: &'static str,
+    ) -> (bool, NetworkBlockerResult) {
+        let blocker_result = self.apply_fail_mode(
+            self.check_network_url(network_url, referer, request_type)
+                .await,
+        );
 
-        self.adblock_request_channel
-            .send(BlockerRequest {
-                respond_to: sender,
-                kind: RequestKind::Url(NetworkUrl {
-                    url: network_url,
-                    referer,
-                }),
+        (blocker_result.matched, blocker_result)
+    }
+
+    /// Checks whether network requests made from the page at `referer`, given along with
+    /// their request type, are to be blocked, at once.
+    pub(crate) async fn are_network_urls_blocked(
+        &self,
+        network_urls: Vec<(String, &'static str)>,
+        referer: String,
+    ) -> Vec<NetworkBlockerResult> {
+        self.check_network_urls(network_urls, referer)
+            .await
+            .into_iter()
+            .map(|blocker_result| self.apply_fail_mode(blocker_result))
+            .collect()
+    }
+
+    fn apply_fail_mode(
+        &self,
+        blocker_result: Result<NetworkBlockerResult, BlockerError>,
+    ) -> NetworkBlockerResult {
+        match blocker_result {
+            Ok(blocker_result) => blocker_result,
+            Err(err @ BlockerError::InvalidRequest(_)) => {
+                log::debug!("{err}");
+                NetworkBlockerResult::default()
+            }
+            Err(err @ BlockerError::Unavailable) => {
+                let blocked = self.fail_mode == FailMode::Closed;
+                log::debug!("{err}, {}", if blocked { "blocking" } else { "allowing" });
+
+                NetworkBlockerResult {
+                    matched: blocked,
+                    filter: blocked.then(|| err.to_string()),
+                    ..Default::default()
+                }
+            }
+        }
+    }
+
+    /// Unlike `is_network_url_blocked`, errors are returned instead of being resolved
+    /// according to the fail mode.
+    pub(crate) async fn check_network_url(
+        &self,
+        network_url: String,
+        referer: String,
+        request_type: &'static str,
+    ) -> Result<NetworkBlockerResult, BlockerError> {
+        self.check_network_urls(vec![(network_url, request_type)], referer)
+            .await
+            .pop()
+            .unwrap_or(Err(BlockerError::Unavailable))
+    }
+
+    /// Looks decisions up in the cache, the requests missing from it are sent to the blocker
+    /// threads together.
+    async fn check_network_urls(
+        &self,
+        network_urls: Vec<(String, &'static str)>,
+        referer: String,
+    ) -> Vec<Result<NetworkBlockerResult, BlockerError>> {
+        let engine_generation = self.engine_stats.generation();
+        let blocking_enabled = self.blocking_disabled.is_enabled();
+
+        // The engine is empty until the first one is built.
+        if blocking_enabled && engine_generation == 0 {
+            return network_urls
+                .iter()
+                .map(|_network_url| Err(BlockerError::Unavailable))
+                .collect();
+        }
+
+        let keys = network_urls
+            .iter()
+            .map(|(network_url, request_type)| {
+                DecisionKey::new(network_url, &referer, request_type)
             })
-            .unwrap();
+            .collect::<Vec<_>>();
 
-        match receiver.await {
-            Ok(blocker_result) => match blocker_result {
-                BlockerResult::Network(blocker_result) => (blocker_result.matched, blocker_result),
-                BlockerResult::Cosmetic(_) => unreachable!(),
-            },
-            Err(_err) => unreachable!(),
+        let cached_results = keys
+            .iter()
+            .map(|key| {
+                let cached_result = {
+                    let mut shard = self.decision_cache.shard(key);
+                    shard.sync(engine_generation, blocking_enabled);
+                    shard.get(key)
+                };
+
+                let counter = match cached_result {
+                    Some(_) => &self.decision_cache.hits,
+                    None => &self.decision_cache.misses,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+
+                cached_result
+            })
+            .collect::<Vec<_>>();
+
+        let uncached_urls = network_urls
+            .into_iter()
+            .zip(&cached_results)
+            .filter(|(_network_url, cached_result)| cached_result.is_none())
+            .map(|((url, request_type), _cached_result)| NetworkUrl {
+                url,
+                referer: referer.clone(),
+                request_type,
+            })
+            .collect::<Vec<_>>();
+
+        let mut checked_results = if uncached_urls.is_empty() {
+            Vec::new()
+        } else {
+            let uncached_count = uncached_urls.len();
+
+            match self.request(RequestKind::Urls(uncached_urls)).await {
+                Ok(BlockerResult::Network(checked_results)) => checked_results,
+                // Taken as coming from an unavailable engine, leaving it to `fail_mode`.
+                Ok(blocker_result) => {
+                    log_unexpected_result(&blocker_result);
+                    vec![Err(BlockerError::Unavailable); uncached_count]
+                }
+                Err(err) => vec![Err(err); uncached_count],
+            }
         }
+        .into_iter();
+
+        keys.into_iter()
+            .zip(cached_results)
+            .map(|(key, cached_result)| {
+                if let Some(cached_result) = cached_result {
+                    return Ok(cached_result);
+                }
+
+                // Blocker threads answer with one result per request.
+                let blocker_result = checked_results
+                    .next()
+                    .unwrap_or(Err(BlockerError::Unavailable))?;
+                let mut shard = self.decision_cache.shard(&key);
+
+                // The engine or the blocking state may have changed while the request was
+                // checked.
+                if shard.is_synced_with(engine_generation, blocking_enabled) {
+                    shard.insert(key, blocker_result.clone());
+                }
+
+                Ok(blocker_result)
+            })
+            .collect()
     }
 }
@@ -7,6 +7,7 @@ use adblock::resources::{MimeType, PermissionMask, Resource, ResourceType};
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::Deserialize;
+use thiserror::Error;
 
 static TOP_COMMENT_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"^/\*[\S\s]+?\n\*/\s*"#).unwrap());
 static NON_EMPTY_LINE_RE: Lazy<Regex> = Lazy::new(|| Regex::new(r#"\S"#).unwrap());
@@ -27,6 +28,16 @@ pub struct ResourceProperties {
 }
 use base64::{engine::general_purpose, Engine};
 
+#[derive(Error, Debug)]
+pub enum ResourcesError {
+    #[error("invalid redirect resources mapping: {0}")]
+    InvalidMapping(String),
+    #[error("invalid scriptlets: {0}")]
+    InvalidScriptlets(String),
+    #[error("resource `{0}` is not valid utf8")]
+    InvalidResource(String),
+}
+
 /// The deserializable represenation of the `alias` field of a resource's properties, which can
 /// either be a single string or a list of strings.
 #[derive(Deserialize)]
@@ -71,7 +82,9 @@ static UNQUOTED_FIELD_RE: Lazy<Regex> =
 /// aliases.
 ///
 /// This is read from the exported `Map`.
-pub fn read_redirectable_resource_mapping(mapfile_data: &str) -> Vec<ResourceProperties> {
+pub fn read_redirectable_resource_mapping(
+    mapfile_data: &str,
+) -> Result<Vec<ResourceProperties>, ResourcesError> {
     // This isn't bulletproof, but it should handle the historical versions of the mapping
     // correctly, and having a strict JSON parser should catch any unexpected format changes. Plus,
     // it prevents dependending on a full JS engine.
@@ -91,7 +104,11 @@ pub fn read_redirectable_resource_mapping(mapfile_data: &str) -> Vec<ResourcePro
 
     // Trim out the beginning `export default new Map(`.
     // Also, replace all single quote characters with double quotes.
-    assert!(map.starts_with(REDIRECTABLE_RESOURCES_DECLARATION));
+    if !map.starts_with(REDIRECTABLE_RESOURCES_DECLARATION) {
+        return Err(ResourcesError::InvalidMapping(format!(
+            "missing `{REDIRECTABLE_RESOURCES_DECLARATION}`"
+        )));
+    }
     map = map[REDIRECTABLE_RESOURCES_DECLARATION.len() - 1..].replace('\'', "\"");
 
     // Remove all whitespace from the entire string.
@@ -106,9 +123,10 @@ pub fn read_redirectable_resource_mapping(mapfile_data: &str) -> Vec<ResourcePro
         .to_string();
 
     // It *should* be valid JSON now, so parse it with serde_json.
-    let parsed: Vec<JsResourceEntry> = serde_json::from_str(&map).unwrap();
+    let parsed: Vec<JsResourceEntry> = serde_json::from_str(&map)
+        .map_err(|err| ResourcesError::InvalidMapping(err.to_string()))?;
 
-    parsed
+    Ok(parsed
         .into_iter()
         .filter_map(|(name, props)| {
             // Ignore resources with params for now, since there's no support for them currently.
@@ -122,12 +140,12 @@ pub fn read_redirectable_resource_mapping(mapfile_data: &str) -> Vec<ResourcePro
                 })
             }
         })
-        .collect()
+        .collect())
 }
 
 /// Reads data from a file in the form of uBlock Origin's `scriptlets.js` file and produces
 /// templatable scriptlets for use in cosmetic filtering.
-pub fn read_template_resources(scriptlets_data: &str) -> Vec<Resource> {
+pub fn read_template_resources(scriptlets_data: &str) -> Result<Vec<Resource>, ResourcesError> {
     let mut resources = Vec::new();
 
     let uncommented = TOP_COMMENT_RE.replace_all(scriptlets_data, "");
@@ -149,8 +167,14 @@ pub fn read_template_resources(scriptlets_data: &str) -> Vec<Resource> {
 
         if let Some(stripped) = line.strip_prefix("/// ") {
             let mut line_parts = stripped.split_whitespace();
-            let prop = line_parts.next().expect("Detail line has property name");
-            let value = line_parts.next().expect("Detail line has property value");
+            let (prop, value) = match (line_parts.next(), line_parts.next()) {
+                (Some(prop), Some(value)) => (prop, value),
+                _ => {
+                    return Err(ResourcesError::InvalidScriptlets(format!(
+                        "detail line `{line}` needs a property name and a value"
+                    )))
+                }
+            };
             details.entry(prop).or_default().push(value);
             continue;
         }
@@ -168,7 +192,8 @@ pub fn read_template_resources(scriptlets_data: &str) -> Vec<Resource> {
         };
 
         resources.push(Resource {
-            name: name.expect("Resource name must be specified").to_owned(),
+            // Lines are only collected once a name was found.
+            name: name.unwrap_or_default().to_owned(),
             aliases: details
                 .remove("alias")
                 .unwrap_or_default()
@@ -186,7 +211,7 @@ pub fn read_template_resources(scriptlets_data: &str) -> Vec<Resource> {
         script.clear();
     }
 
-    resources
+    Ok(resources)
 }
 
 /// Reads byte data from an arbitrary resource file, and assembles a `Resource` from it with the
@@ -194,24 +219,44 @@ pub fn read_template_resources(scriptlets_data: &str) -> Vec<Resource> {
 pub fn build_resource_from_file_contents(
     resource_contents: &[u8],
     resource_info: &ResourceProperties,
-) -> Resource {
+) -> Result<Resource, ResourcesError> {
     let name = resource_info.name.clone();
     let aliases = resource_info.alias.clone();
     let mimetype = MimeType::from_extension(&resource_info.name);
     let content = match mimetype {
         MimeType::ApplicationJavascript | MimeType::TextHtml | MimeType::TextPlain => {
-            let utf8string = std::str::from_utf8(resource_contents).unwrap();
+            let utf8string = std::str::from_utf8(resource_contents)
+                .map_err(|_| ResourcesError::InvalidResource(resource_info.name.clone()))?;
             general_purpose::STANDARD.encode(utf8string.replace('\r', ""))
         }
         _ => general_purpose::STANDARD.encode(resource_contents),
     };
 
-    Resource {
+    Ok(Resource {
         name,
         aliases,
         kind: ResourceType::Mime(mimetype),
         content,
         dependencies: Vec::new(),
         permission: PermissionMask::default(),
+    })
+}
+
+/// Assembles the resources of a copy of uBlock Origin's `scriptlets.js`, `redirect-resources.js`
+/// and `web_accessible_resources` directory, whose files are looked up by name through
+/// `web_accessible_resource`. Resources missing from the directory are skipped.
+pub fn assemble_resources<'a>(
+    scriptlets_data: &str,
+    mapfile_data: &str,
+    web_accessible_resource: impl Fn(&str) -> Option<&'a [u8]>,
+) -> Result<Vec<Resource>, ResourcesError> {
+    let mut resources = read_template_resources(scriptlets_data)?;
+
+    for resource_info in read_redirectable_resource_mapping(mapfile_data)? {
+        if let Some(contents) = web_accessible_resource(&resource_info.name) {
+            resources.push(build_resource_from_file_contents(contents, &resource_info)?);
+        }
     }
+
+    Ok(resources)
 }
@@ -1,20 +1,252 @@
 #![allow(clippy::unnecessary_operation)]
 #![allow(clippy::let_unit_value)]
+use base64::{engine::general_purpose, Engine};
 use openssl::asn1::Asn1Time;
 use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
 use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
 use openssl::pkey::PKey;
 use openssl::pkey::Private;
 use openssl::rsa::Rsa;
 use openssl::x509::extension::{BasicConstraints, KeyUsage, SubjectKeyIdentifier};
 use openssl::x509::X509NameBuilder;
-use openssl::x509::X509;
+use openssl::x509::{X509Extension, X509};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
 
 const ORGANIZATION_NAME: &str = "Privaxy";
 
-pub fn make_ca_certificate() -> (X509, PKey<Private>) {
-    let rsa: Rsa<Private> = Rsa::generate(2048).unwrap();
-    let key_pair = PKey::from_rsa(rsa).unwrap();
+/// DER encoding of the `signedData` PKCS#7 content type (1.2.840.113549.1.7.2).
+const PKCS7_SIGNED_DATA_OID: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02,
+];
+
+/// DER encoding of the name constraints extension identifier (2.5.29.30).
+const NAME_CONSTRAINTS_OID: &[u8] = &[0x55, 0x1d, 0x1e];
+
+/// DER encoding of the `data` PKCS#7 content type (1.2.840.113549.1.7.1).
+const PKCS7_DATA_OID: &[u8] = &[
+    0x06, 0x09, 0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x01,
+];
+
+/// Longest validity we accept for a generated CA, in days.
+pub(crate) const MAX_CA_VALIDITY_DAYS: u32 = 3650 * 2;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CaKeyType {
+    #[default]
+    Rsa2048,
+    Rsa4096,
+    EcdsaP256,
+    EcdsaP384,
+}
+
+impl CaKeyType {
+    fn generate(self) -> PKey<Private> {
+        match self {
+            CaKeyType::Rsa2048 => PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap(),
+            CaKeyType::Rsa4096 => PKey::from_rsa(Rsa::generate(4096).unwrap()).unwrap(),
+            CaKeyType::EcdsaP256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+                PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+            }
+            CaKeyType::EcdsaP384 => {
+                let group = EcGroup::from_curve_name(Nid::SECP384R1).unwrap();
+                PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap()
+            }
+        }
+    }
+
+    fn message_digest(self) -> MessageDigest {
+        match self {
+            CaKeyType::EcdsaP384 => MessageDigest::sha384(),
+            _ => MessageDigest::sha256(),
+        }
+    }
+}
+
+/// Parameters of a CA to generate.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CaOptions {
+    #[serde(default)]
+    pub key_type: CaKeyType,
+    #[serde(default = "default_validity_days")]
+    pub validity_days: u32,
+}
+
+fn default_validity_days() -> u32 {
+    3650
+}
+
+impl Default for CaOptions {
+    fn default() -> Self {
+        Self {
+            key_type: CaKeyType::default(),
+            validity_days: default_validity_days(),
+        }
+    }
+}
+
+/// DNS subtrees a generated CA is restricted to, as defined in RFC 5280 (section 4.2.1.10).
+///
+/// A constraint like `example.com` covers the domain and all of its subdomains, while
+/// `.example.com` only covers subdomains.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NameConstraints {
+    #[serde(default)]
+    pub permitted: Vec<String>,
+    #[serde(default)]
+    pub excluded: Vec<String>,
+}
+
+impl NameConstraints {
+    pub fn is_empty(&self) -> bool {
+        self.permitted.is_empty() && self.excluded.is_empty()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        for constraint in self.permitted.iter().chain(self.excluded.iter()) {
+            let domain = constraint.strip_prefix('.').unwrap_or(constraint);
+
+            let is_valid = !domain.is_empty()
+                && domain.split('.').all(|label| {
+                    !label.is_empty()
+                        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                });
+
+            if !is_valid {
+                return Err(format!("invalid DNS name constraint: {constraint}"));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn subtree_matches(constraint: &str, host: &str) -> bool {
+        let constraint = constraint.to_ascii_lowercase();
+
+        if constraint.starts_with('.') {
+            host.ends_with(&constraint)
+        } else {
+            host == constraint || host.ends_with(&format!(".{constraint}"))
+        }
+    }
+
+    /// Whether a CA generated with these constraints may sign a certificate for `host`.
+    pub fn permits(&self, host: &str) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+
+        // Constrained CAs exclude every IP address.
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.parse::<IpAddr>().is_ok() {
+            return false;
+        }
+
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+
+        if self
+            .excluded
+            .iter()
+            .any(|constraint| Self::subtree_matches(constraint, &host))
+        {
+            return false;
+        }
+
+        self.permitted.is_empty()
+            || self
+                .permitted
+                .iter()
+                .any(|constraint| Self::subtree_matches(constraint, &host))
+    }
+
+    /// DNS name constraints of the name constraints extension of `certificate`, which
+    /// clients check the certificates it signs against.
+    pub fn from_certificate(certificate: &X509) -> Self {
+        certificate
+            .to_der()
+            .ok()
+            .and_then(|certificate| Self::decode(&certificate))
+            .unwrap_or_default()
+    }
+
+    // The openssl crate has no accessor for this extension.
+    fn decode(certificate: &[u8]) -> Option<Self> {
+        let (_, certificate) = der_elements(certificate).next()?;
+        let (_, tbs_certificate) = der_elements(certificate).next()?;
+        let (_, extensions) = der_elements(tbs_certificate).find(|(tag, _)| *tag == 0xa3)?;
+        let (_, extensions) = der_elements(extensions).next()?;
+
+        let value = der_elements(extensions).find_map(|(_, extension)| {
+            let mut fields = der_elements(extension);
+            match fields.next()? {
+                (0x06, NAME_CONSTRAINTS_OID) => {
+                    fields.find_map(|(tag, value)| (tag == 0x04).then_some(value))
+                }
+                _ => None,
+            }
+        })?;
+        let (_, subtrees) = der_elements(value).next()?;
+
+        let mut name_constraints = Self::default();
+
+        for (tag, subtrees) in der_elements(subtrees) {
+            let constraints = match tag {
+                0xa0 => &mut name_constraints.permitted,
+                0xa1 => &mut name_constraints.excluded,
+                _ => continue,
+            };
+
+            // Only DNS names (`[2] IA5String`) are checked, constrained CAs exclude every
+            // IP address anyway.
+            constraints.extend(der_elements(subtrees).filter_map(|(_, subtree)| {
+                match der_elements(subtree).next()? {
+                    (0x82, name) => Some(String::from_utf8_lossy(name).into_owned()),
+                    _ => None,
+                }
+            }));
+        }
+
+        Some(name_constraints)
+    }
+
+    // There is no builder for this extension, constraints are validated
+    // beforehand so they cannot inject anything into the configuration string.
+    #[allow(deprecated)]
+    fn build(&self) -> X509Extension {
+        let mut subtrees = self
+            .permitted
+            .iter()
+            .map(|constraint| format!("permitted;DNS:{constraint}"))
+            .chain(
+                self.excluded
+                    .iter()
+                    .map(|constraint| format!("excluded;DNS:{constraint}")),
+            )
+            .collect::<Vec<_>>();
+
+        // Without this, the CA would still be able to sign certificates for any IP address.
+        subtrees.push("excluded;IP:0.0.0.0/0.0.0.0".to_string());
+        subtrees.push("excluded;IP:::/::".to_string());
+
+        X509Extension::new_nid(
+            None,
+            None,
+            Nid::NAME_CONSTRAINTS,
+            &format!("critical,{}", subtrees.join(",")),
+        )
+        .unwrap()
+    }
+}
+
+pub fn make_ca_certificate(
+    options: &CaOptions,
+    name_constraints: &NameConstraints,
+) -> (X509, PKey<Private>) {
+    let key_pair = options.key_type.generate();
 
     let mut x509_name = X509NameBuilder::new().unwrap();
     x509_name.append_entry_by_text("C", "US").unwrap();
@@ -44,7 +276,7 @@ pub fn make_ca_certificate() -> (X509, PKey<Private>) {
     let not_before = Asn1Time::days_from_now(0).unwrap();
     cert_builder.set_not_before(&not_before).unwrap();
 
-    let not_after = Asn1Time::days_from_now(3650).unwrap();
+    let not_after = Asn1Time::days_from_now(options.validity_days).unwrap();
 
     cert_builder.set_not_after(&not_after).unwrap();
     cert_builder
@@ -61,6 +293,12 @@ pub fn make_ca_certificate() -> (X509, PKey<Private>) {
         )
         .unwrap();
 
+    if !name_constraints.is_empty() {
+        cert_builder
+            .append_extension(name_constraints.build())
+            .unwrap();
+    }
+
     let subject_key_identifier = SubjectKeyIdentifier::new()
         .build(&cert_builder.x509v3_context(None, None))
         .unwrap();
@@ -69,10 +307,194 @@ pub fn make_ca_certificate() -> (X509, PKey<Private>) {
         .append_extension(subject_key_identifier)
         .unwrap();
     cert_builder
-        .sign(&key_pair, MessageDigest::sha256())
+        .sign(&key_pair, options.key_type.message_digest())
         .unwrap();
 
     let cert = cert_builder.build();
 
     (cert, key_pair)
 }
+
+/// Colon separated uppercase SHA-256 fingerprint, the way browsers and operating systems display it.
+pub fn sha256_fingerprint(certificate: &X509) -> String {
+    certificate
+        .digest(MessageDigest::sha256())
+        .unwrap()
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn der_encode(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+
+    if content.len() < 0x80 {
+        encoded.push(content.len() as u8);
+    } else {
+        let length = content.len().to_be_bytes();
+        let length = &length[length.iter().take_while(|byte| **byte == 0).count()..];
+        encoded.push(0x80 | length.len() as u8);
+        encoded.extend_from_slice(length);
+    }
+
+    encoded.extend_from_slice(content);
+    encoded
+}
+
+/// Splits the first element off of `der`, returning its tag, its content and what follows it.
+fn der_decode(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, der) = der.split_first()?;
+    let (&length, mut der) = der.split_first()?;
+
+    let length = if length < 0x80 {
+        usize::from(length)
+    } else {
+        let length_size = usize::from(length & 0x7f);
+        if length_size > std::mem::size_of::<usize>() || der.len() < length_size {
+            return None;
+        }
+
+        let (length, rest) = der.split_at(length_size);
+        der = rest;
+        length
+            .iter()
+            .fold(0, |length, byte| (length << 8) | usize::from(*byte))
+    };
+
+    if der.len() < length {
+        return None;
+    }
+
+    let (content, rest) = der.split_at(length);
+    Some((tag, content, rest))
+}
+
+/// Tags and contents of the elements of `der`, up to the first malformed one.
+fn der_elements(mut der: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    std::iter::from_fn(move || {
+        let (tag, content, rest) = der_decode(der)?;
+        der = rest;
+        Some((tag, content))
+    })
+}
+
+/// Bundles certificates in a "certs-only" PKCS#7 structure (`.p7b`), as produced by
+/// `openssl crl2pkcs7 -nocrl`. The openssl crate can only build signed PKCS#7 messages.
+pub fn make_pkcs7_bundle(certificates: &[X509]) -> Vec<u8> {
+    let certificates_der = certificates
+        .iter()
+        .flat_map(|certificate| certificate.to_der().unwrap())
+        .collect::<Vec<_>>();
+
+    let signed_data = [
+        // Version
+        der_encode(0x02, &[0x01]),
+        // Digest algorithms
+        der_encode(0x31, &[]),
+        // Content info
+        der_encode(0x30, PKCS7_DATA_OID),
+        // Certificates
+        der_encode(0xa0, &certificates_der),
+        // Signer infos
+        der_encode(0x31, &[]),
+    ]
+    .concat();
+
+    der_encode(
+        0x30,
+        &[
+            PKCS7_SIGNED_DATA_OID.to_vec(),
+            der_encode(0xa0, &der_encode(0x30, &signed_data)),
+        ]
+        .concat(),
+    )
+}
+
+/// Derives a stable UUID from a certificate so that installing a profile again replaces
+/// the previous one instead of adding a duplicate.
+fn payload_uuid(seed: &[u8]) -> String {
+    let mut bytes = openssl::sha::sha256(seed);
+    // Version 4 and RFC 4122 variant bits.
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = hex::encode(&bytes[..16]);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Builds an Apple configuration profile installing the certificates as trusted roots.
+pub fn make_mobileconfig(certificates: &[X509]) -> String {
+    let certificate_payloads = certificates
+        .iter()
+        .enumerate()
+        .map(|(index, certificate)| {
+            let der = certificate.to_der().unwrap();
+            let uuid = payload_uuid(&der);
+
+            format!(
+                r#"        <dict>
+            <key>PayloadCertificateFileName</key>
+            <string>privaxy-ca-{index}.cer</string>
+            <key>PayloadContent</key>
+            <data>{content}</data>
+            <key>PayloadDescription</key>
+            <string>Adds the Privaxy CA root certificate</string>
+            <key>PayloadDisplayName</key>
+            <string>{ORGANIZATION_NAME} CA</string>
+            <key>PayloadIdentifier</key>
+            <string>com.privaxy.ca.{uuid}</string>
+            <key>PayloadType</key>
+            <string>com.apple.security.root</string>
+            <key>PayloadUUID</key>
+            <string>{uuid}</string>
+            <key>PayloadVersion</key>
+            <integer>1</integer>
+        </dict>
+"#,
+                content = general_purpose::STANDARD.encode(der),
+            )
+        })
+        .collect::<String>();
+
+    let profile_uuid = payload_uuid(
+        &certificates
+            .iter()
+            .flat_map(|certificate| certificate.to_der().unwrap())
+            .collect::<Vec<_>>(),
+    );
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>PayloadContent</key>
+    <array>
+{certificate_payloads}    </array>
+    <key>PayloadDescription</key>
+    <string>Installs the certificate authority Privaxy uses to filter HTTPS traffic</string>
+    <key>PayloadDisplayName</key>
+    <string>{ORGANIZATION_NAME}</string>
+    <key>PayloadIdentifier</key>
+    <string>com.privaxy.profile</string>
+    <key>PayloadRemovalDisallowed</key>
+    <false/>
+    <key>PayloadType</key>
+    <string>Configuration</string>
+    <key>PayloadUUID</key>
+    <string>{profile_uuid}</string>
+    <key>PayloadVersion</key>
+    <integer>1</integer>
+</dict>
+</plist>
+"#
+    )
+}
@@ -1,5 +1,8 @@
 #![allow(clippy::unnecessary_operation)]
 #![allow(clippy::let_unit_value)]
+use crate::ca::NameConstraints;
+use crate::configuration::{Ca, ConfigurationResult};
+use chrono::{DateTime, Duration, Utc};
 use http::uri::Authority;
 use openssl::{
     asn1::Asn1Time,
@@ -16,19 +19,45 @@ use openssl::{
     },
 };
 use rustls::{Certificate, PrivateKey, ServerConfig};
-use std::time::{SystemTime, UNIX_EPOCH};
-use std::{str::FromStr, sync::Arc};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
 use tokio::sync::Mutex;
 use uluru::LRUCache;
 
 const MAX_CACHED_CERTIFICATES: usize = 1_000;
 
+/// Validity of the leaf certificates we generate.
+const CERTIFICATE_VALIDITY_DAYS: i64 = 365;
+
+/// Cached certificates expiring within this window are regenerated on their next use.
+const CERTIFICATE_RENEWAL_WINDOW_DAYS: i64 = 30;
+
 #[derive(Clone)]
 pub struct SignedWithCaCert {
     authority: Authority,
+    certificate: X509,
+    not_before: DateTime<Utc>,
+    not_after: DateTime<Utc>,
     pub server_configuration: ServerConfig,
 }
 
+/// Description of a cached leaf certificate, as exposed by the API.
+#[derive(Debug, Serialize)]
+pub struct CachedCertificateInfo {
+    pub authority: String,
+    pub serial_number: String,
+    pub sha256_fingerprint: String,
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    pub needs_renewal: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pem: Option<String>,
+}
+
 impl SignedWithCaCert {
     pub(super) fn new(
         authority: Authority,
@@ -36,8 +65,18 @@ impl SignedWithCaCert {
         ca_certificate: X509,
         ca_private_key: PKey<Private>,
     ) -> Self {
-        let x509 =
-            Self::build_ca_signed_cert(&ca_certificate, &ca_private_key, &authority, &private_key);
+        // Backdate the certificate slightly to tolerate clients whose clock is a bit behind.
+        let not_before = Utc::now() - Duration::seconds(60);
+        let not_after = Utc::now() + Duration::days(CERTIFICATE_VALIDITY_DAYS);
+
+        let x509 = Self::build_ca_signed_cert(
+            &ca_certificate,
+            &ca_private_key,
+            &authority,
+            &private_key,
+            not_before,
+            not_after,
+        );
 
         let certs = vec![
             Certificate(x509.to_der().unwrap()),
@@ -55,10 +94,55 @@ impl SignedWithCaCert {
 
         Self {
             authority,
+            certificate: x509,
+            not_before,
+            not_after,
             server_configuration,
         }
     }
 
+    /// Whether the certificate is expired or about to, and should not be served anymore.
+    fn needs_renewal(&self) -> bool {
+        self.not_after - Duration::days(CERTIFICATE_RENEWAL_WINDOW_DAYS) <= Utc::now()
+    }
+
+    fn matches(&self, authority: &str) -> bool {
+        self.authority.as_str().eq_ignore_ascii_case(authority)
+            || self.authority.host().eq_ignore_ascii_case(authority)
+    }
+
+    fn info(&self, with_pem: bool) -> CachedCertificateInfo {
+        let serial_number = self
+            .certificate
+            .serial_number()
+            .to_bn()
+            .and_then(|serial| serial.to_hex_str().map(|hex| hex.to_string()))
+            .unwrap_or_default();
+
+        let sha256_fingerprint = self
+            .certificate
+            .digest(MessageDigest::sha256())
+            .map(hex::encode)
+            .unwrap_or_default();
+
+        CachedCertificateInfo {
+            authority: self.authority.to_string(),
+            serial_number,
+            sha256_fingerprint,
+            not_before: self.not_before,
+            not_after: self.not_after,
+            needs_renewal: self.needs_renewal(),
+            pem: if with_pem {
+                self.certificate
+                    .to_pem()
+                    .ok()
+                    .and_then(|pem| String::from_utf8(pem).ok())
+            } else {
+                None
+            },
+        }
+    }
+
     fn build_certificate_request(key_pair: &PKey<Private>, authority: &Authority) -> X509Req {
         let mut request_builder = X509ReqBuilder::new().unwrap();
         request_builder.set_pubkey(key_pair).unwrap();
@@ -92,6 +176,8 @@ impl SignedWithCaCert {
         ca_key_pair: &PKeyRef<Private>,
         authority: &Authority,
         private_key: &PKey<Private>,
+        not_before: DateTime<Utc>,
+        not_after: DateTime<Utc>,
     ) -> X509 {
         let req = Self::build_certificate_request(private_key, authority);
 
@@ -111,17 +197,10 @@ impl SignedWithCaCert {
             .unwrap();
         cert_builder.set_pubkey(private_key).unwrap();
 
-        let not_before = {
-            let current_time = SystemTime::now();
-            let since_epoch = current_time
-                .duration_since(UNIX_EPOCH)
-                .expect("Time went backwards");
-            // patch NotValidBefore
-            Asn1Time::from_unix((since_epoch.as_secs() as i64 - 60) as libc::time_t).unwrap()
-        };
+        let not_before = Asn1Time::from_unix(not_before.timestamp() as libc::time_t).unwrap();
         cert_builder.set_not_before(&not_before).unwrap();
 
-        let not_after = Asn1Time::days_from_now(365).unwrap();
+        let not_after = Asn1Time::from_unix(not_after.timestamp() as libc::time_t).unwrap();
         cert_builder.set_not_after(&not_after).unwrap();
 
         cert_builder
@@ -182,59 +261,249 @@ impl SignedWithCaCert {
     }
 }
 
-#[derive(Clone)]
-pub struct CertCache {
-    cache: Arc<Mutex<LRUCache<SignedWithCaCert, MAX_CACHED_CERTIFICATES>>>,
+/// The CA material leaf certificates are signed with.
+struct SigningAuthority {
     // We use a single RSA key for all certificates.
     private_key: PKey<Private>,
     ca_certificate: X509,
     ca_private_key: PKey<Private>,
+    name_constraints: NameConstraints,
+    /// Bumped whenever certificates signed so far stop being valid, so that certificates
+    /// generated in the meantime are not cached.
+    generation: u64,
+}
+
+type PendingGenerations = Arc<std::sync::Mutex<HashMap<Authority, Arc<Mutex<()>>>>>;
+
+/// Registration of a lookup of the certificate of an authority, letting one of them at a time
+/// generate it. The entry goes along with the last registration, even a cancelled one.
+struct PendingGeneration {
+    pending: PendingGenerations,
+    authority: Authority,
+    lock: Option<Arc<Mutex<()>>>,
+}
+
+impl PendingGeneration {
+    fn new(pending: &PendingGenerations, authority: Authority) -> Self {
+        let lock = Arc::clone(
+            pending
+                .lock()
+                .unwrap()
+                .entry(authority.clone())
+                .or_default(),
+        );
+
+        Self {
+            pending: Arc::clone(pending),
+            authority,
+            lock: Some(lock),
+        }
+    }
+
+    async fn lock(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.lock.as_ref().unwrap().lock().await
+    }
+}
+
+impl Drop for PendingGeneration {
+    fn drop(&mut self) {
+        let mut pending = self.pending.lock().unwrap();
+
+        let lock = match self.lock.take() {
+            Some(lock) => lock,
+            None => return,
+        };
+
+        // Registrations are made with `pending` locked, the entry and this registration
+        // holding the only references means no other one waits on it.
+        let is_last_registration = pending
+            .get(&self.authority)
+            .is_some_and(|entry| Arc::ptr_eq(entry, &lock))
+            && Arc::strong_count(&lock) == 2;
+
+        drop(lock);
+
+        if is_last_registration {
+            pending.remove(&self.authority);
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct CertCache {
+    cache: Arc<Mutex<LRUCache<SignedWithCaCert, MAX_CACHED_CERTIFICATES>>>,
+    signing_authority: Arc<RwLock<SigningAuthority>>,
+    /// Held while the certificate of an authority is looked up and generated, so that
+    /// concurrent requests for the same host wait for a single certificate.
+    pending: PendingGenerations,
 }
 
 impl CertCache {
     pub fn new(ca_certificate: X509, ca_private_key: PKey<Private>) -> Self {
         Self {
             cache: Arc::new(Mutex::new(LRUCache::default())),
-            private_key: {
-                let rsa: Rsa<Private> = Rsa::generate(2048).unwrap();
-                PKey::from_rsa(rsa).unwrap()
-            },
-            ca_certificate,
-            ca_private_key,
+            signing_authority: Arc::new(RwLock::new(SigningAuthority {
+                private_key: {
+                    let rsa: Rsa<Private> = Rsa::generate(2048).unwrap();
+                    PKey::from_rsa(rsa).unwrap()
+                },
+                name_constraints: NameConstraints::from_certificate(&ca_certificate),
+                ca_certificate,
+                ca_private_key,
+                generation: 0,
+            })),
+            pending: Arc::new(std::sync::Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Starts signing certificates with a different CA. Certificates signed by the
+    /// previous one are dropped.
+    pub async fn replace_ca(&self, ca_certificate: X509, ca_private_key: PKey<Private>) {
+        {
+            let mut signing_authority = self.signing_authority.write().unwrap();
+            signing_authority.name_constraints = NameConstraints::from_certificate(&ca_certificate);
+            signing_authority.ca_certificate = ca_certificate;
+            signing_authority.ca_private_key = ca_private_key;
+            signing_authority.generation += 1;
+        }
+
+        self.purge().await;
+    }
+
+    /// Makes sure certificates are signed with the CA `ca` currently designates,
+    /// switching over when it changed. Returns whether the CA was switched.
+    pub(crate) async fn sync_with(&self, ca: &Ca) -> ConfigurationResult<bool> {
+        let (ca_certificate, ca_private_key) = ca.get_signing_ca().await?;
+
+        let is_current = {
+            let signing_authority = self.signing_authority.read().unwrap();
+            signing_authority.ca_certificate.to_der()? == ca_certificate.to_der()?
+        };
+
+        if is_current {
+            return Ok(false);
         }
+
+        log::info!("Signing certificates with a new CA");
+        self.replace_ca(ca_certificate, ca_private_key).await;
+
+        Ok(true)
     }
 
-    async fn insert(&self, certificate: SignedWithCaCert) {
+    /// Certificates signed before the CA or its constraints changed are not cached.
+    async fn insert(&self, certificate: SignedWithCaCert, generation: u64) {
         let mut cache = self.cache.lock().await;
+
+        // Checked with the cache locked, a CA change after the check purges the cache
+        // only once the certificate is in.
+        if self.signing_authority.read().unwrap().generation != generation {
+            log::debug!(
+                "Not caching certificate for {}, the CA changed",
+                certificate.authority
+            );
+            return;
+        }
+
         cache.insert(certificate);
     }
 
-    pub async fn get(&self, authority: Authority) -> SignedWithCaCert {
-        let mut cache = self.cache.lock().await;
+    /// Whether the name constraints of the signing CA allow minting a certificate for `host`.
+    pub fn permits(&self, host: &str) -> bool {
+        self.signing_authority
+            .read()
+            .unwrap()
+            .name_constraints
+            .permits(host)
+    }
 
-        match cache.find(|cert| cert.authority == authority) {
-            Some(certificate) => certificate.clone(),
-            None => {
-                // We release the previously acquired lock early as `insert`, which we will call just
-                // afterwards also waits to acquire a lock.
-                std::mem::drop(cache);
+    /// Returns `None` when `authority` falls outside of the configured name constraints,
+    /// such hosts should be tunneled.
+    pub async fn get(&self, authority: Authority) -> Option<SignedWithCaCert> {
+        if !self.permits(authority.host()) {
+            return None;
+        }
 
-                let private_key = self.private_key.clone();
+        let pending_generation = PendingGeneration::new(&self.pending, authority.clone());
+        let _generating = pending_generation.lock().await;
 
-                let ca_certificate = self.ca_certificate.clone();
-                let ca_private_key = self.ca_private_key.clone();
+        Some(self.get_or_generate(authority).await)
+    }
 
-                // This operation is somewhat CPU intensive and on some lower powered machines,
-                // not running it inside of a thread pool may cause it to block the executor for too long.
-                let certificate = tokio::task::spawn_blocking(move || {
-                    SignedWithCaCert::new(authority, private_key, ca_certificate, ca_private_key)
-                })
-                .await
-                .unwrap();
+    async fn get_or_generate(&self, authority: Authority) -> SignedWithCaCert {
+        let is_renewal = {
+            let mut cache = self.cache.lock().await;
 
-                self.insert(certificate.clone()).await;
-                certificate
+            match cache.find(|cert| cert.authority == authority) {
+                Some(certificate) if !certificate.needs_renewal() => return certificate.clone(),
+                cached => cached.is_some(),
             }
+        };
+
+        if is_renewal {
+            log::debug!("Renewing certificate for {}", authority);
+            self.remove(authority.as_str()).await;
+        }
+
+        let (private_key, ca_certificate, ca_private_key, generation) = {
+            let signing_authority = self.signing_authority.read().unwrap();
+            (
+                signing_authority.private_key.clone(),
+                signing_authority.ca_certificate.clone(),
+                signing_authority.ca_private_key.clone(),
+                signing_authority.generation,
+            )
+        };
+
+        // This operation is somewhat CPU intensive and on some lower powered machines,
+        // not running it inside of a thread pool may cause it to block the executor for too long.
+        let certificate = tokio::task::spawn_blocking(move || {
+            SignedWithCaCert::new(authority, private_key, ca_certificate, ca_private_key)
+        })
+        .await
+        .unwrap();
+
+        self.insert(certificate.clone(), generation).await;
+        certificate
+    }
+
+    /// Lists cached certificates, most recently used first.
+    pub async fn list(&self) -> Vec<CachedCertificateInfo> {
+        let cache = self.cache.lock().await;
+
+        cache.iter().map(|cert| cert.info(false)).collect()
+    }
+
+    /// `authority` may either be a full authority (`host:port`) or just a host.
+    pub async fn inspect(&self, authority: &str) -> Option<CachedCertificateInfo> {
+        let mut cache = self.cache.lock().await;
+
+        cache
+            .find(|cert| cert.matches(authority))
+            .map(|cert| cert.info(true))
+    }
+
+    /// Removes the certificates cached for `authority`, returning whether there were any.
+    pub async fn remove(&self, authority: &str) -> bool {
+        let mut cache = self.cache.lock().await;
+
+        // `LRUCache` has no way of removing a single entry, we rebuild it instead,
+        // least recently used entries first so that the order is preserved.
+        let retained = cache
+            .iter()
+            .filter(|cert| !cert.matches(authority))
+            .cloned()
+            .collect::<Vec<_>>();
+        let removed = retained.len() != cache.len();
+
+        cache.clear();
+        for certificate in retained.into_iter().rev() {
+            cache.insert(certificate);
         }
+
+        removed
+    }
+
+    pub async fn purge(&self) {
+        self.cache.lock().await.clear();
     }
 }
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// Blocking engine configuration for Privaxy
+pub struct BlockerConfig {
+    /// Number of threads answering blocking requests.
+    /// Defaults to the number of available CPUs. Only applied on startup.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workers: Option<usize>,
+    /// What happens to requests while the blocking engine is unavailable. Only applied on startup.
+    #[serde(default)]
+    pub fail_mode: FailMode,
+    /// Directory or URL uBlock Origin resources used by redirect and scriptlet rules are
+    /// loaded from, refreshed along with filters. The embedded resources are used when unset
+    /// or when they can't be loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resources: Option<String>,
+}
+
+/// Policy applied to requests the blocking engine can't check, such as the ones made
+/// before the first engine is built or the ones a crashing blocker thread was handling.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FailMode {
+    /// Requests go through unfiltered.
+    #[default]
+    Open,
+    /// Requests are blocked.
+    Closed,
+}
+
+impl BlockerConfig {
+    pub fn worker_count(&self) -> usize {
+        match self.workers {
+            Some(workers) => workers.max(1),
+            None => std::thread::available_parallelism()
+                .map(|parallelism| parallelism.get())
+                .unwrap_or(1),
+        }
+    }
+}
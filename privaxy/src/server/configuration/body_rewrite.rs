@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+/// Transforms the bodies of responses matching a URL and a content type, reaching content
+/// network rules can't, such as ads embedded in JSON API responses.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct BodyRewriteRule {
+    pub name: String,
+    /// URL pattern, `*` matching any sequence of characters.
+    pub url: String,
+    /// Content type pattern, such as `application/json` or `text/*`. Every content type
+    /// matches when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(flatten)]
+    pub action: BodyRewriteAction,
+    #[serde(default = "super::default_enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum BodyRewriteAction {
+    /// Replaces the matches of a regular expression, `$1` in the replacement standing for
+    /// the first capture group.
+    RegexReplace {
+        pattern: String,
+        #[serde(default)]
+        replacement: String,
+    },
+    /// Removes the values at dot separated paths from JSON bodies, `*` matching every key
+    /// of an object or item of an array.
+    JsonRemove { paths: Vec<String> },
+    /// Removes the elements matching a CSS selector from HTML documents.
+    HtmlRemove { selector: String },
+}
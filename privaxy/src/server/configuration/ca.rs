@@ -1,12 +1,33 @@
+use crate::ca::{make_ca_certificate, CaOptions, NameConstraints, MAX_CA_VALIDITY_DAYS};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use openssl::asn1::{Asn1Time, Asn1TimeRef};
 use openssl::pkey::PKey;
 use openssl::pkey::Private;
+use openssl::symm::Cipher;
 use openssl::x509::X509;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::env;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 use tokio::fs;
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+/// Environment variable holding the passphrase protecting the CA private key.
+const CA_KEY_PASSPHRASE_ENV: &str = "PRIVAXY_CA_KEY_PASSPHRASE";
+
+/// Environment variable pointing to a file holding the passphrase protecting the CA private key.
+const CA_KEY_PASSPHRASE_FILE_ENV: &str = "PRIVAXY_CA_KEY_PASSPHRASE_FILE";
+
+/// Name of the systemd credential (see `LoadCredential=`) holding the passphrase
+/// protecting the CA private key.
+const CA_KEY_PASSPHRASE_CREDENTIAL: &str = "privaxy-ca-key-passphrase";
+
+/// From how many days before its expiry we start warning about the CA expiring.
+pub(crate) const CA_EXPIRY_WARNING_DAYS: i64 = 30;
+
+/// Longest overlap window we accept when rotating the CA, in days.
+const MAX_ROTATION_OVERLAP_DAYS: u32 = 365;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Ca {
     #[serde(default)]
     pub(super) ca_certificate: Option<String>,
@@ -16,6 +37,32 @@ pub struct Ca {
     pub(super) ca_certificate_path: Option<String>,
     #[serde(default)]
     pub(super) ca_private_key_path: Option<String>,
+    /// Restricts the domains generated CAs may sign certificates for. Hosts
+    /// outside of them are tunneled instead of being intercepted.
+    #[serde(default, skip_serializing_if = "NameConstraints::is_empty")]
+    pub(super) name_constraints: NameConstraints,
+    /// CA that was in use before the last rotation. Leaf certificates keep being
+    /// signed with it until `previous_ca_trusted_until`, giving clients time to
+    /// install the new CA.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) previous_ca_certificate: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) previous_ca_private_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) previous_ca_trusted_until: Option<DateTime<Utc>>,
+}
+
+/// Validity information about the configured CA, as exposed by the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaStatus {
+    pub not_before: DateTime<Utc>,
+    pub not_after: DateTime<Utc>,
+    pub days_remaining: i64,
+    pub expires_soon: bool,
+    pub expired: bool,
+    /// Set while leaf certificates are still being signed by the CA that was rotated out.
+    pub previous_ca_trusted_until: Option<DateTime<Utc>>,
+    pub name_constraints: NameConstraints,
 }
 
 #[derive(Error, Debug)]
@@ -28,10 +75,125 @@ pub enum CaError {
     CaPrivateKeyError(String),
     #[error("private key does not match the certificate")]
     PrivateKeyMismatch,
+    #[error("invalid CA options: {0}")]
+    InvalidOptions(String),
+    #[error("the CA private key is encrypted but no passphrase was provided, set PRIVAXY_CA_KEY_PASSPHRASE, PRIVAXY_CA_KEY_PASSPHRASE_FILE or the privaxy-ca-key-passphrase systemd credential")]
+    MissingPassphrase,
+    #[error("unable to decrypt the CA private key, is the passphrase correct?")]
+    InvalidPassphrase,
+    #[error("the CA private key in {0} is readable by other users, restrict its permissions with `chmod 600 {0}`")]
+    InsecureKeyPermissions(String),
+}
+
+pub(crate) fn asn1_time_to_datetime(
+    time: &Asn1TimeRef,
+) -> super::ConfigurationResult<DateTime<Utc>> {
+    let epoch = Asn1Time::from_unix(0)?;
+    let since_epoch = epoch.diff(time)?;
+
+    Ok(Utc
+        .timestamp_opt(
+            i64::from(since_epoch.days) * 86_400 + i64::from(since_epoch.secs),
+            0,
+        )
+        .unwrap())
+}
+
+fn to_pem_string(pem: Vec<u8>) -> String {
+    String::from_utf8(pem).unwrap()
+}
+
+async fn read_passphrase_file(path: &Path) -> super::ConfigurationResult<Vec<u8>> {
+    let mut passphrase = fs::read(path).await?;
+
+    while matches!(passphrase.last(), Some(b'\n' | b'\r')) {
+        passphrase.pop();
+    }
+
+    Ok(passphrase)
+}
+
+/// Passphrase protecting the CA private key, looked up in the environment, then in
+/// the file the environment points to and lastly in the systemd credentials.
+async fn get_ca_key_passphrase() -> super::ConfigurationResult<Option<Vec<u8>>> {
+    if let Ok(passphrase) = env::var(CA_KEY_PASSPHRASE_ENV) {
+        return Ok(Some(passphrase.into_bytes()));
+    }
+
+    if let Ok(passphrase_path) = env::var(CA_KEY_PASSPHRASE_FILE_ENV) {
+        return read_passphrase_file(Path::new(&passphrase_path))
+            .await
+            .map(Some);
+    }
+
+    if let Ok(credentials_directory) = env::var("CREDENTIALS_DIRECTORY") {
+        let credential_path = Path::new(&credentials_directory).join(CA_KEY_PASSPHRASE_CREDENTIAL);
+
+        if fs::try_exists(&credential_path).await? {
+            return read_passphrase_file(&credential_path).await.map(Some);
+        }
+    }
+
+    Ok(None)
+}
+
+fn is_encrypted_private_key(pem: &[u8]) -> bool {
+    // Matches both PKCS#8 (`ENCRYPTED PRIVATE KEY`) and legacy (`Proc-Type: 4,ENCRYPTED`) keys.
+    pem.windows(b"ENCRYPTED".len())
+        .any(|window| window == b"ENCRYPTED")
+}
+
+async fn decode_private_key(pem: &[u8]) -> super::ConfigurationResult<PKey<Private>> {
+    if !is_encrypted_private_key(pem) {
+        return PKey::private_key_from_pem(pem)
+            .map_err(|_| super::ConfigurationError::DirectoryNotFound);
+    }
+
+    match get_ca_key_passphrase().await? {
+        Some(passphrase) => PKey::private_key_from_pem_passphrase(pem, &passphrase)
+            .map_err(|_| CaError::InvalidPassphrase.into()),
+        None => Err(CaError::MissingPassphrase.into()),
+    }
+}
+
+/// Encodes a private key to PEM, encrypting it when a passphrase is configured.
+pub(super) async fn encode_private_key(
+    private_key: &PKey<Private>,
+) -> super::ConfigurationResult<String> {
+    let pem = match get_ca_key_passphrase().await? {
+        Some(passphrase) => {
+            private_key.private_key_to_pem_pkcs8_passphrase(Cipher::aes_256_cbc(), &passphrase)?
+        }
+        None => private_key.private_key_to_pem_pkcs8()?,
+    };
+
+    Ok(to_pem_string(pem))
+}
+
+#[cfg(unix)]
+async fn ensure_not_world_readable(path: &Path) -> super::ConfigurationResult<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path).await?.permissions().mode();
+
+    if mode & 0o004 != 0 {
+        return Err(CaError::InsecureKeyPermissions(path.display().to_string()).into());
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn ensure_not_world_readable(_path: &Path) -> super::ConfigurationResult<()> {
+    Ok(())
 }
 
 impl Ca {
     pub(crate) async fn validate(&self) -> Result<(), super::ConfigurationError> {
+        self.name_constraints
+            .validate()
+            .map_err(CaError::InvalidOptions)?;
+
         let ca_cert = match self.get_ca_certificate().await {
             Ok(cert) => cert,
             Err(err) => {
@@ -106,17 +268,11 @@ impl Ca {
         if let Some(ref ca_private_key_path) = self.ca_private_key_path {
             let ca_path = PathBuf::from(ca_private_key_path);
             match fs::read(&ca_path).await {
-                Ok(ca_key) => {
-                    let pkey = PKey::private_key_from_pem(&ca_key)
-                        .map_err(|_| super::ConfigurationError::DirectoryNotFound)?;
-                    Ok(pkey)
-                }
+                Ok(ca_key) => decode_private_key(&ca_key).await,
                 Err(err) => Err(super::ConfigurationError::FileSystemError(err)),
             }
         } else if let Some(ref ca_private_key) = self.ca_private_key {
-            let pkey = PKey::private_key_from_pem(ca_private_key.as_bytes())
-                .map_err(|_| super::ConfigurationError::DirectoryNotFound)?;
-            Ok(pkey)
+            decode_private_key(ca_private_key.as_bytes()).await
         } else {
             Err(super::ConfigurationError::DirectoryNotFound)
         }
@@ -128,7 +284,7 @@ impl Ca {
     ) -> super::ConfigurationResult<()> {
         if let Some(ref ca_private_key_path) = &self.ca_private_key_path {
             let ca_path = PathBuf::from(ca_private_key_path);
-            match fs::write(&ca_path, ca_private_key.as_bytes()).await {
+            match super::write_private_file(&ca_path, ca_private_key.as_bytes()).await {
                 Ok(()) => Ok(()),
                 Err(err) => Err(super::ConfigurationError::FileSystemError(err)),
             }
@@ -137,4 +293,174 @@ impl Ca {
             Ok(())
         }
     }
+
+    /// Sets the constraints CAs are generated with. The current CA is left untouched.
+    pub(crate) fn set_name_constraints(
+        &mut self,
+        name_constraints: NameConstraints,
+    ) -> super::ConfigurationResult<()> {
+        name_constraints
+            .validate()
+            .map_err(CaError::InvalidOptions)?;
+
+        self.name_constraints = name_constraints;
+
+        Ok(())
+    }
+
+    /// Replaces the CA with a freshly generated one.
+    pub(crate) async fn generate(&mut self, options: &CaOptions) -> super::ConfigurationResult<()> {
+        if options.validity_days == 0 || options.validity_days > MAX_CA_VALIDITY_DAYS {
+            return Err(CaError::InvalidOptions(format!(
+                "validity must be between 1 and {MAX_CA_VALIDITY_DAYS} days"
+            ))
+            .into());
+        }
+
+        let options = options.clone();
+        let name_constraints = self.name_constraints.clone();
+        // Generating large RSA keys can take a few seconds.
+        let (ca_certificate, ca_private_key) =
+            tokio::task::spawn_blocking(move || make_ca_certificate(&options, &name_constraints))
+                .await
+                .unwrap();
+
+        self.set_ca_certificate(&to_pem_string(ca_certificate.to_pem()?))
+            .await?;
+        self.set_ca_private_key(&encode_private_key(&ca_private_key).await?)
+            .await?;
+
+        self.previous_ca_certificate = None;
+        self.previous_ca_private_key = None;
+        self.previous_ca_trusted_until = None;
+
+        Ok(())
+    }
+
+    /// Generates a new CA while keeping the current one in use for `overlap_days`.
+    pub(crate) async fn rotate(
+        &mut self,
+        options: &CaOptions,
+        overlap_days: u32,
+    ) -> super::ConfigurationResult<()> {
+        if overlap_days > MAX_ROTATION_OVERLAP_DAYS {
+            return Err(CaError::InvalidOptions(format!(
+                "overlap must be at most {MAX_ROTATION_OVERLAP_DAYS} days"
+            ))
+            .into());
+        }
+
+        let previous_ca_certificate = self.get_ca_certificate().await?;
+        let previous_ca_private_key = self.get_ca_private_key().await?;
+
+        self.generate(options).await?;
+
+        if overlap_days > 0 {
+            self.previous_ca_certificate = Some(to_pem_string(previous_ca_certificate.to_pem()?));
+            self.previous_ca_private_key =
+                Some(encode_private_key(&previous_ca_private_key).await?);
+            self.previous_ca_trusted_until =
+                Some(Utc::now() + Duration::days(i64::from(overlap_days)));
+        }
+
+        Ok(())
+    }
+
+    fn is_previous_ca_trusted(&self) -> bool {
+        matches!(self.previous_ca_trusted_until, Some(until) if until > Utc::now())
+    }
+
+    /// Returns the CA leaf certificates should currently be signed with.
+    pub(crate) async fn get_signing_ca(&self) -> super::ConfigurationResult<(X509, PKey<Private>)> {
+        if self.is_previous_ca_trusted() {
+            if let (Some(certificate), Some(private_key)) =
+                (&self.previous_ca_certificate, &self.previous_ca_private_key)
+            {
+                return Ok((
+                    X509::from_pem(certificate.as_bytes())?,
+                    decode_private_key(private_key.as_bytes()).await?,
+                ));
+            }
+        }
+
+        Ok((
+            self.get_ca_certificate().await?,
+            self.get_ca_private_key().await?,
+        ))
+    }
+
+    /// Makes sure private keys are not readable by other users, be it in their own file
+    /// or inside of the configuration file.
+    pub(crate) async fn check_private_key_permissions(&self) -> super::ConfigurationResult<()> {
+        if let Some(ca_private_key_path) = &self.ca_private_key_path {
+            ensure_not_world_readable(Path::new(ca_private_key_path)).await?;
+        }
+
+        let configuration_file_path = super::get_config_file();
+
+        if (self.ca_private_key.is_some() || self.previous_ca_private_key.is_some())
+            && fs::try_exists(&configuration_file_path).await?
+        {
+            ensure_not_world_readable(&configuration_file_path).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Certificates clients should trust: the current CA, along with the previous one while
+    /// it still signs certificates.
+    pub(crate) async fn get_trusted_ca_certificates(
+        &self,
+    ) -> super::ConfigurationResult<Vec<X509>> {
+        let mut certificates = vec![self.get_ca_certificate().await?];
+
+        if self.is_previous_ca_trusted() {
+            if let Some(previous_ca_certificate) = &self.previous_ca_certificate {
+                certificates.push(X509::from_pem(previous_ca_certificate.as_bytes())?);
+            }
+        }
+
+        Ok(certificates)
+    }
+
+    pub(crate) async fn status(&self) -> super::ConfigurationResult<CaStatus> {
+        let ca_certificate = self.get_ca_certificate().await?;
+
+        let not_before = asn1_time_to_datetime(ca_certificate.not_before())?;
+        let not_after = asn1_time_to_datetime(ca_certificate.not_after())?;
+        let now = Utc::now();
+
+        Ok(CaStatus {
+            not_before,
+            not_after,
+            days_remaining: (not_after - now).num_days(),
+            expires_soon: not_after - Duration::days(CA_EXPIRY_WARNING_DAYS) <= now,
+            expired: not_after <= now,
+            previous_ca_trusted_until: self
+                .previous_ca_trusted_until
+                .filter(|_| self.is_previous_ca_trusted()),
+            name_constraints: self.name_constraints.clone(),
+        })
+    }
+
+    /// Logs a warning when the CA is about to expire.
+    pub(crate) async fn warn_on_expiry(&self) {
+        match self.status().await {
+            Ok(status) if status.expired => {
+                log::error!(
+                    "The CA certificate expired on {}, generate a new one from the settings.",
+                    status.not_after
+                );
+            }
+            Ok(status) if status.expires_soon => {
+                log::warn!(
+                    "The CA certificate expires in {} days ({}), consider rotating it.",
+                    status.days_remaining,
+                    status.not_after
+                );
+            }
+            Ok(_) => {}
+            Err(err) => log::error!("Unable to check CA certificate expiry: {err}"),
+        }
+    }
 }
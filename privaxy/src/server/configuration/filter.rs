@@ -8,6 +8,9 @@ use url::Url;
 use serde_with::{serde_as, DisplayFromStr};
 pub(crate) const FILTERS_DIRECTORY_NAME: &str = "filters";
 
+/// Name blocking decisions made by custom filters are attributed to.
+pub(crate) const CUSTOM_FILTERS_LIST_NAME: &str = "custom filters";
+
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
 pub enum FilterGroup {
     Default,
@@ -59,6 +62,13 @@ pub struct Filter {
     pub url: Url,
 }
 
+/// Contents of a filter list, along with the name blocking decisions are attributed to.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FilterListContent {
+    pub name: String,
+    pub content: String,
+}
+
 #[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct DefaultFilters(Vec<DefaultFilter>);
 
@@ -280,6 +290,15 @@ impl Filter {
             Ok(filter) => Ok(std::str::from_utf8(&filter)?.to_string()),
         }
     }
+
+    /// Name blocking decisions made by this filter are attributed to.
+    pub fn list_name(&self) -> &str {
+        if self.title.is_empty() {
+            &self.file_name
+        } else {
+            &self.title
+        }
+    }
 }
 
 impl From<DefaultFilter> for Filter {
@@ -331,12 +350,18 @@ fn get_filter_directory() -> PathBuf {
 pub(crate) async fn get_filters_content(
     configuration: &mut super::Configuration,
     http_client: &reqwest::Client,
-) -> Vec<String> {
+) -> Vec<FilterListContent> {
     let mut filters = Vec::new();
     let mut futures = vec![];
 
     for filter in configuration.get_enabled_filters() {
-        let future = filter.get_contents(http_client);
+        let name = filter.list_name().to_string();
+        let future = async move {
+            filter
+                .get_contents(http_client)
+                .await
+                .map(|content| FilterListContent { name, content })
+        };
         futures.push(future);
     }
 
@@ -350,9 +375,15 @@ pub(crate) async fn get_filters_content(
         }
     }
 
-    filters.append(&mut configuration.custom_filters);
+    if !configuration.custom_filters.is_empty() {
+        filters.push(FilterListContent {
+            name: CUSTOM_FILTERS_LIST_NAME.to_string(),
+            content: configuration.custom_filters.join("\n"),
+        });
+    }
+
+    // Sorted so that the same lists always give the same engine, whatever order they were
+    // retrieved in.
     filters.sort_unstable();
-    // Filter out duplicate lines, if present
-    filters.dedup();
     filters
 }
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/// Changes the headers of requests made to the hosts it matches, and of their responses.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct HeaderPolicy {
+    pub name: String,
+    /// Host patterns, `*` matching any sequence of characters. Every host matches when empty.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(flatten)]
+    pub action: HeaderPolicyAction,
+    #[serde(default = "super::default_enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum HeaderPolicyAction {
+    /// Trims the `Referer` of cross-site requests down to its origin. Sites are registrable
+    /// domains, requests from `www.example.com` to `cdn.example.com` keep their `Referer`.
+    TrimCrossSiteReferer,
+    /// Replaces the `User-Agent`, or removes it when no replacement is set.
+    UserAgent {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        replacement: Option<String>,
+    },
+    /// Removes `Sec-CH-*` client hints from requests, along with the `Accept-CH` and
+    /// `Critical-CH` response headers asking for them.
+    StripClientHints,
+    /// Sends `Sec-GPC: 1`, opting out of the sale or sharing of personal data.
+    GlobalPrivacyControl,
+    /// Sends `DNT: 1`.
+    DoNotTrack,
+    /// Removes `ETag` response headers and `If-None-Match` request headers, which can carry
+    /// identifiers across visits.
+    StripEtags,
+    /// Removes `Alt-Svc` response headers, which let browsers switch to HTTP/3 over QUIC,
+    /// going around the proxy.
+    StripAltSvc,
+}
+
+pub(super) fn default_header_policies() -> Vec<HeaderPolicy> {
+    vec![HeaderPolicy {
+        name: String::from("Keep browsers on the proxy"),
+        hosts: Vec::new(),
+        action: HeaderPolicyAction::StripAltSvc,
+        enabled: true,
+    }]
+}
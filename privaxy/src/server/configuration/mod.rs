@@ -2,26 +2,52 @@ use serde::{Deserialize, Serialize};
 use std::{collections::BTreeSet, time::Duration};
 use thiserror::Error;
 use tokio::fs;
+use tokio::io::AsyncWriteExt;
+mod blocker;
+mod body_rewrite;
 mod ca;
 mod filter;
+mod header_policy;
 mod network;
+mod resources;
+mod rewriter;
 mod updater;
+mod url_cleaning;
+mod user_content;
+pub use blocker::*;
+pub use body_rewrite::*;
 pub use ca::*;
 pub use filter::*;
 use futures::future::try_join_all;
+use header_policy::default_header_policies;
+pub use header_policy::{HeaderPolicy, HeaderPolicyAction};
 pub use network::*;
+pub(crate) use resources::get_resources;
+pub use rewriter::*;
 use std::env;
 use std::path::{Path, PathBuf};
 pub use updater::*;
+pub(crate) use url_cleaning::get_url_cleaning_rules;
+pub use url_cleaning::UrlCleaningConfig;
+pub use user_content::*;
 pub(crate) type ConfigurationResult<T> = Result<T, ConfigurationError>;
 pub(crate) const FILTERS_UPDATE_AFTER: Duration = Duration::from_secs(60 * 10);
 
 /// Filename of the configuration file.
 pub(crate) const CONFIGURATION_FILE_NAME: &str = "config";
 
+/// Filename of the serialized blocking engine cache.
+const ENGINE_CACHE_FILE_NAME: &str = "engine.dat";
+
+const PAUSES_FILE_NAME: &str = "pauses.json";
+
 /// Default configuration directory name.
 const CONFIGURATION_DIRECTORY_NAME: &str = "/etc/privaxy";
 
+fn default_enabled() -> bool {
+    true
+}
+
 #[derive(Error, Debug)]
 pub enum ConfigurationError {
     #[error("NetworkConfigError error: {0}")]
@@ -42,15 +68,39 @@ pub enum ConfigurationError {
     UnableToDecodePem(#[from] openssl::error::ErrorStack),
     #[error("filter error: {0}")]
     FilterError(String),
+    #[error("resources error: {0}")]
+    ResourcesError(#[from] crate::blocker_utils::ResourcesError),
+    #[error("body rewrite rule error: {0}")]
+    BodyRewriteRuleError(#[from] crate::proxy::body_rewrite::BodyRewriteRuleError),
+    #[error("URL cleaning rules error: {0}")]
+    UrlCleaningRulesError(#[from] crate::proxy::url_cleaning::UrlCleaningRulesError),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct Configuration {
     pub exclusions: BTreeSet<String>,
+    /// Sites blocking is disabled for, while still going through the MITM pipeline.
+    #[serde(default)]
+    pub allowlist: BTreeSet<String>,
     pub custom_filters: Vec<String>,
     pub ca: Ca,
     pub network: NetworkConfig,
     pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub blocker: BlockerConfig,
+    #[serde(default)]
+    pub rewriter: RewriterConfig,
+    /// User scripts and user styles injected into the documents they match.
+    #[serde(default)]
+    pub user_content: Vec<UserContent>,
+    /// Rules transforming the bodies of the responses they match.
+    #[serde(default)]
+    pub body_rewrite_rules: Vec<BodyRewriteRule>,
+    #[serde(default)]
+    pub url_cleaning: UrlCleaningConfig,
+    /// Policies changing the headers of requests and responses.
+    #[serde(default = "default_header_policies")]
+    pub header_policies: Vec<HeaderPolicy>,
 }
 
 #[derive(Error, Debug)]
@@ -101,7 +151,8 @@ impl Configuration {
 
         let configuration_serialized = toml::to_string_pretty(&self).unwrap();
 
-        fs::write(configuration_file_path, configuration_serialized).await?;
+        // The configuration may embed the CA private key.
+        write_private_file(configuration_file_path, configuration_serialized).await?;
 
         Ok(())
     }
@@ -148,6 +199,65 @@ impl Configuration {
         Ok(())
     }
 
+    pub async fn set_allowlist(
+        &mut self,
+        allowlist: &str,
+        allowlist_store: crate::proxy::allowlist::AllowlistStore,
+    ) -> ConfigurationResult<()> {
+        self.allowlist = Self::deserialize_lines(allowlist);
+
+        self.save().await?;
+
+        allowlist_store.replace_allowlist(Vec::from_iter(self.allowlist.clone().into_iter()));
+
+        Ok(())
+    }
+
+    pub async fn set_user_content(
+        &mut self,
+        user_content: Vec<UserContent>,
+        user_content_store: crate::proxy::user_content::UserContentStore,
+    ) -> ConfigurationResult<()> {
+        self.user_content = user_content;
+
+        self.save().await?;
+
+        user_content_store.replace_user_content(self.user_content.clone());
+
+        Ok(())
+    }
+
+    /// Replaces body rewrite rules, which are all checked before anything is saved.
+    pub async fn set_body_rewrite_rules(
+        &mut self,
+        body_rewrite_rules: Vec<BodyRewriteRule>,
+        body_rewrite_store: crate::proxy::body_rewrite::BodyRewriteStore,
+    ) -> ConfigurationResult<()> {
+        crate::proxy::body_rewrite::check_rules(&body_rewrite_rules)?;
+
+        self.body_rewrite_rules = body_rewrite_rules;
+
+        self.save().await?;
+
+        body_rewrite_store.replace_rules(self.body_rewrite_rules.clone());
+
+        Ok(())
+    }
+
+    pub async fn set_header_policies(
+        &mut self,
+        header_policies: Vec<HeaderPolicy>,
+        header_policy_store: crate::proxy::header_policy::HeaderPolicyStore,
+    ) -> ConfigurationResult<()> {
+        self.header_policies = header_policies;
+
+        self.save().await?;
+
+        header_policy_store.replace_header_policies(self.header_policies.clone());
+
+        Ok(())
+    }
+
     pub async fn set_filter_enabled_status(
         &mut self,
         filter_file_name: &str,
@@ -221,25 +331,41 @@ impl Configuration {
         Ok(())
     }
 
-    pub async fn set_ca_settings(&mut self, ca_config: &Ca) -> ConfigurationResult<()> {
+    /// Name constraints are left as they are when `name_constraints` is `None`, and cleared
+    /// when it holds empty ones.
+    pub async fn set_ca_settings(
+        &mut self,
+        ca_config: &Ca,
+        name_constraints: Option<crate::ca::NameConstraints>,
+    ) -> ConfigurationResult<()> {
         if let Err(err) = ca_config.validate().await {
             log::error!("Failed to validate ca settings: {err}");
             return Err(err);
         };
-        self.ca = ca_config.clone();
+        if let Some(name_constraints) = &name_constraints {
+            name_constraints
+                .validate()
+                .map_err(CaError::InvalidOptions)?;
+        }
+        let previous_ca = std::mem::replace(&mut self.ca, ca_config.clone());
+        self.ca.name_constraints = name_constraints.unwrap_or(previous_ca.name_constraints);
+        // The CA that was rotated out is not part of the settings the web gui sends over,
+        // it keeps signing until its overlap ends.
+        self.ca.previous_ca_certificate = previous_ca.previous_ca_certificate;
+        self.ca.previous_ca_private_key = previous_ca.previous_ca_private_key;
+        self.ca.previous_ca_trusted_until = previous_ca.previous_ca_trusted_until;
         Ok(())
     }
 
     async fn new_default() -> ConfigurationResult<Self> {
-        let (x509, private_key) = crate::ca::make_ca_certificate();
+        let (x509, private_key) =
+            crate::ca::make_ca_certificate(&Default::default(), &Default::default());
 
         let x509_pem = std::str::from_utf8(&x509.to_pem().unwrap())
             .unwrap()
             .to_string();
 
-        let private_key_pem = std::str::from_utf8(&private_key.private_key_to_pem_pkcs8().unwrap())
-            .unwrap()
-            .to_string();
+        let private_key_pem = ca::encode_private_key(&private_key).await?;
 
         let default_filters = DefaultFilters::new();
         Ok(Configuration {
@@ -253,6 +379,7 @@ impl Configuration {
                 ca_certificate_path: None,
                 ca_private_key: Some(private_key_pem),
                 ca_private_key_path: None,
+                ..Ca::default()
             },
             network: NetworkConfig {
                 bind_addr: "127.0.0.1".to_string(),
@@ -264,15 +391,54 @@ impl Configuration {
                 listen_url: None,
             },
             exclusions: BTreeSet::new(),
+            allowlist: BTreeSet::new(),
             custom_filters: Vec::new(),
+            blocker: BlockerConfig::default(),
+            rewriter: RewriterConfig::default(),
+            user_content: Vec::new(),
+            body_rewrite_rules: Vec::new(),
+            url_cleaning: UrlCleaningConfig::default(),
+            header_policies: default_header_policies(),
         })
     }
 }
 
+/// Writes a file only its owner is able to read, for files holding private keys.
+pub(crate) async fn write_private_file(
+    path: impl AsRef<Path>,
+    contents: impl AsRef<[u8]>,
+) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    let mut file = options.open(path).await?;
+
+    // The mode is only applied to newly created files.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))
+            .await?;
+    }
+
+    file.write_all(contents.as_ref()).await?;
+    file.flush().await
+}
+
 pub(crate) fn get_config_file() -> PathBuf {
     get_base_directory().unwrap().join(CONFIGURATION_FILE_NAME)
 }
 
+pub(crate) fn get_engine_cache_file() -> PathBuf {
+    get_base_directory().unwrap().join(ENGINE_CACHE_FILE_NAME)
+}
+
+pub(crate) fn get_pauses_file() -> PathBuf {
+    get_base_directory().unwrap().join(PAUSES_FILE_NAME)
+}
+
 fn get_base_directory() -> ConfigurationResult<PathBuf> {
     let base_directory: PathBuf = match env::var("PRIVAXY_BASE_PATH") {
         Ok(val) => PathBuf::from(&val),
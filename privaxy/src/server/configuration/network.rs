@@ -177,7 +177,7 @@ impl NetworkConfig {
     }
     pub(crate) async fn write_tls_key(&self, key: PKey<Private>) -> ConfigurationResult<()> {
         if let Some(key_path) = &self.tls_key_path {
-            fs::write(key_path, key.private_key_to_pem_pkcs8().unwrap()).await?;
+            super::write_private_file(key_path, key.private_key_to_pem_pkcs8().unwrap()).await?;
             Ok(())
         } else {
             Err(NetworkConfigError::TlsKeyError("No TLS key in path".to_string()).into())
@@ -0,0 +1,114 @@
+use super::{BlockerConfig, ConfigurationError, ConfigurationResult};
+use crate::blocker::embedded_resources;
+use crate::blocker_utils::{assemble_resources, read_redirectable_resource_mapping};
+use adblock::resources::Resource;
+use futures::future::try_join_all;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::fs;
+
+const SCRIPTLETS_FILE_NAME: &str = "scriptlets.js";
+const REDIRECT_RESOURCES_FILE_NAME: &str = "redirect-resources.js";
+const WEB_ACCESSIBLE_RESOURCES_DIRECTORY_NAME: &str = "web_accessible_resources";
+
+/// Where uBlock Origin resources are loaded from. Both are laid out like the vendored copy:
+/// `scriptlets.js` and `redirect-resources.js` next to a `web_accessible_resources` directory.
+enum ResourcesSource {
+    Directory(PathBuf),
+    Url(String),
+}
+
+impl ResourcesSource {
+    fn new(source: &str) -> Self {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            Self::Url(source.trim_end_matches('/').to_string())
+        } else {
+            Self::Directory(PathBuf::from(source))
+        }
+    }
+
+    async fn read(
+        &self,
+        path: &str,
+        http_client: &reqwest::Client,
+    ) -> ConfigurationResult<Vec<u8>> {
+        match self {
+            Self::Directory(directory) => Ok(fs::read(directory.join(path)).await?),
+            Self::Url(url) => {
+                let response = http_client
+                    .get(format!("{url}/{path}"))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+
+                Ok(response.bytes().await?.to_vec())
+            }
+        }
+    }
+
+    async fn read_to_string(
+        &self,
+        path: &str,
+        http_client: &reqwest::Client,
+    ) -> ConfigurationResult<String> {
+        Ok(String::from_utf8(self.read(path, http_client).await?)
+            .map_err(|err| err.utf8_error())?)
+    }
+
+    async fn load(&self, http_client: &reqwest::Client) -> ConfigurationResult<Vec<Resource>> {
+        let scriptlets = self
+            .read_to_string(SCRIPTLETS_FILE_NAME, http_client)
+            .await?;
+        let redirect_resources = self
+            .read_to_string(REDIRECT_RESOURCES_FILE_NAME, http_client)
+            .await?;
+
+        // Unlike the vendored copy, every resource of the mapping is expected to be there,
+        // a partial copy would quietly break the redirect rules using the missing ones.
+        let web_accessible_resources = try_join_all(
+            read_redirectable_resource_mapping(&redirect_resources)?
+                .into_iter()
+                .map(|resource_info| async move {
+                    let path = format!(
+                        "{WEB_ACCESSIBLE_RESOURCES_DIRECTORY_NAME}/{}",
+                        resource_info.name
+                    );
+                    let contents = self.read(&path, http_client).await?;
+
+                    Ok::<_, ConfigurationError>((resource_info.name, contents))
+                }),
+        )
+        .await?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+        Ok(assemble_resources(
+            &scriptlets,
+            &redirect_resources,
+            |name| web_accessible_resources.get(name).map(Vec::as_slice),
+        )?)
+    }
+}
+
+/// Returns the resources the blocking engine uses for redirect and scriptlet rules,
+/// falling back to the embedded ones when `blocker.resources` is unset or can't be loaded.
+pub(crate) async fn get_resources(
+    blocker_config: &BlockerConfig,
+    http_client: &reqwest::Client,
+) -> Vec<Resource> {
+    let source = match &blocker_config.resources {
+        Some(source) => source,
+        None => return embedded_resources(),
+    };
+
+    match ResourcesSource::new(source).load(http_client).await {
+        Ok(resources) => {
+            log::debug!("Loaded {} resources from {source}", resources.len());
+            resources
+        }
+        Err(err) => {
+            log::error!("Unable to load resources from {source}, using embedded ones: {err}");
+            embedded_resources()
+        }
+    }
+}
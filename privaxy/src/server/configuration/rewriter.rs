@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+
+/// Size above which documents are not rewritten, 10 MiB.
+const DEFAULT_MAX_DOCUMENT_SIZE: u64 = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// HTML rewriter configuration for Privaxy
+pub struct RewriterConfig {
+    /// Size in bytes above which HTML documents are passed through untouched, without
+    /// cosmetic filtering. Defaults to 10 MiB. Applied when the proxy starts.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_document_size: Option<u64>,
+}
+
+impl RewriterConfig {
+    pub fn max_document_size(&self) -> u64 {
+        self.max_document_size.unwrap_or(DEFAULT_MAX_DOCUMENT_SIZE)
+    }
+}
@@ -1,4 +1,5 @@
-use crate::blocker::AdblockRequester;
+use crate::blocker::{AdblockRequester, EngineSources};
+use crate::proxy::url_cleaning::UrlCleaner;
 use futures::future::{AbortHandle, Abortable};
 
 use tokio::sync::mpsc::Receiver;
@@ -10,6 +11,7 @@ pub struct ConfigurationUpdater {
     pub tx: Sender<super::Configuration>,
     http_client: reqwest::Client,
     adblock_requester: AdblockRequester,
+    url_cleaner: UrlCleaner,
 }
 
 impl ConfigurationUpdater {
@@ -17,6 +19,7 @@ impl ConfigurationUpdater {
         configuration: super::Configuration,
         http_client: reqwest::Client,
         adblock_requester: AdblockRequester,
+        url_cleaner: UrlCleaner,
         tx_rx: Option<(
             sync::mpsc::Sender<super::Configuration>,
             sync::mpsc::Receiver<super::Configuration>,
@@ -31,12 +34,14 @@ impl ConfigurationUpdater {
 
         let http_client_clone = http_client.clone();
         let adblock_requester_clone = adblock_requester.clone();
+        let url_cleaner_clone = url_cleaner.clone();
 
         let filters_updater = Abortable::new(
             async move {
                 Self::filters_updater(
                     configuration,
                     adblock_requester_clone,
+                    url_cleaner_clone,
                     http_client_clone.clone(),
                 )
                 .await
@@ -52,6 +57,7 @@ impl ConfigurationUpdater {
             tx,
             http_client,
             adblock_requester,
+            url_cleaner,
         }
     }
 
@@ -63,15 +69,27 @@ impl ConfigurationUpdater {
 
                 let filters =
                     super::filter::get_filters_content(&mut configuration, &self.http_client).await;
-                self.adblock_requester.replace_engine(filters).await;
+                let resources =
+                    super::get_resources(&configuration.blocker, &self.http_client).await;
+                self.adblock_requester
+                    .replace_engine(EngineSources { filters, resources })
+                    .await;
+
+                // Rules may be downloaded, the engine doesn't wait for them.
+                self.url_cleaner.replace_rules(
+                    super::get_url_cleaning_rules(&configuration.url_cleaning, &self.http_client)
+                        .await,
+                );
 
                 let adblock_requester_clone = self.adblock_requester.clone();
+                let url_cleaner_clone = self.url_cleaner.clone();
                 let http_client_clone = self.http_client.clone();
 
                 tokio::spawn(async move {
                     Self::filters_updater(
                         configuration,
                         adblock_requester_clone,
+                        url_cleaner_clone,
                         http_client_clone,
                     )
                     .await;
@@ -85,6 +103,7 @@ impl ConfigurationUpdater {
     async fn filters_updater(
         mut configuration: super::Configuration,
         adblock_requester: AdblockRequester,
+        url_cleaner: UrlCleaner,
         http_client: reqwest::Client,
     ) {
         loop {
@@ -94,13 +113,20 @@ impl ConfigurationUpdater {
                 log::error!("An error occured while trying to update filters: {:?}", err);
             }
 
-            // We don't bother diffing the filters as replacing the engine is very cheap and
-            // filters are not updated often enough that the cost would matter.
+            // Engines are rebuilt in the background, and not at all when the fingerprint of
+            // their filters and resources matches the one they were built from.
             let filters =
                 super::filter::get_filters_content(&mut configuration, &http_client).await;
-            adblock_requester.replace_engine(filters).await;
+            let resources = super::get_resources(&configuration.blocker, &http_client).await;
+            adblock_requester
+                .replace_engine(EngineSources { filters, resources })
+                .await;
+
+            url_cleaner.replace_rules(
+                super::get_url_cleaning_rules(&configuration.url_cleaning, &http_client).await,
+            );
 
-            log::info!("Updated filters");
+            log::info!("Updated filters, resources and URL cleaning rules");
         }
     }
 }
@@ -0,0 +1,75 @@
+use super::ConfigurationResult;
+use crate::proxy::url_cleaning::UrlCleaningRules;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+/// Tracking parameter stripping and redirect wrapper unwrapping configuration for Privaxy
+pub struct UrlCleaningConfig {
+    #[serde(default = "super::default_enabled")]
+    pub enabled: bool,
+    /// File or URL of a ClearURLs formatted ruleset, refreshed along with filters.
+    /// The embedded ruleset is used when unset or when it can't be loaded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rules: Option<String>,
+}
+
+impl Default for UrlCleaningConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rules: None,
+        }
+    }
+}
+
+async fn read_rules(source: &str, http_client: &reqwest::Client) -> ConfigurationResult<Vec<u8>> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        let response = http_client.get(source).send().await?.error_for_status()?;
+
+        Ok(response.bytes().await?.to_vec())
+    } else {
+        Ok(fs::read(source).await?)
+    }
+}
+
+async fn load_rules(
+    source: &str,
+    http_client: &reqwest::Client,
+) -> ConfigurationResult<UrlCleaningRules> {
+    let rules = read_rules(source, http_client).await?;
+
+    Ok(UrlCleaningRules::parse(&rules)?)
+}
+
+/// Returns the rules applied to request URLs, falling back to the embedded ones when
+/// `url_cleaning.rules` is unset or can't be loaded.
+pub(crate) async fn get_url_cleaning_rules(
+    url_cleaning_config: &UrlCleaningConfig,
+    http_client: &reqwest::Client,
+) -> UrlCleaningRules {
+    if !url_cleaning_config.enabled {
+        return UrlCleaningRules::default();
+    }
+
+    let source = match &url_cleaning_config.rules {
+        Some(source) => source,
+        None => return UrlCleaningRules::embedded(),
+    };
+
+    match load_rules(source, http_client).await {
+        Ok(rules) => {
+            log::debug!(
+                "Loaded {} URL cleaning providers from {source}",
+                rules.provider_count()
+            );
+            rules
+        }
+        Err(err) => {
+            log::error!(
+                "Unable to load URL cleaning rules from {source}, using embedded ones: {err}"
+            );
+            UrlCleaningRules::embedded()
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+/// Script or stylesheet injected into the HTML documents it matches, like Greasemonkey
+/// user scripts and user styles.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct UserContent {
+    pub name: String,
+    pub kind: UserContentKind,
+    /// URL patterns the content is injected into, `*` matching any sequence of characters.
+    /// Every URL matches when empty.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// URL patterns the content is not injected into, even when included.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub run_at: RunAt,
+    #[serde(default = "super::default_enabled")]
+    pub enabled: bool,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UserContentKind {
+    /// JavaScript, injected in a `<script>` element.
+    Script,
+    /// CSS, injected in a `<style>` element.
+    Style,
+}
+
+/// Where in the document user content is injected, named after Greasemonkey's `@run-at`.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RunAt {
+    /// At the start of `<head>`, before the page's own scripts run.
+    DocumentStart,
+    /// At the end of the document, next to the cosmetic filters.
+    #[default]
+    DocumentEnd,
+}
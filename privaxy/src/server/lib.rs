@@ -3,7 +3,12 @@
 
 use crate::blocker::AdblockRequester;
 use crate::configuration::NetworkConfig;
+use crate::proxy::allowlist::AllowlistStore;
+use crate::proxy::body_rewrite::BodyRewriteStore;
 use crate::proxy::exclusions::LocalExclusionStore;
+use crate::proxy::header_policy::HeaderPolicyStore;
+use crate::proxy::url_cleaning::UrlCleaner;
+use crate::proxy::user_content::UserContentStore;
 use crate::web_gui::events::Event;
 use hyper::server::conn::AddrStream;
 use hyper::service::{make_service_fn, service_fn};
@@ -17,11 +22,11 @@ use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::thread;
 use std::time::Duration;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::broadcast;
 use tokio::sync::Notify;
+mod adguard;
 pub mod blocker;
 mod blocker_utils;
 mod ca;
@@ -33,6 +38,9 @@ mod web_gui;
 
 pub const WEBAPP_FRONTEND_DIR: Dir<'_> = include_dir!("web_frontend/dist");
 
+/// How often the CA is checked for expiry and for the end of rotation overlap windows.
+const CA_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Debug)]
 pub struct PrivaxyServer {
     pub ca_certificate_pem: String,
@@ -107,6 +115,17 @@ pub async fn start_privaxy() -> PrivaxyServer {
         LocalExclusionStore::new(Vec::from_iter(configuration.exclusions.clone().into_iter()));
     let local_exclusion_store_clone = local_exclusion_store.clone();
 
+    let allowlist_store =
+        AllowlistStore::new(Vec::from_iter(configuration.allowlist.clone().into_iter()));
+
+    let user_content_store = UserContentStore::new(configuration.user_content.clone());
+
+    let body_rewrite_store = BodyRewriteStore::new(configuration.body_rewrite_rules.clone());
+
+    let url_cleaner = UrlCleaner::default();
+
+    let header_policy_store = HeaderPolicyStore::new(configuration.header_policies.clone());
+
     let ca_certificate = match configuration.ca.get_ca_certificate().await {
         Ok(ca_certificate) => ca_certificate,
         Err(err) => {
@@ -119,14 +138,31 @@ pub async fn start_privaxy() -> PrivaxyServer {
         .unwrap()
         .to_string();
 
-    let ca_private_key = match configuration.ca.get_ca_private_key().await {
-        Ok(ca_private_key) => ca_private_key,
+    if let Err(err) = configuration.ca.check_private_key_permissions().await {
+        println!("Refusing to start: {err}");
+        std::process::exit(1)
+    }
+
+    if let Err(err) = configuration.ca.get_ca_private_key().await {
+        println!("Unable to decode ca private key: {err}");
+        std::process::exit(1)
+    }
+
+    let cert_cache = match configuration.ca.get_signing_ca().await {
+        Ok((signing_certificate, signing_private_key)) => {
+            cert::CertCache::new(signing_certificate, signing_private_key)
+        }
         Err(err) => {
-            println!("Unable to decode ca private key: {:?}", err);
+            println!("Unable to decode signing ca: {:?}", err);
             std::process::exit(1)
         }
     };
 
+    configuration.ca.warn_on_expiry().await;
+
+    let blocker_workers = configuration.blocker.worker_count();
+    let blocker_fail_mode = configuration.blocker.fail_mode;
+
     let statistics = statistics::Statistics::new();
     let statistics_clone = statistics.clone();
 
@@ -134,18 +170,26 @@ pub async fn start_privaxy() -> PrivaxyServer {
     let broadcast_tx_clone = broadcast_tx.clone();
 
     let blocking_disabled_store =
-        blocker::BlockingDisabledStore(Arc::new(std::sync::RwLock::new(false)));
+        blocker::BlockingDisabledStore::load(configuration::get_pauses_file());
     let blocking_disabled_store_clone = blocking_disabled_store.clone();
 
-    let (crossbeam_sender, crossbeam_receiver) = crossbeam_channel::unbounded();
-    let blocker_sender = crossbeam_sender.clone();
+    let (blocker_sender, crossbeam_receiver) = crossbeam_channel::unbounded();
+
+    let engine_stats = blocker::EngineStatsStore::default();
 
-    let blocker_requester = AdblockRequester::new(blocker_sender);
+    let blocker_requester = AdblockRequester::new(
+        blocker_sender,
+        engine_stats.clone(),
+        blocking_disabled_store.clone(),
+        allowlist_store.clone(),
+        blocker_fail_mode,
+    );
 
     let configuration_updater = configuration::ConfigurationUpdater::new(
         configuration.clone(),
         client.clone(),
         blocker_requester.clone(),
+        url_cleaner.clone(),
         None,
     )
     .await;
@@ -161,11 +205,18 @@ pub async fn start_privaxy() -> PrivaxyServer {
 
     let block_disable_ref = blocking_disabled_store.clone();
     let local_exclusion_store_ref = local_exclusion_store.clone();
+    let allowlist_store_ref = allowlist_store.clone();
+    let user_content_store_ref = user_content_store.clone();
+    let body_rewrite_store_ref = body_rewrite_store.clone();
+    let header_policy_store_ref = header_policy_store.clone();
+    let url_cleaner_ref = url_cleaner.clone();
     let stats_clone = statistics.clone();
     let configuration_updater_tx_ref = configuration_updater_tx.clone();
     let configuration_save_lock_ref = configuration_save_lock.clone();
     let broadcast_tx_ref = broadcast_tx.clone();
     let notify_reload_clone = notify_reload.clone();
+    let cert_cache_ref = cert_cache.clone();
+    let blocker_requester_ref = blocker_requester.clone();
 
     tokio::spawn(async move {
         let notify_reload_frontend = notify_reload_clone.clone();
@@ -175,10 +226,17 @@ pub async fn start_privaxy() -> PrivaxyServer {
             privaxy_frontend(
                 broadcast_tx_ref.clone(),
                 local_exclusion_store_ref.clone(),
+                allowlist_store_ref.clone(),
+                user_content_store_ref.clone(),
+                body_rewrite_store_ref.clone(),
+                header_policy_store_ref.clone(),
+                url_cleaner_ref.clone(),
                 stats_clone.clone(),
                 block_disable_ref.clone(),
                 configuration_updater_tx_ref.clone(),
                 cfg_lock_frontend.clone(),
+                cert_cache_ref.clone(),
+                blocker_requester_ref.clone(),
                 notify_reload_frontend.clone(),
             )
             .await;
@@ -187,13 +245,12 @@ pub async fn start_privaxy() -> PrivaxyServer {
         }
     });
 
-    let disabled_store_ref = blocking_disabled_store_clone.clone();
-    thread::spawn(move || {
-        let blocker =
-            blocker::Blocker::new(crossbeam_sender, crossbeam_receiver, disabled_store_ref);
-
-        blocker.handle_requests()
-    });
+    blocker::Blocker::start_pool(
+        blocker_workers,
+        crossbeam_receiver,
+        blocking_disabled_store_clone.clone(),
+        engine_stats,
+    );
 
     let notify_reload_clone = notify_reload.clone();
     let configuration_save_lock_ref = configuration_save_lock.clone();
@@ -201,29 +258,43 @@ pub async fn start_privaxy() -> PrivaxyServer {
     tokio::spawn(async move {
         let notify_reload_backend = notify_reload_clone.clone();
         let cfg_lock_backend = configuration_save_lock_ref.clone();
-        let mut rt_cert_cache =
-            cert::CertCache::new(ca_certificate.clone(), ca_private_key.clone());
-        let mut rt_ca_certificate = ca_certificate;
         loop {
             log::info!("Starting Privaxy proxy");
             privaxy_backend(
                 client.clone(),
-                rt_cert_cache.clone(),
+                cert_cache.clone(),
                 blocker_requester.clone(),
                 broadcast_tx.clone(),
                 statistics.clone(),
                 local_exclusion_store.clone(),
+                user_content_store.clone(),
+                body_rewrite_store.clone(),
+                url_cleaner.clone(),
+                header_policy_store.clone(),
                 cfg_lock_backend.clone(),
                 notify_reload_backend.clone(),
             )
             .await;
             let cfg = read_configuration(&cfg_lock_backend).await;
-            let ca_cert = cfg.ca.get_ca_certificate().await.unwrap();
-            let ca_key = cfg.ca.get_ca_private_key().await.unwrap();
-            if !ca_key.public_eq(&rt_ca_certificate.public_key().unwrap()) {
-                rt_ca_certificate = ca_cert.clone();
-                rt_cert_cache = cert::CertCache::new(ca_cert, ca_key);
+            if let Err(err) = cert_cache.sync_with(&cfg.ca).await {
+                log::error!("Unable to load the CA certificate: {err}");
+            }
+        }
+    });
+
+    let configuration_save_lock_ref = configuration_save_lock.clone();
+    let cert_cache_ref = cert_cache.clone();
+
+    // Picks up the end of CA rotation overlap windows and warns about expiring CAs.
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(CA_CHECK_INTERVAL).await;
+
+            let cfg = read_configuration(&configuration_save_lock_ref).await;
+            if let Err(err) = cert_cache_ref.sync_with(&cfg.ca).await {
+                log::error!("Unable to load the CA certificate: {err}");
             }
+            cfg.ca.warn_on_expiry().await;
         }
     });
     PrivaxyServer {
@@ -237,13 +308,21 @@ pub async fn start_privaxy() -> PrivaxyServer {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn privaxy_frontend(
     broadcast_tx: tokio::sync::broadcast::Sender<Event>,
     local_exclusion_store: LocalExclusionStore,
+    allowlist_store: AllowlistStore,
+    user_content_store: UserContentStore,
+    body_rewrite_store: BodyRewriteStore,
+    header_policy_store: HeaderPolicyStore,
+    url_cleaner: UrlCleaner,
     statistics: statistics::Statistics,
     block_disable_ref: blocker::BlockingDisabledStore,
     configuration_updater_tx: tokio::sync::mpsc::Sender<configuration::Configuration>,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    cert_cache: cert::CertCache,
+    blocker_requester: AdblockRequester,
     notify_reload: Arc<tokio::sync::Notify>,
 ) {
     let config = read_configuration(&configuration_save_lock).await;
@@ -254,6 +333,13 @@ async fn privaxy_frontend(
         &configuration_updater_tx,
         &configuration_save_lock,
         &local_exclusion_store,
+        &allowlist_store,
+        &user_content_store,
+        &body_rewrite_store,
+        &header_policy_store,
+        &url_cleaner,
+        &cert_cache,
+        &blocker_requester,
         config.network.tls,
         notify_reload.clone(),
     );
@@ -324,6 +410,7 @@ async fn env_or_config_ip(network_config: &NetworkConfig) -> IpAddr {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn privaxy_backend(
     client: reqwest::Client,
     cert_cache: cert::CertCache,
@@ -331,6 +418,10 @@ async fn privaxy_backend(
     broadcast_tx: broadcast::Sender<Event>,
     statistics: statistics::Statistics,
     local_exclusion_store: LocalExclusionStore,
+    user_content_store: UserContentStore,
+    body_rewrite_store: BodyRewriteStore,
+    url_cleaner: UrlCleaner,
+    header_policy_store: HeaderPolicyStore,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
     notify_reload: Arc<tokio::sync::Notify>,
 ) {
@@ -341,6 +432,7 @@ async fn privaxy_backend(
         .build();
     let config = read_configuration(&configuration_save_lock).await;
     let network_config = &config.network;
+    let max_rewritten_document_size = config.rewriter.max_document_size();
 
     // The hyper client is only used to perform upgrades. We don't need to
     // handle compression.
@@ -358,6 +450,10 @@ async fn privaxy_backend(
         let broadcast_tx = broadcast_tx.clone();
         let statistics = statistics.clone();
         let local_exclusion_store = local_exclusion_store.clone();
+        let user_content_store = user_content_store.clone();
+        let body_rewrite_store = body_rewrite_store.clone();
+        let url_cleaner = url_cleaner.clone();
+        let header_policy_store = header_policy_store.clone();
 
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
@@ -371,6 +467,11 @@ async fn privaxy_backend(
                     statistics.clone(),
                     client_ip_address,
                     local_exclusion_store.clone(),
+                    user_content_store.clone(),
+                    body_rewrite_store.clone(),
+                    url_cleaner.clone(),
+                    header_policy_store.clone(),
+                    max_rewritten_document_size,
                 )
             }))
         }
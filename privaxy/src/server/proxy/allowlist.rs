@@ -0,0 +1,27 @@
+use super::exclusions::WildMatchCollection;
+use std::sync::{Arc, RwLock};
+
+/// Sites blocking is disabled for. Unlike exclusions, their traffic still goes through the
+/// MITM pipeline, so it keeps showing in the requests feed and statistics.
+#[derive(Debug, Clone)]
+pub struct AllowlistStore(Arc<RwLock<WildMatchCollection>>);
+
+impl AllowlistStore {
+    pub fn new(allowlist: Vec<String>) -> Self {
+        Self(Arc::new(RwLock::new(WildMatchCollection::new(allowlist))))
+    }
+
+    pub fn replace_allowlist(&self, allowlist: Vec<String>) {
+        *self.0.write().unwrap() = WildMatchCollection::new(allowlist);
+    }
+
+    /// Whether a site is allowlisted, allowlisting a domain covers its subdomains as well.
+    pub fn contains(&self, site: &str) -> bool {
+        let allowlist = self.0.read().unwrap();
+
+        std::iter::successors(Some(site), |domain| {
+            domain.split_once('.').map(|(_label, parent)| parent)
+        })
+        .any(|domain| allowlist.is_match(domain))
+    }
+}
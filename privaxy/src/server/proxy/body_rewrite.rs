@@ -0,0 +1,358 @@
+use super::exclusions::WildMatchCollection;
+use crate::configuration::{BodyRewriteAction, BodyRewriteRule};
+use regex::bytes::Regex;
+use serde_json::Value;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BodyRewriteRuleError {
+    #[error("invalid regular expression in {0}: {1}")]
+    InvalidRegex(String, regex::Error),
+    #[error("invalid JSON path in {0}: {1}")]
+    InvalidJsonPath(String, String),
+    #[error("invalid selector in {0}: {1}")]
+    InvalidSelector(String, String),
+}
+
+/// Transform applied to whole bodies, which are buffered for it.
+#[derive(Debug, Clone)]
+enum BodyTransform {
+    RegexReplace { regex: Regex, replacement: String },
+    JsonRemove(Vec<Vec<String>>),
+}
+
+#[derive(Debug, Clone)]
+enum CompiledAction {
+    Transform(BodyTransform),
+    /// Applied by the HTML rewriter while documents are streamed.
+    HtmlRemove(String),
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    url: WildMatchCollection,
+    content_type: Option<WildMatchCollection>,
+    action: CompiledAction,
+}
+
+impl CompiledRule {
+    fn new(rule: &BodyRewriteRule) -> Result<Self, BodyRewriteRuleError> {
+        let action = match &rule.action {
+            BodyRewriteAction::RegexReplace {
+                pattern,
+                replacement,
+            } => CompiledAction::Transform(BodyTransform::RegexReplace {
+                regex: Regex::new(pattern)
+                    .map_err(|err| BodyRewriteRuleError::InvalidRegex(rule.name.clone(), err))?,
+                replacement: replacement.clone(),
+            }),
+            BodyRewriteAction::JsonRemove { paths } => {
+                CompiledAction::Transform(BodyTransform::JsonRemove(
+                    paths
+                        .iter()
+                        .map(|path| {
+                            parse_json_path(path).ok_or_else(|| {
+                                BodyRewriteRuleError::InvalidJsonPath(
+                                    rule.name.clone(),
+                                    path.clone(),
+                                )
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                ))
+            }
+            BodyRewriteAction::HtmlRemove { selector } => {
+                if selector.parse::<lol_html::Selector>().is_err() {
+                    return Err(BodyRewriteRuleError::InvalidSelector(
+                        rule.name.clone(),
+                        selector.clone(),
+                    ));
+                }
+
+                CompiledAction::HtmlRemove(selector.clone())
+            }
+        };
+
+        Ok(Self {
+            url: WildMatchCollection::new(vec![rule.url.clone()]),
+            content_type: rule
+                .content_type
+                .clone()
+                .map(|content_type| WildMatchCollection::new(vec![content_type])),
+            action,
+        })
+    }
+
+    fn is_match(&self, url: &str, mime_type: &str) -> bool {
+        let is_content_type_match = match (&self.action, &self.content_type) {
+            (CompiledAction::HtmlRemove(_), _) => mime_type == "text/html",
+            (_, Some(content_type)) => content_type.is_match(mime_type),
+            (_, None) => true,
+        };
+
+        is_content_type_match && self.url.is_match(url)
+    }
+}
+
+/// `$.data[*].ad` and `data.*.ad` both stand for the `ad` key of every item of `data`.
+fn parse_json_path(path: &str) -> Option<Vec<String>> {
+    let path = path.trim();
+    let path = path.strip_prefix("$.").unwrap_or(path);
+
+    let segments = path
+        .replace("[*]", ".*")
+        .split('.')
+        .map(String::from)
+        .collect::<Vec<_>>();
+
+    if segments.iter().any(|segment| segment.is_empty()) {
+        return None;
+    }
+
+    Some(segments)
+}
+
+/// Removes the values at `path`, returns whether any was.
+fn remove_json_path(value: &mut Value, path: &[String]) -> bool {
+    let (segment, rest) = match path.split_first() {
+        Some(split_path) => split_path,
+        None => return false,
+    };
+
+    if rest.is_empty() {
+        return match value {
+            Value::Object(object) if segment == "*" => {
+                let has_values = !object.is_empty();
+                object.clear();
+                has_values
+            }
+            Value::Object(object) => object.remove(segment).is_some(),
+            Value::Array(items) if segment == "*" => {
+                let has_items = !items.is_empty();
+                items.clear();
+                has_items
+            }
+            Value::Array(items) => match segment.parse::<usize>() {
+                Ok(index) if index < items.len() => {
+                    items.remove(index);
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        };
+    }
+
+    let children: Vec<&mut Value> = match value {
+        Value::Object(object) if segment == "*" => object.values_mut().collect(),
+        Value::Object(object) => object.get_mut(segment).into_iter().collect(),
+        Value::Array(items) if segment == "*" => items.iter_mut().collect(),
+        Value::Array(items) => segment
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| items.get_mut(index))
+            .into_iter()
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    children.into_iter().fold(false, |removed, child| {
+        remove_json_path(child, rest) || removed
+    })
+}
+
+impl BodyTransform {
+    /// Returns the transformed body, or `None` when it is left unchanged.
+    fn apply(&self, body: &[u8]) -> Option<Vec<u8>> {
+        match self {
+            Self::RegexReplace { regex, replacement } => {
+                if !regex.is_match(body) {
+                    return None;
+                }
+
+                Some(regex.replace_all(body, replacement.as_bytes()).into_owned())
+            }
+            Self::JsonRemove(paths) => {
+                let mut value = serde_json::from_slice::<Value>(body).ok()?;
+
+                let removed = paths.iter().fold(false, |removed, path| {
+                    remove_json_path(&mut value, path) || removed
+                });
+
+                removed.then(|| serde_json::to_vec(&value).unwrap())
+            }
+        }
+    }
+}
+
+/// Body rewrite rules matching a response.
+#[derive(Debug, Default)]
+pub(crate) struct MatchedBodyRewriteRules {
+    transforms: Vec<BodyTransform>,
+    /// Selectors of the elements to remove from HTML documents.
+    pub html_removals: Vec<String>,
+}
+
+impl MatchedBodyRewriteRules {
+    /// Whether the body has to be read whole before being sent.
+    pub(crate) fn has_transforms(&self) -> bool {
+        !self.transforms.is_empty()
+    }
+
+    /// Applies transforms in configuration order, returns `None` when none changed the body.
+    pub(crate) fn transform(&self, body: &[u8]) -> Option<Vec<u8>> {
+        self.transforms
+            .iter()
+            .fold(None, |transformed: Option<Vec<u8>>, transform| {
+                let current = transformed.as_deref().unwrap_or(body);
+
+                transform.apply(current).or(transformed)
+            })
+    }
+}
+
+/// Checks every rule can be applied.
+pub(crate) fn check_rules(rules: &[BodyRewriteRule]) -> Result<(), BodyRewriteRuleError> {
+    rules
+        .iter()
+        .try_for_each(|rule| CompiledRule::new(rule).map(|_rule| ()))
+}
+
+/// Enabled body rewrite rules.
+#[derive(Debug, Clone)]
+pub struct BodyRewriteStore(Arc<RwLock<Vec<CompiledRule>>>);
+
+impl BodyRewriteStore {
+    pub fn new(rules: Vec<BodyRewriteRule>) -> Self {
+        Self(Arc::new(RwLock::new(Self::compile(rules))))
+    }
+
+    pub fn replace_rules(&self, rules: Vec<BodyRewriteRule>) {
+        *self.0.write().unwrap() = Self::compile(rules);
+    }
+
+    /// Invalid rules are left out, as rules set through the API are checked they can only
+    /// come from a hand edited configuration file.
+    fn compile(rules: Vec<BodyRewriteRule>) -> Vec<CompiledRule> {
+        rules
+            .iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| match CompiledRule::new(rule) {
+                Ok(compiled_rule) => Some(compiled_rule),
+                Err(err) => {
+                    log::error!("Ignoring body rewrite rule: {err}");
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Rules matching a response, by URL and `Content-Type` header value.
+    pub(crate) fn get_matching(&self, url: &str, content_type: &str) -> MatchedBodyRewriteRules {
+        let mime_type = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+
+        let mut matched_rules = MatchedBodyRewriteRules::default();
+
+        for rule in self.0.read().unwrap().iter() {
+            if !rule.is_match(url, &mime_type) {
+                continue;
+            }
+
+            match &rule.action {
+                CompiledAction::Transform(transform) => {
+                    matched_rules.transforms.push(transform.clone())
+                }
+                CompiledAction::HtmlRemove(selector) => {
+                    matched_rules.html_removals.push(selector.clone())
+                }
+            }
+        }
+
+        matched_rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn remove(value: &mut Value, path: &str) -> bool {
+        remove_json_path(value, &parse_json_path(path).unwrap())
+    }
+
+    #[test]
+    fn parses_json_paths() {
+        assert_eq!(
+            parse_json_path("$.a[*].b"),
+            Some(vec!["a".to_string(), "*".to_string(), "b".to_string()])
+        );
+        assert_eq!(parse_json_path("a.*.b"), parse_json_path("$.a[*].b"));
+        assert_eq!(
+            parse_json_path(" a.0 "),
+            Some(vec!["a".to_string(), "0".to_string()])
+        );
+        assert_eq!(parse_json_path("a..b"), None);
+        assert_eq!(parse_json_path("$."), None);
+    }
+
+    #[test]
+    fn removes_keys_of_every_item() {
+        let mut value = json!({"a": [{"b": 1, "c": 2}, {"b": 3}, {"c": 4}]});
+
+        assert!(remove(&mut value, "$.a[*].b"));
+        assert_eq!(value, json!({"a": [{"c": 2}, {}, {"c": 4}]}));
+        assert!(!remove(&mut value, "$.a[*].b"));
+    }
+
+    #[test]
+    fn removes_items_by_index() {
+        let mut value = json!({"a": [{"b": 1}, {"b": 2}, {"b": 3}]});
+
+        assert!(remove(&mut value, "a.1"));
+        assert_eq!(value, json!({"a": [{"b": 1}, {"b": 3}]}));
+
+        assert!(remove(&mut value, "a.0.b"));
+        assert_eq!(value, json!({"a": [{}, {"b": 3}]}));
+
+        assert!(!remove(&mut value, "a.2"));
+        assert!(!remove(&mut value, "a.first"));
+    }
+
+    #[test]
+    fn clears_values_with_a_leaf_wildcard() {
+        let mut value = json!({"a": {"b": 1, "c": 2}, "d": [1, 2], "e": []});
+
+        assert!(remove(&mut value, "a.*"));
+        assert!(remove(&mut value, "d.*"));
+        assert!(!remove(&mut value, "e.*"));
+        assert_eq!(value, json!({"a": {}, "d": [], "e": []}));
+    }
+
+    #[test]
+    fn leaves_missing_paths_alone() {
+        let mut value = json!({"a": 1});
+
+        assert!(!remove(&mut value, "b"));
+        assert!(!remove(&mut value, "a.b"));
+        assert_eq!(value, json!({"a": 1}));
+    }
+
+    #[test]
+    fn leaves_non_json_bodies_unchanged() {
+        let transform = BodyTransform::JsonRemove(vec![parse_json_path("a").unwrap()]);
+
+        assert_eq!(transform.apply(b"<html></html>"), None);
+        assert_eq!(transform.apply(br#"{"b": 1}"#), None);
+        assert_eq!(
+            transform.apply(br#"{"a": 1, "b": 2}"#),
+            Some(br#"{"b":2}"#.to_vec())
+        );
+    }
+}
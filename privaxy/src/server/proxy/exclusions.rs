@@ -3,10 +3,10 @@ use std::sync::{Arc, RwLock};
 use wildmatch::WildMatch;
 
 #[derive(Debug, Clone)]
-struct WildMatchCollection(Vec<WildMatch>);
+pub(super) struct WildMatchCollection(Vec<WildMatch>);
 
 impl WildMatchCollection {
-    fn new(patterns: Vec<String>) -> Self {
+    pub(super) fn new(patterns: Vec<String>) -> Self {
         Self(
             patterns
                 .into_iter()
@@ -20,7 +20,7 @@ impl WildMatchCollection {
         )
     }
 
-    fn is_match(&self, element: &str) -> bool {
+    pub(super) fn is_match(&self, element: &str) -> bool {
         // Making things case insensitive
         let lowercase_element = element.to_lowercase();
 
@@ -0,0 +1,92 @@
+use crate::blocker::AdblockRequester;
+use hyper::{http, Body, Request, Response};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// Path pages ask for generic selectors on, answered by Privaxy on every site so that
+/// requests from the injected script are same-origin.
+pub(crate) const GENERIC_COSMETIC_PATH: &str = "/__privaxy__/generic-cosmetic";
+
+/// Lists of classes and ids are sent in batches, larger bodies are refused.
+const MAX_BODY_SIZE: u64 = 256 * 1024;
+
+/// Watches for elements added to the page and hides the ones matching generic selectors.
+pub(crate) const GENERIC_COSMETIC_RUNTIME: &str =
+    include_str!("../../resources/generic_cosmetic.js");
+
+#[derive(Debug, Deserialize)]
+struct GenericCosmeticRequest {
+    /// Page the classes and ids were found in.
+    url: String,
+    #[serde(default)]
+    ids: Vec<String>,
+    #[serde(default)]
+    classes: Vec<String>,
+}
+
+pub(super) async fn serve_generic_cosmetic(
+    adblock_requester: AdblockRequester,
+    request: Request<Body>,
+    client_ip_address: IpAddr,
+) -> Response<Body> {
+    if request.method() != http::Method::POST {
+        return get_response(http::StatusCode::METHOD_NOT_ALLOWED, Body::empty());
+    }
+
+    let content_length = request
+        .headers()
+        .get(http::header::CONTENT_LENGTH)
+        .and_then(|content_length| content_length.to_str().ok()?.parse::<u64>().ok());
+
+    if !matches!(content_length, Some(content_length) if content_length <= MAX_BODY_SIZE) {
+        return get_response(http::StatusCode::PAYLOAD_TOO_LARGE, Body::empty());
+    }
+
+    let generic_cosmetic_request = match hyper::body::to_bytes(request.into_body())
+        .await
+        .ok()
+        .and_then(|body| serde_json::from_slice::<GenericCosmeticRequest>(&body).ok())
+    {
+        Some(generic_cosmetic_request) => generic_cosmetic_request,
+        None => return get_response(http::StatusCode::BAD_REQUEST, Body::empty()),
+    };
+
+    let site = match url::Url::parse(&generic_cosmetic_request.url) {
+        Ok(url) => url.host_str().unwrap_or_default().to_lowercase(),
+        Err(_err) => return get_response(http::StatusCode::BAD_REQUEST, Body::empty()),
+    };
+
+    let hidden_selectors = if adblock_requester.is_blocking_enabled_for(client_ip_address, &site) {
+        adblock_requester
+            .get_generic_cosmetic_selectors(
+                generic_cosmetic_request.url,
+                generic_cosmetic_request.ids,
+                generic_cosmetic_request.classes,
+            )
+            .await
+    } else {
+        Vec::new()
+    };
+
+    let mut response = get_response(
+        http::StatusCode::OK,
+        Body::from(serde_json::to_string(&hidden_selectors).unwrap()),
+    );
+    response.headers_mut().insert(
+        http::header::CONTENT_TYPE,
+        http::HeaderValue::from_static("application/json"),
+    );
+    response.headers_mut().insert(
+        http::header::CACHE_CONTROL,
+        http::HeaderValue::from_static("no-store"),
+    );
+
+    response
+}
+
+fn get_response(status_code: http::StatusCode, body: Body) -> Response<Body> {
+    let mut response = Response::new(body);
+    *response.status_mut() = status_code;
+
+    response
+}
@@ -0,0 +1,425 @@
+use super::exclusions::WildMatchCollection;
+use crate::configuration::{HeaderPolicy, HeaderPolicyAction};
+use crate::statistics::Statistics;
+use http::header::{self, HeaderName};
+use http::{HeaderMap, HeaderValue, Uri};
+use std::sync::{Arc, RwLock};
+
+const CLIENT_HINTS_PREFIX: &str = "sec-ch-";
+const SEC_GPC: &str = "sec-gpc";
+const DNT: &str = "dnt";
+const ACCEPT_CH: &str = "accept-ch";
+const CRITICAL_CH: &str = "critical-ch";
+
+#[derive(Debug)]
+struct CompiledHeaderPolicy {
+    name: String,
+    hosts: Option<WildMatchCollection>,
+    action: HeaderPolicyAction,
+}
+
+impl CompiledHeaderPolicy {
+    fn new(header_policy: HeaderPolicy) -> Self {
+        Self {
+            name: header_policy.name,
+            hosts: (!header_policy.hosts.is_empty())
+                .then(|| WildMatchCollection::new(header_policy.hosts)),
+            action: header_policy.action,
+        }
+    }
+
+    fn is_match(&self, host: &str) -> bool {
+        self.hosts.as_ref().is_none_or(|hosts| hosts.is_match(host))
+    }
+}
+
+/// Sets `name` to `value`, returns whether it was not already.
+fn set_header(headers: &mut HeaderMap, name: HeaderName, value: HeaderValue) -> bool {
+    if headers.get(&name) == Some(&value) {
+        return false;
+    }
+
+    headers.insert(name, value);
+    true
+}
+
+/// Removes every header `is_removed` returns true for, returns whether any was.
+fn remove_headers(headers: &mut HeaderMap, is_removed: impl Fn(&str) -> bool) -> bool {
+    let removed_names = headers
+        .keys()
+        .filter(|name| is_removed(name.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    for name in &removed_names {
+        headers.remove(name);
+    }
+
+    !removed_names.is_empty()
+}
+
+/// Registrable domain of `url`, such as `example.co.uk` for `www.example.co.uk`. Hosts
+/// without one, such as IP addresses, stand for themselves.
+fn get_site(url: &url::Url) -> Option<&str> {
+    let host = url.host_str()?;
+
+    Some(psl::domain_str(host).unwrap_or(host))
+}
+
+/// Origin the `Referer` is trimmed to, when it is from another site than `uri`.
+fn get_trimmed_cross_site_referer(headers: &HeaderMap, uri: &Uri) -> Option<HeaderValue> {
+    let referer = url::Url::parse(headers.get(header::REFERER)?.to_str().ok()?).ok()?;
+    let request_url = url::Url::parse(&uri.to_string()).ok()?;
+
+    if get_site(&referer) == get_site(&request_url) {
+        return None;
+    }
+
+    let trimmed_referer = format!("{}/", referer.origin().ascii_serialization());
+
+    if referer.as_str() == trimmed_referer {
+        return None;
+    }
+
+    HeaderValue::from_str(&trimmed_referer).ok()
+}
+
+fn apply_to_request(action: &HeaderPolicyAction, headers: &mut HeaderMap, uri: &Uri) -> bool {
+    match action {
+        HeaderPolicyAction::TrimCrossSiteReferer => {
+            match get_trimmed_cross_site_referer(headers, uri) {
+                Some(trimmed_referer) => {
+                    headers.insert(header::REFERER, trimmed_referer);
+                    true
+                }
+                None => false,
+            }
+        }
+        HeaderPolicyAction::UserAgent { replacement } => {
+            match replacement
+                .as_deref()
+                .and_then(|replacement| HeaderValue::from_str(replacement).ok())
+            {
+                Some(replacement) => set_header(headers, header::USER_AGENT, replacement),
+                None => headers.remove(header::USER_AGENT).is_some(),
+            }
+        }
+        HeaderPolicyAction::StripClientHints => {
+            remove_headers(headers, |name| name.starts_with(CLIENT_HINTS_PREFIX))
+        }
+        HeaderPolicyAction::GlobalPrivacyControl => set_header(
+            headers,
+            HeaderName::from_static(SEC_GPC),
+            HeaderValue::from_static("1"),
+        ),
+        HeaderPolicyAction::DoNotTrack => set_header(
+            headers,
+            HeaderName::from_static(DNT),
+            HeaderValue::from_static("1"),
+        ),
+        HeaderPolicyAction::StripEtags => headers.remove(header::IF_NONE_MATCH).is_some(),
+        HeaderPolicyAction::StripAltSvc => false,
+    }
+}
+
+fn apply_to_response(action: &HeaderPolicyAction, headers: &mut HeaderMap) -> bool {
+    match action {
+        HeaderPolicyAction::StripClientHints => {
+            remove_headers(headers, |name| name == ACCEPT_CH || name == CRITICAL_CH)
+        }
+        HeaderPolicyAction::StripEtags => headers.remove(header::ETAG).is_some(),
+        HeaderPolicyAction::StripAltSvc => headers.remove(header::ALT_SVC).is_some(),
+        HeaderPolicyAction::TrimCrossSiteReferer
+        | HeaderPolicyAction::UserAgent { .. }
+        | HeaderPolicyAction::GlobalPrivacyControl
+        | HeaderPolicyAction::DoNotTrack => false,
+    }
+}
+
+/// Header policies matching a request, in configuration order.
+#[derive(Debug, Default)]
+pub(crate) struct MatchedHeaderPolicies(Vec<(String, HeaderPolicyAction)>);
+
+impl MatchedHeaderPolicies {
+    /// Policies are counted in statistics every time they change headers.
+    pub(crate) fn apply_to_request(
+        &self,
+        headers: &mut HeaderMap,
+        uri: &Uri,
+        statistics: &Statistics,
+    ) {
+        for (name, action) in &self.0 {
+            if apply_to_request(action, headers, uri) {
+                statistics.increment_header_policy_hits(name);
+            }
+        }
+    }
+
+    pub(crate) fn apply_to_response(&self, headers: &mut HeaderMap, statistics: &Statistics) {
+        for (name, action) in &self.0 {
+            if apply_to_response(action, headers) {
+                statistics.increment_header_policy_hits(name);
+            }
+        }
+    }
+}
+
+/// Enabled header policies.
+#[derive(Debug, Clone)]
+pub struct HeaderPolicyStore(Arc<RwLock<Vec<CompiledHeaderPolicy>>>);
+
+impl HeaderPolicyStore {
+    pub fn new(header_policies: Vec<HeaderPolicy>) -> Self {
+        Self(Arc::new(RwLock::new(Self::compile(header_policies))))
+    }
+
+    pub fn replace_header_policies(&self, header_policies: Vec<HeaderPolicy>) {
+        *self.0.write().unwrap() = Self::compile(header_policies);
+    }
+
+    fn compile(header_policies: Vec<HeaderPolicy>) -> Vec<CompiledHeaderPolicy> {
+        header_policies
+            .into_iter()
+            .filter(|header_policy| header_policy.enabled)
+            .map(CompiledHeaderPolicy::new)
+            .collect()
+    }
+
+    pub(crate) fn get_matching(&self, host: &str) -> MatchedHeaderPolicies {
+        MatchedHeaderPolicies(
+            self.0
+                .read()
+                .unwrap()
+                .iter()
+                .filter(|header_policy| header_policy.is_match(host))
+                .map(|header_policy| (header_policy.name.clone(), header_policy.action.clone()))
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    fn trim(referer: &'static str, url: &str) -> Option<String> {
+        let headers = get_headers(&[("referer", referer)]);
+
+        get_trimmed_cross_site_referer(&headers, &url.parse().unwrap())
+            .map(|referer| referer.to_str().unwrap().to_string())
+    }
+
+    fn get_policy(name: &str, hosts: &[&str], action: HeaderPolicyAction) -> HeaderPolicy {
+        HeaderPolicy {
+            name: name.to_string(),
+            hosts: hosts.iter().map(|host| host.to_string()).collect(),
+            action,
+            enabled: true,
+        }
+    }
+
+    #[test]
+    fn trims_cross_site_referers() {
+        assert_eq!(
+            trim("https://a.com/page?q=1", "https://b.com/script.js").as_deref(),
+            Some("https://a.com/")
+        );
+        assert_eq!(
+            trim("http://a.com:8080/page", "https://b.com/").as_deref(),
+            Some("http://a.com:8080/")
+        );
+        assert_eq!(
+            trim("https://a.co.uk/page", "https://b.co.uk/").as_deref(),
+            Some("https://a.co.uk/")
+        );
+        assert_eq!(
+            trim("http://192.168.1.1/admin", "http://192.168.1.2/").as_deref(),
+            Some("http://192.168.1.1/")
+        );
+    }
+
+    #[test]
+    fn keeps_same_site_referers() {
+        assert_eq!(
+            trim(
+                "https://www.example.com/page",
+                "https://cdn.example.com/a.js"
+            ),
+            None
+        );
+        assert_eq!(
+            trim("https://example.co.uk/page", "https://www.example.co.uk/"),
+            None
+        );
+        assert_eq!(
+            trim("http://192.168.1.1/admin", "http://192.168.1.1/api"),
+            None
+        );
+    }
+
+    #[test]
+    fn keeps_referers_already_trimmed_or_invalid() {
+        assert_eq!(trim("https://a.com/", "https://b.com/"), None);
+        assert_eq!(trim("not a url", "https://b.com/"), None);
+        assert_eq!(
+            get_trimmed_cross_site_referer(&HeaderMap::new(), &"https://b.com/".parse().unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn replaces_or_removes_user_agents() {
+        let uri = "https://a.com/".parse().unwrap();
+        let replacement = HeaderPolicyAction::UserAgent {
+            replacement: Some("Mozilla/5.0".to_string()),
+        };
+        let mut headers = get_headers(&[("user-agent", "Browser/1.0")]);
+
+        assert!(apply_to_request(&replacement, &mut headers, &uri));
+        assert_eq!(headers[header::USER_AGENT], "Mozilla/5.0");
+        assert!(!apply_to_request(&replacement, &mut headers, &uri));
+
+        let removal = HeaderPolicyAction::UserAgent { replacement: None };
+
+        assert!(apply_to_request(&removal, &mut headers, &uri));
+        assert!(!headers.contains_key(header::USER_AGENT));
+        assert!(!apply_to_request(&removal, &mut headers, &uri));
+    }
+
+    #[test]
+    fn strips_client_hints() {
+        let uri = "https://a.com/".parse().unwrap();
+        let mut request_headers = get_headers(&[
+            ("sec-ch-ua", "\"Browser\";v=\"1\""),
+            ("sec-ch-ua-platform", "\"Linux\""),
+            ("sec-fetch-dest", "document"),
+        ]);
+        let mut response_headers = get_headers(&[
+            ("accept-ch", "Sec-CH-UA-Model"),
+            ("critical-ch", "Sec-CH-UA-Model"),
+            ("content-type", "text/html"),
+        ]);
+
+        let action = HeaderPolicyAction::StripClientHints;
+
+        assert!(apply_to_request(&action, &mut request_headers, &uri));
+        assert_eq!(
+            request_headers,
+            get_headers(&[("sec-fetch-dest", "document")])
+        );
+        assert!(!apply_to_request(&action, &mut request_headers, &uri));
+
+        assert!(apply_to_response(&action, &mut response_headers));
+        assert_eq!(
+            response_headers,
+            get_headers(&[("content-type", "text/html")])
+        );
+    }
+
+    #[test]
+    fn sends_privacy_signals() {
+        let uri = "https://a.com/".parse().unwrap();
+        let mut headers = HeaderMap::new();
+
+        for action in [
+            HeaderPolicyAction::GlobalPrivacyControl,
+            HeaderPolicyAction::DoNotTrack,
+        ] {
+            assert!(apply_to_request(&action, &mut headers, &uri));
+            assert!(!apply_to_request(&action, &mut headers, &uri));
+        }
+
+        assert_eq!(headers, get_headers(&[("sec-gpc", "1"), ("dnt", "1")]));
+    }
+
+    #[test]
+    fn strips_etags_and_alt_svc() {
+        let uri = "https://a.com/".parse().unwrap();
+        let mut request_headers = get_headers(&[("if-none-match", "\"abc\"")]);
+        let mut response_headers = get_headers(&[("etag", "\"abc\""), ("alt-svc", "h3=\":443\"")]);
+
+        assert!(apply_to_request(
+            &HeaderPolicyAction::StripEtags,
+            &mut request_headers,
+            &uri
+        ));
+        assert!(request_headers.is_empty());
+        assert!(!apply_to_request(
+            &HeaderPolicyAction::StripAltSvc,
+            &mut request_headers,
+            &uri
+        ));
+
+        assert!(apply_to_response(
+            &HeaderPolicyAction::StripEtags,
+            &mut response_headers
+        ));
+        assert!(apply_to_response(
+            &HeaderPolicyAction::StripAltSvc,
+            &mut response_headers
+        ));
+        assert!(response_headers.is_empty());
+    }
+
+    #[test]
+    fn applies_matching_policies_and_counts_hits() {
+        let mut disabled_policy = get_policy("Disabled", &[], HeaderPolicyAction::DoNotTrack);
+        disabled_policy.enabled = false;
+
+        let store = HeaderPolicyStore::new(vec![
+            get_policy(
+                "Example referers",
+                &["*.example.com"],
+                HeaderPolicyAction::TrimCrossSiteReferer,
+            ),
+            get_policy("Everywhere", &[], HeaderPolicyAction::GlobalPrivacyControl),
+            disabled_policy,
+        ]);
+        let statistics = Statistics::new();
+        let uri = "https://cdn.example.com/a.js".parse().unwrap();
+        let mut headers = get_headers(&[("referer", "https://a.com/page")]);
+
+        store
+            .get_matching("cdn.example.com")
+            .apply_to_request(&mut headers, &uri, &statistics);
+
+        assert_eq!(
+            headers,
+            get_headers(&[("referer", "https://a.com/"), ("sec-gpc", "1")])
+        );
+
+        let mut headers = get_headers(&[("referer", "https://a.com/page")]);
+
+        store
+            .get_matching("other.com")
+            .apply_to_request(&mut headers, &uri, &statistics);
+
+        assert_eq!(
+            headers,
+            get_headers(&[("referer", "https://a.com/page"), ("sec-gpc", "1")])
+        );
+
+        let mut hits = statistics.get_serialized().header_policy_hits;
+        hits.sort();
+
+        assert_eq!(
+            hits,
+            [
+                ("Everywhere".to_string(), 2),
+                ("Example referers".to_string(), 1)
+            ]
+        );
+    }
+}
@@ -1,59 +1,409 @@
-use crate::{blocker::AdblockRequester, statistics::Statistics};
-use crossbeam_channel::Receiver;
+use super::generic_cosmetic::{GENERIC_COSMETIC_PATH, GENERIC_COSMETIC_RUNTIME};
+use super::user_content::MatchedUserContent;
+use crate::blocker::{AdblockRequester, NetworkBlockerResult, NETWORK_REQUEST_TYPE};
+use crate::statistics::Statistics;
+use encoding_rs::Encoding;
 use hyper::body::Bytes;
-use lol_html::{element, HtmlRewriter, Settings};
+use lazy_static::lazy_static;
+use lol_html::html_content::{ContentType, Element};
+use lol_html::{element, AsciiCompatibleEncoding, HtmlRewriter, Settings};
 use regex::Regex;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Semaphore};
+
+/// Evaluates procedural cosmetic filters in the page, only injected when there are some.
+const PROCEDURAL_RUNTIME: &str = include_str!("../../resources/procedural.js");
+
+/// Length of the start of documents `<meta charset>` is looked for in, as browsers do.
+const ENCODING_PRESCAN_LENGTH: usize = 1024;
+
+/// Longest start of a tag cut by the end of a chunk that is looked for resources along with
+/// the next chunk.
+const MAX_CUT_TAG_LENGTH: usize = 4096;
+
+/// Documents rewritten at the same time, each of them holding a blocking thread until it
+/// was read whole. Others wait for their turn, without being read further meanwhile.
+const MAX_REWRITING_DOCUMENTS: usize = 64;
+
+lazy_static! {
+    /// Matches both `<meta charset="...">` and `<meta http-equiv="Content-Type" content="...">`.
+    static ref META_CHARSET: regex::bytes::Regex =
+        regex::bytes::Regex::new(r#"(?i-u)<meta[^>]*?charset\s*=\s*["']?\s*([a-z0-9_:.\-]+)"#)
+            .unwrap();
+
+    /// Start tags of the elements `ResourceStripper` handles.
+    static ref RESOURCE_TAG: regex::bytes::Regex =
+        regex::bytes::Regex::new(r"(?i-u)<(script|img|iframe|link)\s[^>]*>").unwrap();
+
+    /// Attributes of a start tag, with a double quoted, single quoted or unquoted value.
+    static ref TAG_ATTRIBUTE: regex::bytes::Regex = regex::bytes::Regex::new(
+        r#"(?i-u)\s([a-z-]+)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#
+    )
+    .unwrap();
+
+    static ref REWRITING_PERMITS: Arc<Semaphore> =
+        Arc::new(Semaphore::new(MAX_REWRITING_DOCUMENTS));
+}
+
+/// Chunks held between upstream and the rewriter, and between the rewriter and the client.
+/// Senders wait once the capacity is reached, so slow clients slow down reading upstream.
+pub(crate) const REWRITER_CHANNEL_CAPACITY: usize = 16;
 
 type InternalBodyChannel = (
-    mpsc::UnboundedSender<(Bytes, Option<AdblockProperties>)>,
-    mpsc::UnboundedReceiver<(Bytes, Option<AdblockProperties>)>,
+    mpsc::Sender<(BufferedChunk, Option<AdblockProperties>)>,
+    mpsc::Receiver<(BufferedChunk, Option<AdblockProperties>)>,
 );
 
+/// Chunk of a document being rewritten, counted in the rewriter buffered bytes until dropped.
+pub(crate) struct BufferedChunk {
+    bytes: Bytes,
+    statistics: Statistics,
+}
+
+impl BufferedChunk {
+    pub(crate) fn new(bytes: Bytes, statistics: &Statistics) -> Self {
+        statistics.add_rewriter_buffered_bytes(bytes.len() as u64);
+
+        Self {
+            bytes,
+            statistics: statistics.clone(),
+        }
+    }
+}
+
+impl Drop for BufferedChunk {
+    fn drop(&mut self) {
+        self.statistics
+            .remove_rewriter_buffered_bytes(self.bytes.len() as u64);
+    }
+}
+
 struct AdblockProperties {
     url: String,
     ids: HashSet<String>,
     classes: HashSet<String>,
+    /// Number of elements removed, as the resource they load is blocked or by body rewrite
+    /// rules.
+    stripped_elements: usize,
+    /// User content to append to the document.
+    user_content: String,
+    /// Whether user content was injected at the start of the document.
+    user_content_injected: bool,
+    /// Encoding of the document, which the appended content is written in.
+    encoding: &'static Encoding,
+    /// Whether cosmetic filters are appended along with user content.
+    is_blocking_enabled: bool,
+}
+
+/// Removes elements loading resources that are blocked, sparing the browser requests
+/// that would be blocked anyway and leaving no broken placeholders behind.
+#[derive(Clone)]
+struct ResourceStripper {
+    page_url: Option<url::Url>,
+    adblock_requester: AdblockRequester,
+    runtime: tokio::runtime::Handle,
+    statistics: Statistics,
+    stripped_elements: Arc<AtomicUsize>,
+    /// Decisions about the resources of the document, by URL and request type.
+    decisions: Arc<Mutex<HashMap<(String, &'static str), NetworkBlockerResult>>>,
+}
+
+impl ResourceStripper {
+    fn resolve(&self, resource_url: &str) -> Option<url::Url> {
+        let resource_url = self.page_url.as_ref()?.join(resource_url.trim()).ok()?;
+
+        matches!(resource_url.scheme(), "http" | "https").then_some(resource_url)
+    }
+
+    /// Checks the resources loaded by the elements of `document` at once, ahead of them
+    /// being handled.
+    fn prefetch(&self, document: &[u8]) {
+        let page_url = match &self.page_url {
+            Some(page_url) => page_url,
+            None => return,
+        };
+
+        let network_urls = {
+            let decisions = self.decisions.lock().unwrap();
+
+            find_resources(document)
+                .into_iter()
+                .filter_map(|(resource_url, request_type)| {
+                    Some((self.resolve(&resource_url)?.to_string(), request_type))
+                })
+                .filter(|resource| !decisions.contains_key(resource))
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        if network_urls.is_empty() {
+            return;
+        }
+
+        // Handlers run on a blocking thread, while the page is being streamed.
+        let blocker_results = self.runtime.block_on(
+            self.adblock_requester
+                .are_network_urls_blocked(network_urls.clone(), page_url.to_string()),
+        );
+
+        self.decisions
+            .lock()
+            .unwrap()
+            .extend(network_urls.into_iter().zip(blocker_results));
+    }
+
+    fn strip_if_blocked(&self, element: &mut Element, attribute: &str, request_type: &'static str) {
+        let page_url = match &self.page_url {
+            Some(page_url) => page_url,
+            None => return,
+        };
+
+        let resource_url = match element
+            .get_attribute(attribute)
+            .and_then(|resource_url| self.resolve(&resource_url))
+        {
+            Some(resource_url) => resource_url,
+            None => return,
+        };
+
+        let prefetched_result = self
+            .decisions
+            .lock()
+            .unwrap()
+            .get(&(resource_url.to_string(), request_type))
+            .cloned();
+
+        // Resources missed when prefetching, such as those with character references in
+        // their URL, are checked on their own.
+        let blocker_result = match prefetched_result {
+            Some(blocker_result) => blocker_result,
+            None => {
+                self.runtime
+                    .block_on(self.adblock_requester.is_network_url_blocked(
+                        resource_url.to_string(),
+                        page_url.to_string(),
+                        request_type,
+                    ))
+                    .1
+            }
+        };
+
+        // Resources blocked with a redirect are replaced by stubs pages may rely on.
+        if !blocker_result.matched || blocker_result.redirect.is_some() {
+            return;
+        }
+
+        log::debug!(
+            "Stripped <{}> loading blocked resource: {}",
+            element.tag_name(),
+            resource_url
+        );
+
+        element.remove();
+
+        self.statistics.increment_blocked_requests();
+        if let Some(filter_list) = &blocker_result.filter_list {
+            self.statistics
+                .increment_top_blocking_filter_lists(filter_list);
+        }
+        self.statistics.increment_top_blocked_paths(format!(
+            "{}://{}{}",
+            resource_url.scheme(),
+            resource_url.host_str().unwrap_or_default(),
+            resource_url.path()
+        ));
+        self.stripped_elements.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Request type of the resource a `<link>` preloads, as given by its `as` attribute.
+fn link_request_type(as_attribute: Option<&str>) -> &'static str {
+    match as_attribute {
+        Some("document") => "sub_frame",
+        Some("fetch") => "xhr",
+        Some("font") => "font",
+        Some("image") => "image",
+        Some("script") => "script",
+        Some("style") => "stylesheet",
+        _ => NETWORK_REQUEST_TYPE,
+    }
+}
+
+/// URLs of the resources loaded by the elements `ResourceStripper` handles, as they appear in
+/// `document`, along with their request type.
+fn find_resources(document: &[u8]) -> Vec<(String, &'static str)> {
+    RESOURCE_TAG
+        .captures_iter(document)
+        .filter_map(|tag| {
+            let attributes = TAG_ATTRIBUTE
+                .captures_iter(&tag[0])
+                .filter_map(|attribute| {
+                    let value = attribute
+                        .get(2)
+                        .or_else(|| attribute.get(3))
+                        .or_else(|| attribute.get(4))?;
+
+                    Some((
+                        String::from_utf8_lossy(&attribute[1]).to_ascii_lowercase(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    ))
+                })
+                .collect::<HashMap<_, _>>();
+
+            let (attribute, request_type) = match tag[1].to_ascii_lowercase().as_slice() {
+                b"script" => ("src", "script"),
+                b"img" => ("src", "image"),
+                b"iframe" => ("src", "sub_frame"),
+                _ => {
+                    let is_preloaded = attributes.get("rel").is_some_and(|rel| {
+                        rel.split_ascii_whitespace()
+                            .any(|rel| matches!(rel, "preload" | "prefetch" | "preconnect"))
+                    });
+
+                    if !is_preloaded {
+                        return None;
+                    }
+
+                    (
+                        "href",
+                        link_request_type(attributes.get("as").map(String::as_str)),
+                    )
+                }
+            };
+
+            Some((attributes.get(attribute)?.clone(), request_type))
+        })
+        .collect()
+}
+
+/// Start of a tag cut by the end of `document`.
+fn get_cut_tag(document: &[u8]) -> &[u8] {
+    match document.iter().rposition(|byte| *byte == b'<') {
+        Some(position)
+            if !document[position..].contains(&b'>')
+                && document.len() - position <= MAX_CUT_TAG_LENGTH =>
+        {
+            &document[position..]
+        }
+        _ => &[],
+    }
+}
+
+/// Encoding of the `charset` parameter of a `Content-Type` header value.
+fn content_type_encoding(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .find_map(|parameter| {
+            let (name, value) = parameter.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| value.trim().trim_matches('"'))
+        })
+        .and_then(|charset| Encoding::for_label_no_replacement(charset.as_bytes()))
+}
+
+/// Encoding of a document from its byte order mark, its `Content-Type` charset or an early
+/// `<meta charset>`, in that order of precedence. Defaults to UTF-8.
+fn sniff_encoding(
+    document_start: &[u8],
+    transport_encoding: Option<&'static Encoding>,
+) -> &'static Encoding {
+    if let Some((encoding, _bom_length)) = Encoding::for_bom(document_start) {
+        return encoding;
+    }
+
+    if let Some(transport_encoding) = transport_encoding {
+        return transport_encoding;
+    }
+
+    let prescanned = &document_start[..document_start.len().min(ENCODING_PRESCAN_LENGTH)];
+
+    META_CHARSET
+        .captures(prescanned)
+        .and_then(|captures| Encoding::for_label(&captures[1]))
+        // A document declaring itself as UTF-16 was decoded as ASCII to find the declaration.
+        .map(Encoding::output_encoding)
+        .unwrap_or(encoding_rs::UTF_8)
 }
 
 pub struct Rewriter {
     url: String,
     adblock_requester: AdblockRequester,
-    receiver: Receiver<Bytes>,
+    receiver: mpsc::Receiver<BufferedChunk>,
     body_sender: hyper::body::Sender,
     statistics: Statistics,
+    /// Only user content is injected when blocking doesn't apply to the document, neither
+    /// cosmetic filters nor the stripping of blocked resources.
+    is_blocking_enabled: bool,
+    user_content: MatchedUserContent,
+    /// Selectors of the elements body rewrite rules remove.
+    removed_selectors: Vec<String>,
+    /// Encoding given by the `Content-Type` charset.
+    transport_encoding: Option<&'static Encoding>,
+    /// Size above which the rest of the document is passed through untouched.
+    max_document_size: u64,
     internal_body_channel: InternalBodyChannel,
 }
 
 impl Rewriter {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         url: String,
         adblock_requester: AdblockRequester,
-        receiver: Receiver<Bytes>,
+        receiver: mpsc::Receiver<BufferedChunk>,
         body_sender: hyper::body::Sender,
         statistics: Statistics,
+        is_blocking_enabled: bool,
+        user_content: MatchedUserContent,
+        removed_selectors: Vec<String>,
+        content_type: &str,
+        max_document_size: u64,
     ) -> Self {
         Self {
             url,
             body_sender,
             statistics,
+            is_blocking_enabled,
+            user_content,
+            removed_selectors,
+            transport_encoding: content_type_encoding(content_type),
+            max_document_size,
             adblock_requester,
             receiver,
-            internal_body_channel: mpsc::unbounded_channel(),
+            internal_body_channel: mpsc::channel(REWRITER_CHANNEL_CAPACITY),
         }
     }
 
-    pub(crate) fn rewrite(self) {
+    /// Rewrites the document on a blocking thread, once there are less than
+    /// `MAX_REWRITING_DOCUMENTS` others being rewritten.
+    pub(crate) async fn rewrite(self) {
+        let statistics = self.statistics.clone();
+
+        let permit = Arc::clone(&REWRITING_PERMITS)
+            .acquire_owned()
+            .await
+            .unwrap();
+
+        statistics.increment_rewriting_documents();
+        let _result = tokio::task::spawn_blocking(move || {
+            self.rewrite_document();
+            drop(permit);
+        })
+        .await;
+        statistics.decrement_rewriting_documents();
+    }
+
+    fn rewrite_document(mut self) {
         let (internal_body_sender, internal_body_receiver) = self.internal_body_channel;
         let body_sender = self.body_sender;
         let adblock_requester = self.adblock_requester.clone();
         let statistics = self.statistics.clone();
 
-        let internal_body_sender = Arc::new(Mutex::new(internal_body_sender));
-
         let classes = Arc::new(Mutex::new(HashSet::new()));
         let ids = Arc::new(Mutex::new(HashSet::new()));
 
@@ -64,121 +414,292 @@ impl Rewriter {
             statistics,
         ));
 
+        // The encoding may only be declared by the document itself, whose start is buffered
+        // until it is known.
+        let mut prescanned_chunks = Vec::new();
+        let mut prescanned_length = 0;
+        while prescanned_length < ENCODING_PRESCAN_LENGTH {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    prescanned_length += chunk.bytes.len();
+                    prescanned_chunks.push(chunk);
+                }
+                None => break,
+            }
+        }
+
+        let document_start = prescanned_chunks
+            .iter()
+            .flat_map(|chunk| chunk.bytes.iter().copied())
+            .take(ENCODING_PRESCAN_LENGTH)
+            .collect::<Vec<_>>();
+
+        let encoding = sniff_encoding(&document_start, self.transport_encoding);
+        let ascii_compatible_encoding = match AsciiCompatibleEncoding::new(encoding) {
+            Some(ascii_compatible_encoding) => ascii_compatible_encoding,
+            None => {
+                // lol_html can't parse UTF-16 documents, they are passed through untouched.
+                log::debug!("Not rewriting {} encoded in {}", self.url, encoding.name());
+
+                Self::pass_through(prescanned_chunks, self.receiver, internal_body_sender);
+                return;
+            }
+        };
+
+        let stripped_elements = Arc::new(AtomicUsize::new(0));
+        let resource_stripper = ResourceStripper {
+            page_url: url::Url::parse(&self.url).ok(),
+            adblock_requester: self.adblock_requester.clone(),
+            runtime: tokio::runtime::Handle::current(),
+            statistics: self.statistics.clone(),
+            stripped_elements: Arc::clone(&stripped_elements),
+            decisions: Arc::default(),
+        };
+        let resource_prefetcher = resource_stripper.clone();
+        let (script_stripper, image_stripper, iframe_stripper, link_stripper) = (
+            resource_stripper.clone(),
+            resource_stripper.clone(),
+            resource_stripper.clone(),
+            resource_stripper,
+        );
+
+        // Injected in the first of `<head>` and `<body>`, appended to the document when
+        // it has neither.
+        let MatchedUserContent {
+            document_start,
+            mut document_end,
+        } = self.user_content;
+        let user_content_injected = !document_start.is_empty();
+        let pending_document_start = Arc::new(Mutex::new(
+            (!document_start.is_empty()).then_some(document_start),
+        ));
+        let pending_document_start_clone = Arc::clone(&pending_document_start);
+
         let re = Regex::new(r"\s+").unwrap();
         let classes_clone = Arc::clone(&classes);
         let ids_clone = Arc::clone(&ids);
-        let internal_body_sender_clone = Arc::clone(&internal_body_sender);
+        let output_sender = internal_body_sender.clone();
+        let output_statistics = self.statistics.clone();
+
+        let mut element_content_handlers = vec![
+            element!("head, body", move |element| {
+                if let Some(document_start) = pending_document_start_clone.lock().unwrap().take() {
+                    element.prepend(&document_start, ContentType::Html);
+                }
+                Ok(())
+            }),
+            element!("html, body", |element| {
+                if let Some(handlers) = element.end_tag_handlers() {
+                    handlers.push(Box::new(move |end| {
+                        end.remove();
+                        Ok(())
+                    }))
+                }
+                Ok(())
+            }),
+        ];
+
+        let cosmetic_handlers = [
+            element!("*", move |element| {
+                if let Some(id) = element.get_attribute("id") {
+                    ids_clone.lock().unwrap().insert(id);
+                }
+                Ok(())
+            }),
+            element!("*", move |element| {
+                if let Some(class) = element.get_attribute("class") {
+                    let classes_without_duplicate_spaces = re.replace_all(&class, " ");
+                    let class_set: HashSet<_> = classes_without_duplicate_spaces
+                        .split_whitespace()
+                        .map(String::from)
+                        .collect();
+                    classes_clone.lock().unwrap().extend(class_set);
+                }
+                Ok(())
+            }),
+            element!("script[src]", move |element| {
+                script_stripper.strip_if_blocked(element, "src", "script");
+                Ok(())
+            }),
+            element!("img[src]", move |element| {
+                image_stripper.strip_if_blocked(element, "src", "image");
+                Ok(())
+            }),
+            element!("iframe[src]", move |element| {
+                iframe_stripper.strip_if_blocked(element, "src", "sub_frame");
+                Ok(())
+            }),
+            element!(
+                "link[href][rel~=preload], link[href][rel~=prefetch], link[href][rel~=preconnect]",
+                move |element| {
+                    let request_type = link_request_type(element.get_attribute("as").as_deref());
+                    link_stripper.strip_if_blocked(element, "href", request_type);
+                    Ok(())
+                }
+            ),
+        ];
+
+        if self.is_blocking_enabled {
+            element_content_handlers.extend(cosmetic_handlers);
+        }
+
+        element_content_handlers.extend(self.removed_selectors.iter().map(|selector| {
+            let removed_elements = Arc::clone(&stripped_elements);
+
+            element!(selector, move |element| {
+                element.remove();
+                removed_elements.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            })
+        }));
 
         let mut rewriter = HtmlRewriter::new(
             Settings {
-                element_content_handlers: vec![
-                    element!("*", move |element| {
-                        if let Some(id) = element.get_attribute("id") {
-                            ids_clone.lock().unwrap().insert(id);
-                        }
-                        Ok(())
-                    }),
-                    element!("*", move |element| {
-                        if let Some(class) = element.get_attribute("class") {
-                            let classes_without_duplicate_spaces = re.replace_all(&class, " ");
-                            let class_set: HashSet<_> = classes_without_duplicate_spaces
-                                .split_whitespace()
-                                .map(String::from)
-                                .collect();
-                            classes_clone.lock().unwrap().extend(class_set);
-                        }
-                        Ok(())
-                    }),
-                    element!("html, body", |element| {
-                        if let Some(handlers) = element.end_tag_handlers() {
-                            handlers.push(Box::new(move |end| {
-                                end.remove();
-                                Ok(())
-                            }))
-                        }
-                        Ok(())
-                    }),
-                ],
+                element_content_handlers,
+                encoding: ascii_compatible_encoding,
                 ..Settings::default()
             },
             move |c: &[u8]| {
-                let _ = internal_body_sender_clone
-                    .lock()
-                    .unwrap()
-                    .send((Bytes::copy_from_slice(c), None));
+                let chunk = BufferedChunk::new(Bytes::copy_from_slice(c), &output_statistics);
+                let _ = output_sender.blocking_send((chunk, None));
             },
         );
 
-        for message in self.receiver {
-            rewriter.write(&message).unwrap();
+        let mut chunks = prescanned_chunks.into_iter();
+        let mut document_size = 0;
+        let mut oversized_chunks = None;
+        let mut rewriting_error = None;
+        let mut unscanned = Vec::new();
+
+        while let Some(chunk) = chunks.next().or_else(|| self.receiver.blocking_recv()) {
+            document_size += chunk.bytes.len() as u64;
+
+            if document_size > self.max_document_size {
+                oversized_chunks = Some(std::iter::once(chunk).chain(chunks).collect());
+                break;
+            }
+
+            if self.is_blocking_enabled {
+                unscanned.extend_from_slice(&chunk.bytes);
+                resource_prefetcher.prefetch(&unscanned);
+                unscanned = get_cut_tag(&unscanned).to_vec();
+            }
+
+            if let Err(err) = rewriter.write(&chunk.bytes) {
+                rewriting_error = Some((err, std::iter::once(chunk).chain(chunks).collect()));
+                break;
+            }
+        }
+
+        // Handlers, such as those of `html_remove` rules, may fail on documents lol_html can't
+        // make sense of. The rewriter can't be used anymore, the rest of the document is sent
+        // as is from the chunk it failed on.
+        if let Some((err, remaining_chunks)) = rewriting_error {
+            log::error!("Unable to rewrite {}, passing it through: {err}", self.url);
+
+            Self::pass_through(remaining_chunks, self.receiver, internal_body_sender);
+            return;
         }
-        rewriter.end().unwrap();
 
-        let _ = internal_body_sender.lock().unwrap().send((
-            Bytes::new(),
+        if let Err(err) = rewriter.end() {
+            log::error!("Unable to finish rewriting {}: {err}", self.url);
+            return;
+        }
+
+        // The size of documents may not be known until they are read, rewriting stops once
+        // they turn out to be too large and nothing is injected.
+        if let Some(oversized_chunks) = oversized_chunks {
+            log::debug!(
+                "Not rewriting the rest of {}, larger than {} bytes",
+                self.url,
+                self.max_document_size
+            );
+
+            self.statistics.increment_oversized_documents();
+            if stripped_elements.load(Ordering::Relaxed) > 0 {
+                self.statistics.increment_modified_responses();
+            }
+
+            Self::pass_through(oversized_chunks, self.receiver, internal_body_sender);
+            return;
+        }
+
+        if let Some(document_start) = pending_document_start.lock().unwrap().take() {
+            document_end.insert_str(0, &document_start);
+        }
+
+        let _ = internal_body_sender.blocking_send((
+            BufferedChunk::new(Bytes::new(), &self.statistics),
             Some(AdblockProperties {
                 ids: ids.lock().unwrap().clone(),
                 classes: classes.lock().unwrap().clone(),
                 url: self.url,
+                stripped_elements: stripped_elements.load(Ordering::Relaxed),
+                user_content: document_end,
+                user_content_injected,
+                encoding,
+                is_blocking_enabled: self.is_blocking_enabled,
             }),
         ));
     }
 
+    /// Forwards the rest of a document untouched, starting with `pending_chunks`.
+    fn pass_through(
+        pending_chunks: Vec<BufferedChunk>,
+        mut receiver: mpsc::Receiver<BufferedChunk>,
+        internal_body_sender: mpsc::Sender<(BufferedChunk, Option<AdblockProperties>)>,
+    ) {
+        let mut pending_chunks = pending_chunks.into_iter();
+
+        while let Some(current_chunk) = pending_chunks.next().or_else(|| receiver.blocking_recv()) {
+            if internal_body_sender
+                .blocking_send((current_chunk, None))
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
     async fn write_body(
-        mut receiver: mpsc::UnboundedReceiver<(Bytes, Option<AdblockProperties>)>,
+        mut receiver: mpsc::Receiver<(BufferedChunk, Option<AdblockProperties>)>,
         mut body_sender: hyper::body::Sender,
         adblock_requester: AdblockRequester,
         statistics: Statistics,
     ) {
-        while let Some((bytes, adblock_properties)) = receiver.recv().await {
-            if let Err(_err) = body_sender.send_data(bytes).await {
+        // Chunks are only dropped once handed over to the client connection.
+        while let Some((chunk, adblock_properties)) = receiver.recv().await {
+            if let Err(_err) = body_sender.send_data(chunk.bytes.clone()).await {
                 break;
             }
+            drop(chunk);
+
             if let Some(adblock_properties) = adblock_properties {
-                let mut response_has_been_modified = false;
+                let mut response_has_been_modified = adblock_properties.stripped_elements > 0
+                    || adblock_properties.user_content_injected;
+
+                let mut to_append_to_response = String::new();
 
-                let blocker_result = adblock_requester
-                    .get_cosmetic_response(
+                if adblock_properties.is_blocking_enabled {
+                    let (cosmetic_content, has_cosmetic_filters) = Self::get_cosmetic_content(
+                        &adblock_requester,
                         adblock_properties.url,
                         adblock_properties.ids.into_iter().collect(),
                         adblock_properties.classes.into_iter().collect(),
                     )
                     .await;
 
-                let hidden_selectors: String = blocker_result
-                    .hidden_selectors
-                    .into_iter()
-                    .map(|selector| format!("{} {{ display: none !important; }}", selector))
-                    .collect();
-
-                let style_selectors: String = blocker_result
-                    .style_selectors
-                    .into_iter()
-                    .map(|(selector, content)| {
-                        response_has_been_modified = true;
-                        format!("{} {{ {} }}", selector, content.join(";"))
-                    })
-                    .collect();
-
-                let mut to_append_to_response = format!(
-                    r#"
-<!-- privaxy proxy -->
-<style>{hidden_selectors}
-{style_selectors}
-</style>
-<!-- privaxy proxy -->"#
-                );
+                    to_append_to_response.push_str(&cosmetic_content);
+                    response_has_been_modified |= has_cosmetic_filters;
+                }
 
-                if let Some(injected_script) = blocker_result.injected_script {
+                if !adblock_properties.user_content.is_empty() {
                     response_has_been_modified = true;
+
                     write!(
                         to_append_to_response,
-                        r#"
-<!-- Privaxy proxy -->
-<script type="application/javascript">{}</script>
-<!-- privaxy proxy -->
-"#,
-                        injected_script
+                        "\n<!-- Privaxy proxy -->{}<!-- privaxy proxy -->\n",
+                        adblock_properties.user_content
                     )
                     .unwrap();
                 }
@@ -187,7 +708,10 @@ impl Rewriter {
                     statistics.increment_modified_responses();
                 }
 
-                let bytes = Bytes::copy_from_slice(to_append_to_response.as_bytes());
+                // Characters the encoding lacks are written as numeric character references.
+                let (encoded, _encoding, _had_unmappable_characters) =
+                    adblock_properties.encoding.encode(&to_append_to_response);
+                let bytes = Bytes::copy_from_slice(&encoded);
 
                 if let Err(_err) = body_sender.send_data(bytes).await {
                     break;
@@ -195,4 +719,99 @@ impl Rewriter {
             }
         }
     }
+
+    /// Cosmetic filters for the document at `url` given the ids and classes found in it, along
+    /// with whether any of them change the document besides hiding elements.
+    async fn get_cosmetic_content(
+        adblock_requester: &AdblockRequester,
+        url: String,
+        ids: Vec<String>,
+        classes: Vec<String>,
+    ) -> (String, bool) {
+        let blocker_result = adblock_requester
+            .get_cosmetic_response(url, ids.clone(), classes.clone())
+            .await;
+        let mut has_cosmetic_filters = false;
+
+        let hidden_selectors: String = blocker_result
+            .hidden_selectors
+            .into_iter()
+            .map(|selector| format!("{} {{ display: none !important; }}", selector))
+            .collect();
+
+        let style_selectors: String = blocker_result
+            .style_selectors
+            .into_iter()
+            .map(|(selector, content)| {
+                has_cosmetic_filters = true;
+                format!("{} {{ {} }}", selector, content.join(";"))
+            })
+            .collect();
+
+        let mut to_append_to_response = format!(
+            r#"
+<!-- privaxy proxy -->
+<style>{hidden_selectors}
+{style_selectors}
+</style>
+<!-- privaxy proxy -->"#
+        );
+
+        if let Some(injected_script) = blocker_result.injected_script {
+            has_cosmetic_filters = true;
+            write!(
+                to_append_to_response,
+                r#"
+<!-- Privaxy proxy -->
+<script type="application/javascript">{}</script>
+<!-- privaxy proxy -->
+"#,
+                injected_script
+            )
+            .unwrap();
+        }
+
+        if !blocker_result.procedural_rules.is_empty() {
+            has_cosmetic_filters = true;
+
+            // Rules could otherwise close the script element early.
+            let procedural_rules = serde_json::to_string(&blocker_result.procedural_rules)
+                .unwrap()
+                .replace("</", "<\\/");
+
+            write!(
+                to_append_to_response,
+                r#"
+<!-- Privaxy proxy -->
+<script type="application/javascript">{PROCEDURAL_RUNTIME}({procedural_rules});</script>
+<!-- privaxy proxy -->
+"#
+            )
+            .unwrap();
+        }
+
+        // Elements added later on are filtered by the page itself, which only needs to ask
+        // for the classes and ids that were not seen yet.
+        if !blocker_result.generichide {
+            let options = serde_json::json!({
+                "endpoint": GENERIC_COSMETIC_PATH,
+                "classes": classes,
+                "ids": ids,
+            })
+            .to_string()
+            .replace("</", "<\\/");
+
+            write!(
+                to_append_to_response,
+                r#"
+<!-- Privaxy proxy -->
+<script type="application/javascript">{GENERIC_COSMETIC_RUNTIME}({options});</script>
+<!-- privaxy proxy -->
+"#
+            )
+            .unwrap();
+        }
+
+        (to_append_to_response, has_cosmetic_filters)
+    }
 }
@@ -1,4 +1,7 @@
-use super::{exclusions::LocalExclusionStore, serve::serve};
+use super::body_rewrite::BodyRewriteStore;
+use super::header_policy::HeaderPolicyStore;
+use super::url_cleaning::UrlCleaner;
+use super::{exclusions::LocalExclusionStore, serve::serve, user_content::UserContentStore};
 use crate::{blocker::AdblockRequester, cert::CertCache, statistics::Statistics, Event};
 use http::uri::{Authority, Scheme};
 use hyper::{
@@ -21,6 +24,11 @@ pub(crate) async fn serve_mitm_session(
     statistics: Statistics,
     client_ip_address: IpAddr,
     local_exclusion_store: LocalExclusionStore,
+    user_content_store: UserContentStore,
+    body_rewrite_store: BodyRewriteStore,
+    url_cleaner: UrlCleaner,
+    header_policy_store: HeaderPolicyStore,
+    max_rewritten_document_size: u64,
 ) -> Result<Response<Body>, hyper::Error> {
     let authority = match req.uri().authority().cloned() {
         Some(authority) => authority,
@@ -44,19 +52,26 @@ pub(crate) async fn serve_mitm_session(
         //
         // When HTTP method is CONNECT we should return an empty body
         // then we can eventually upgrade the connection and talk a new protocol.
-        let server_configuration =
-            Arc::new(cert_cache.get(authority.clone()).await.server_configuration);
+        let server_configuration = cert_cache
+            .get(authority.clone())
+            .await
+            .map(|certificate| Arc::new(certificate.server_configuration));
 
         tokio::task::spawn(async move {
             match hyper::upgrade::on(req).await {
                 Ok(mut upgraded) => {
                     let is_host_blacklisted = local_exclusion_store.contains(authority.host());
 
-                    if is_host_blacklisted {
-                        let _result = tunnel(&mut upgraded, &authority).await;
+                    // Hosts outside of the CA name constraints are tunneled as well,
+                    // we are not allowed to mint certificates for them.
+                    let server_configuration = match server_configuration {
+                        Some(server_configuration) if !is_host_blacklisted => server_configuration,
+                        _ => {
+                            let _result = tunnel(&mut upgraded, &authority).await;
 
-                        return;
-                    }
+                            return;
+                        }
+                    };
 
                     let http = Http::new();
 
@@ -79,6 +94,11 @@ pub(crate) async fn serve_mitm_session(
                                             broadcast_tx.clone(),
                                             statistics.clone(),
                                             client_ip_address,
+                                            user_content_store.clone(),
+                                            body_rewrite_store.clone(),
+                                            url_cleaner.clone(),
+                                            header_policy_store.clone(),
+                                            max_rewritten_document_size,
                                         )
                                     }),
                                 )
@@ -114,6 +134,11 @@ pub(crate) async fn serve_mitm_session(
             broadcast_tx,
             statistics,
             client_ip_address,
+            user_content_store,
+            body_rewrite_store,
+            url_cleaner,
+            header_policy_store,
+            max_rewritten_document_size,
         )
         .await
     }
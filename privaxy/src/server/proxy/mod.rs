@@ -1,5 +1,11 @@
 pub(crate) mod mitm;
 pub(crate) mod serve;
 pub(crate) use mitm::serve_mitm_session;
+pub(crate) mod allowlist;
+pub(crate) mod body_rewrite;
 pub(crate) mod exclusions;
+pub(crate) mod generic_cosmetic;
+pub(crate) mod header_policy;
 pub(crate) mod html_rewriter;
+pub(crate) mod url_cleaning;
+pub(crate) mod user_content;
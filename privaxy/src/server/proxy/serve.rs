@@ -1,8 +1,12 @@
-use super::html_rewriter::Rewriter;
-use crate::blocker::AdblockRequester;
+use super::body_rewrite::{BodyRewriteStore, MatchedBodyRewriteRules};
+use super::generic_cosmetic::{serve_generic_cosmetic, GENERIC_COSMETIC_PATH};
+use super::header_policy::HeaderPolicyStore;
+use super::html_rewriter::{BufferedChunk, Rewriter, REWRITER_CHANNEL_CAPACITY};
+use super::url_cleaning::{CleanedUrl, UrlCleaner};
+use super::user_content::UserContentStore;
+use crate::blocker::{AdblockRequester, NetworkBlockerResult, NETWORK_REQUEST_TYPE};
 use crate::statistics::Statistics;
 use crate::web_gui::events::Event;
-use adblock::blocker::BlockerResult;
 use http::uri::{Authority, Scheme};
 use http::{StatusCode, Uri};
 use hyper::body::Bytes;
@@ -23,10 +27,15 @@ pub(crate) async fn serve(
     broadcast_sender: broadcast::Sender<Event>,
     statistics: Statistics,
     client_ip_address: IpAddr,
+    user_content_store: UserContentStore,
+    body_rewrite_store: BodyRewriteStore,
+    url_cleaner: UrlCleaner,
+    header_policy_store: HeaderPolicyStore,
+    max_rewritten_document_size: u64,
 ) -> Result<Response<Body>, hyper::Error> {
     let scheme_string = scheme.to_string();
 
-    let uri = match http::uri::Builder::new()
+    let mut uri = match http::uri::Builder::new()
         .scheme(scheme)
         .authority(authority)
         .path_and_query(match request.uri().path_and_query() {
@@ -41,6 +50,10 @@ pub(crate) async fn serve(
         }
     };
 
+    if uri.path() == GENERIC_COSMETIC_PATH {
+        return Ok(serve_generic_cosmetic(adblock_requester, request, client_ip_address).await);
+    }
+
     if request.headers().contains_key(http::header::UPGRADE) {
         return Ok(perform_two_ends_upgrade(request, uri, hyper_client).await);
     }
@@ -48,35 +61,87 @@ pub(crate) async fn serve(
     let (mut parts, body) = request.into_parts();
     parts.uri = uri.clone();
 
-    let (sender, new_body) = Body::channel();
+    let (mut sender, new_body) = Body::channel();
 
-    let req = Request::from_parts(parts, body);
+    let mut req = Request::from_parts(parts, body);
 
     log::debug!("{} {}", req.method(), req.uri());
 
     statistics.increment_top_clients(client_ip_address);
 
-    let (is_request_blocked, blocker_result) = adblock_requester
-        .is_network_url_blocked(
-            uri.to_string(),
-            match req.headers().get(http::header::REFERER) {
-                Some(referer) => referer.to_str().unwrap().to_string(),
-                // When no referer, we default to `uri` as we otherwise may get many false
-                // positives due to the blocker thinking it's third party requests.
-                None => uri.to_string(),
-            },
-        )
-        .await;
+    let is_blocking_enabled = adblock_requester
+        .is_blocking_enabled_for(client_ip_address, &get_site_host(req.headers(), &uri));
+
+    // Cleaned before the blocker check, requests only blocked for their tracking parameters
+    // then go through without them.
+    if is_blocking_enabled {
+        match url_cleaner.clean(&uri.to_string()) {
+            Some(CleanedUrl::Unwrapped { url, rule }) if req.method() == http::Method::GET => {
+                if let Ok(location) = http::HeaderValue::from_str(&url) {
+                    log::debug!("Unwrapped {uri} to {url}");
+
+                    statistics.increment_cleaned_urls();
+                    statistics.increment_top_url_cleaning_rules(&rule);
+
+                    let mut response = get_empty_response(http::StatusCode::FOUND);
+                    response
+                        .headers_mut()
+                        .insert(http::header::LOCATION, location);
+
+                    return Ok(response);
+                }
+            }
+            Some(CleanedUrl::Stripped { url, rules }) => {
+                if let Ok(cleaned_uri) = url.parse::<Uri>() {
+                    log::debug!("Stripped {uri} to {cleaned_uri}");
+
+                    statistics.increment_cleaned_urls();
+                    for rule in &rules {
+                        statistics.increment_top_url_cleaning_rules(rule);
+                    }
+
+                    uri = cleaned_uri;
+                    *req.uri_mut() = uri.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (is_request_blocked, blocker_result) = if is_blocking_enabled {
+        adblock_requester
+            .is_network_url_blocked(
+                uri.to_string(),
+                match req
+                    .headers()
+                    .get(http::header::REFERER)
+                    .and_then(|referer| referer.to_str().ok())
+                {
+                    Some(referer) => referer.to_string(),
+                    // When no referer, we default to `uri` as we otherwise may get many false
+                    // positives due to the blocker thinking it's third party requests.
+                    None => uri.to_string(),
+                },
+                NETWORK_REQUEST_TYPE,
+            )
+            .await
+    } else {
+        (false, NetworkBlockerResult::default())
+    };
 
     let _result = broadcast_sender.send(Event {
         now: chrono::Utc::now(),
         method: req.method().to_string(),
         url: req.uri().to_string(),
         is_request_blocked,
+        filter_list: blocker_result.filter_list.clone(),
     });
 
     if is_request_blocked {
         statistics.increment_blocked_requests();
+        if let Some(filter_list) = &blocker_result.filter_list {
+            statistics.increment_top_blocking_filter_lists(filter_list);
+        }
         statistics.increment_top_blocked_paths(format!(
             "{}://{}{}",
             scheme_string,
@@ -91,10 +156,15 @@ pub(crate) async fn serve(
 
     let mut new_response = Response::new(new_body);
 
+    // Header policies, like user content, apply to requests blocking doesn't apply to.
+    let header_policies = header_policy_store.get_matching(uri.host().unwrap_or_default());
+
     let mut request_headers = req.headers().clone();
     request_headers.remove(http::header::CONNECTION);
     request_headers.remove(http::header::HOST);
 
+    header_policies.apply_to_request(&mut request_headers, &uri, &statistics);
+
     // zstd is causing issues
     if let Some(accept_encoding) = request_headers.get(http::header::ACCEPT_ENCODING) {
         if let Ok(encoding_str) = accept_encoding.to_str() {
@@ -136,46 +206,203 @@ pub(crate) async fn serve(
 
     *new_response.headers_mut() = response.headers().clone();
 
+    header_policies.apply_to_response(new_response.headers_mut(), &statistics);
+
     let (mut parts, new_new_body) = new_response.into_parts();
     parts.status = response.status();
 
-    let new_response = Response::from_parts(parts, new_new_body);
+    let mut new_response = Response::from_parts(parts, new_new_body);
 
-    if let Some(content_type) = response.headers().get(http::header::CONTENT_TYPE) {
-        if let Ok(value) = content_type.to_str() {
-            if value.contains("text/html") {
-                let (sender_rewriter, receiver_rewriter) = crossbeam_channel::unbounded::<Bytes>();
+    let content_type = response
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
 
-                let rewriter = Rewriter::new(
-                    uri.to_string(),
-                    adblock_requester,
-                    receiver_rewriter,
-                    sender,
-                    statistics,
-                );
+    let user_content = user_content_store.get_matching(&uri.to_string());
 
-                tokio::task::spawn_blocking(|| rewriter.rewrite());
+    // Body rewrite rules, like user content, apply to pages blocking doesn't apply to.
+    let body_rewrite_rules = body_rewrite_store.get_matching(&uri.to_string(), &content_type);
+    let is_html_rewritten = content_type.contains("text/html")
+        && (is_blocking_enabled
+            || !user_content.is_empty()
+            || !body_rewrite_rules.html_removals.is_empty());
 
-                while let Ok(Some(chunk)) = response.chunk().await {
-                    if let Err(_err) = sender_rewriter.send(chunk) {
-                        break;
-                    }
-                }
+    if !is_html_rewritten && !body_rewrite_rules.has_transforms() {
+        tokio::spawn(write_proxied_body(response, sender));
 
-                return Ok(new_response);
-            }
-        }
+        return Ok(new_response);
+    }
+
+    let is_oversized = response
+        .content_length()
+        .is_some_and(|content_length| content_length > max_rewritten_document_size);
+
+    if is_oversized {
+        log::debug!("Not rewriting {uri}, larger than {max_rewritten_document_size} bytes");
+        statistics.increment_oversized_documents();
 
         tokio::spawn(write_proxied_body(response, sender));
 
         return Ok(new_response);
     }
 
-    tokio::spawn(write_proxied_body(response, sender));
+    // Rewritten bodies don't have the length announced by the server.
+    new_response
+        .headers_mut()
+        .remove(http::header::CONTENT_LENGTH);
+
+    if !is_html_rewritten {
+        tokio::spawn(async move {
+            let body = match read_transformed_body(
+                &mut response,
+                &body_rewrite_rules,
+                max_rewritten_document_size,
+                &statistics,
+            )
+            .await
+            {
+                Ok(body) => body,
+                Err(err) => {
+                    log::error!("Failed to read the body of {uri}: {err}");
+                    sender.abort();
+                    return;
+                }
+            };
+
+            if sender.send_data(body).await.is_ok() {
+                write_proxied_body(response, sender).await;
+            }
+        });
+
+        return Ok(new_response);
+    }
+
+    // Waits for the rewriter to catch up, rather than reading the whole document
+    // ahead of a slow client.
+    tokio::spawn(async move {
+        // Failing to read a transformed body aborts the response, rather than sending
+        // a truncated document.
+        let transformed_body = if body_rewrite_rules.has_transforms() {
+            match read_transformed_body(
+                &mut response,
+                &body_rewrite_rules,
+                max_rewritten_document_size,
+                &statistics,
+            )
+            .await
+            {
+                Ok(body) => Some(body),
+                Err(err) => {
+                    log::error!("Failed to read the body of {uri}: {err}");
+                    sender.abort();
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+
+        let (sender_rewriter, receiver_rewriter) =
+            tokio::sync::mpsc::channel(REWRITER_CHANNEL_CAPACITY);
+
+        let rewriter = Rewriter::new(
+            uri.to_string(),
+            adblock_requester,
+            receiver_rewriter,
+            sender,
+            statistics.clone(),
+            is_blocking_enabled,
+            user_content,
+            body_rewrite_rules.html_removals.clone(),
+            &content_type,
+            max_rewritten_document_size,
+        );
+
+        tokio::spawn(rewriter.rewrite());
+
+        if let Some(body) = transformed_body {
+            if let Err(_err) = sender_rewriter
+                .send(BufferedChunk::new(body, &statistics))
+                .await
+            {
+                return;
+            }
+        }
+
+        while let Ok(Some(chunk)) = response.chunk().await {
+            let chunk = BufferedChunk::new(chunk, &statistics);
+
+            if let Err(_err) = sender_rewriter.send(chunk).await {
+                break;
+            }
+        }
+    });
 
     Ok(new_response)
 }
 
+/// Reads a whole body to apply the transforms of body rewrite rules to it. Bodies turning out
+/// to be larger than `max_size` are returned untouched as far as they were read, the rest
+/// being left in `response`. Errors reading the body are returned, callers then abort the
+/// response rather than sending it truncated.
+async fn read_transformed_body(
+    response: &mut reqwest::Response,
+    body_rewrite_rules: &MatchedBodyRewriteRules,
+    max_size: u64,
+    statistics: &Statistics,
+) -> Result<Bytes, reqwest::Error> {
+    let mut body = Vec::new();
+
+    while let Some(chunk) = response.chunk().await? {
+        body.extend_from_slice(&chunk);
+
+        if body.len() as u64 > max_size {
+            log::debug!(
+                "Not rewriting {}, larger than {max_size} bytes",
+                response.url()
+            );
+            statistics.increment_oversized_documents();
+
+            return Ok(Bytes::from(body));
+        }
+    }
+
+    match body_rewrite_rules.transform(&body) {
+        Some(transformed_body) => {
+            statistics.increment_modified_responses();
+            Ok(Bytes::from(transformed_body))
+        }
+        None => Ok(Bytes::from(body)),
+    }
+}
+
+/// Host of the page a request is made from: the requested host for page loads,
+/// the host of the `Origin` or `Referer` otherwise.
+fn get_site_host(headers: &http::HeaderMap, uri: &Uri) -> String {
+    let request_host = || uri.host().unwrap_or_default().to_lowercase();
+
+    let is_page_load = headers
+        .get("Sec-Fetch-Dest")
+        .is_some_and(|destination| destination == "document");
+
+    if is_page_load {
+        return request_host();
+    }
+
+    [http::header::ORIGIN, http::header::REFERER]
+        .iter()
+        .filter_map(|header| headers.get(header)?.to_str().ok())
+        .find_map(|value| {
+            url::Url::parse(value)
+                .ok()?
+                .host_str()
+                .map(str::to_lowercase)
+        })
+        .unwrap_or_else(request_host)
+}
+
 fn get_informative_error_response(reason: &str) -> Response<Body> {
     let mut response_body = String::from(include_str!("../../resources/head.html"));
     response_body +=
@@ -187,7 +414,7 @@ fn get_informative_error_response(reason: &str) -> Response<Body> {
     response
 }
 
-fn get_blocked_by_privaxy_response(blocker_result: BlockerResult) -> Response<Body> {
+fn get_blocked_by_privaxy_response(blocker_result: NetworkBlockerResult) -> Response<Body> {
     // We don't redirect to network urls due to security concerns.
     if let Some(resource) = blocker_result.redirect {
         let response = Response::new(Body::from(resource));
@@ -200,9 +427,15 @@ fn get_blocked_by_privaxy_response(blocker_result: BlockerResult) -> Response<Bo
         None => "No information".to_string(),
     };
 
+    let filter_list = match blocker_result.filter_list {
+        Some(filter_list) => filter_list,
+        None => "No information".to_string(),
+    };
+
     let mut response_body = String::from(include_str!("../../resources/head.html"));
     response_body += &include_str!("../../resources/blocked_by_privaxy.html")
-        .replace("#{matching_filter}#", &filter_information);
+        .replace("#{matching_filter}#", &filter_information)
+        .replace("#{filter_list}#", &filter_list);
 
     let mut response = Response::new(Body::from(response_body));
     *response.status_mut() = http::StatusCode::FORBIDDEN;
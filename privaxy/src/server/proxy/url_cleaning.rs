@@ -0,0 +1,409 @@
+use percent_encoding::percent_decode_str;
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+const EMBEDDED_RULES: &[u8] = include_bytes!("../../resources/url_cleaning_rules.json");
+
+#[derive(Error, Debug)]
+pub enum UrlCleaningRulesError {
+    #[error("unable to parse ruleset: {0}")]
+    InvalidRuleset(#[from] serde_json::Error),
+}
+
+/// Ruleset in the format of ClearURLs' `data.min.json`.
+#[derive(Deserialize)]
+struct Ruleset {
+    providers: BTreeMap<String, Provider>,
+}
+
+/// `completeProvider`, blocking whole sites, is left to filter lists and ignored.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Provider {
+    url_pattern: String,
+    /// Patterns of the names of the query parameters to remove.
+    #[serde(default)]
+    rules: Vec<String>,
+    /// Patterns removed from anywhere in URLs.
+    #[serde(default)]
+    raw_rules: Vec<String>,
+    /// Like `rules`, for parameters only used for referral marketing.
+    #[serde(default)]
+    referral_marketing: Vec<String>,
+    /// Patterns of the URLs the provider does not apply to.
+    #[serde(default)]
+    exceptions: Vec<String>,
+    /// Patterns of redirect wrappers, capturing the URL they redirect to.
+    #[serde(default)]
+    redirections: Vec<String>,
+}
+
+/// Rule along with its compiled pattern, named after its provider and pattern in statistics.
+#[derive(Debug)]
+struct Rule {
+    name: String,
+    regex: Regex,
+}
+
+#[derive(Debug)]
+struct CompiledProvider {
+    url_pattern: Regex,
+    exceptions: Vec<Regex>,
+    parameters: Vec<Rule>,
+    raw_rules: Vec<Rule>,
+    redirections: Vec<Rule>,
+}
+
+/// ClearURLs patterns are JavaScript regular expressions, the few relying on features such as
+/// lookarounds are left out.
+fn compile_pattern(pattern: &str) -> Option<Regex> {
+    match RegexBuilder::new(pattern).case_insensitive(true).build() {
+        Ok(regex) => Some(regex),
+        Err(err) => {
+            log::debug!("Ignoring URL cleaning pattern {pattern}: {err}");
+            None
+        }
+    }
+}
+
+fn compile_rules<'a>(
+    provider_name: &str,
+    patterns: impl Iterator<Item = &'a String>,
+    to_regex: impl Fn(&str) -> String,
+) -> Vec<Rule> {
+    patterns
+        .filter_map(|pattern| {
+            Some(Rule {
+                name: format!("{provider_name}: {pattern}"),
+                regex: compile_pattern(&to_regex(pattern))?,
+            })
+        })
+        .collect()
+}
+
+impl CompiledProvider {
+    fn new(name: &str, provider: &Provider) -> Option<Self> {
+        Some(Self {
+            url_pattern: compile_pattern(&provider.url_pattern)?,
+            exceptions: provider
+                .exceptions
+                .iter()
+                .filter_map(|exception| compile_pattern(exception))
+                .collect(),
+            // Parameter names are matched whole, as they are sent.
+            parameters: compile_rules(
+                name,
+                provider.rules.iter().chain(&provider.referral_marketing),
+                |pattern| format!("^(?:{pattern})$"),
+            ),
+            raw_rules: compile_rules(name, provider.raw_rules.iter(), str::to_string),
+            redirections: compile_rules(name, provider.redirections.iter(), str::to_string),
+        })
+    }
+
+    fn is_match(&self, url: &str) -> bool {
+        self.url_pattern.is_match(url)
+            && !self
+                .exceptions
+                .iter()
+                .any(|exception| exception.is_match(url))
+    }
+
+    /// URL a redirect wrapper redirects to, with the redirection it was found with.
+    fn unwrap_redirection(&self, url: &str) -> Option<(String, &Rule)> {
+        self.redirections.iter().find_map(|redirection| {
+            let target = redirection.regex.captures(url)?.get(1)?.as_str();
+            let target = url::Url::parse(&percent_decode_str(target).decode_utf8_lossy()).ok()?;
+
+            matches!(target.scheme(), "http" | "https").then(|| (target.to_string(), redirection))
+        })
+    }
+
+    /// Removes matching query parameters, leaving the others as they were sent.
+    fn strip_parameters(&self, url: &str, stripped_rules: &mut Vec<String>) -> String {
+        let (base, query) = match url.split_once('?') {
+            Some(split_url) => split_url,
+            None => return url.to_string(),
+        };
+
+        let (query, fragment) = match query.split_once('#') {
+            Some((query, fragment)) => (query, Some(fragment)),
+            None => (query, None),
+        };
+
+        let kept_parameters = query
+            .split('&')
+            .filter(|parameter| {
+                let name = parameter.split('=').next().unwrap_or_default();
+
+                match self
+                    .parameters
+                    .iter()
+                    .find(|rule| rule.regex.is_match(name))
+                {
+                    Some(rule) => {
+                        stripped_rules.push(rule.name.clone());
+                        false
+                    }
+                    None => true,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let mut stripped_url = base.to_string();
+
+        if !kept_parameters.is_empty() {
+            stripped_url.push('?');
+            stripped_url.push_str(&kept_parameters.join("&"));
+        }
+
+        if let Some(fragment) = fragment {
+            stripped_url.push('#');
+            stripped_url.push_str(fragment);
+        }
+
+        stripped_url
+    }
+}
+
+/// What cleaning a URL led to.
+pub(crate) enum CleanedUrl {
+    /// The URL is a redirect wrapper, `url` being where it redirects to.
+    Unwrapped { url: String, rule: String },
+    /// Tracking parameters were removed, `rules` holding the rule behind each removal.
+    Stripped { url: String, rules: Vec<String> },
+}
+
+/// Compiled URL cleaning rules.
+#[derive(Debug, Default)]
+pub struct UrlCleaningRules(Vec<CompiledProvider>);
+
+impl UrlCleaningRules {
+    /// Parses a ClearURLs formatted ruleset. Providers and rules with patterns that can't be
+    /// compiled are left out rather than failing the whole ruleset.
+    pub(crate) fn parse(ruleset: &[u8]) -> Result<Self, UrlCleaningRulesError> {
+        let ruleset = serde_json::from_slice::<Ruleset>(ruleset)?;
+
+        Ok(Self(
+            ruleset
+                .providers
+                .iter()
+                .filter_map(|(name, provider)| CompiledProvider::new(name, provider))
+                .collect(),
+        ))
+    }
+
+    pub(crate) fn embedded() -> Self {
+        Self::parse(EMBEDDED_RULES).unwrap()
+    }
+
+    pub(crate) fn provider_count(&self) -> usize {
+        self.0.len()
+    }
+
+    fn clean(&self, url: &str) -> Option<CleanedUrl> {
+        let providers = self
+            .0
+            .iter()
+            .filter(|provider| provider.is_match(url))
+            .collect::<Vec<_>>();
+
+        if let Some((target, redirection)) = providers
+            .iter()
+            .find_map(|provider| provider.unwrap_redirection(url))
+        {
+            return Some(CleanedUrl::Unwrapped {
+                url: target,
+                rule: redirection.name.clone(),
+            });
+        }
+
+        let mut cleaned_url = url.to_string();
+        let mut rules = Vec::new();
+
+        for provider in providers {
+            for raw_rule in &provider.raw_rules {
+                if raw_rule.regex.is_match(&cleaned_url) {
+                    cleaned_url = raw_rule.regex.replace_all(&cleaned_url, "").into_owned();
+                    rules.push(raw_rule.name.clone());
+                }
+            }
+
+            cleaned_url = provider.strip_parameters(&cleaned_url, &mut rules);
+        }
+
+        (!rules.is_empty()).then_some(CleanedUrl::Stripped {
+            url: cleaned_url,
+            rules,
+        })
+    }
+}
+
+/// URL cleaning rules in use, empty until the configuration updater loads them.
+#[derive(Debug, Clone, Default)]
+pub struct UrlCleaner(Arc<RwLock<UrlCleaningRules>>);
+
+impl UrlCleaner {
+    pub fn replace_rules(&self, rules: UrlCleaningRules) {
+        *self.0.write().unwrap() = rules;
+    }
+
+    /// Unwraps redirect wrappers and strips tracking parameters from `url`, returns `None`
+    /// when it is left as is.
+    pub(crate) fn clean(&self, url: &str) -> Option<CleanedUrl> {
+        self.0.read().unwrap().clean(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULESET: &str = r#"{
+        "providers": {
+            "globalRules": {
+                "urlPattern": ".*",
+                "rules": ["utm_[a-z]+", "fbclid"],
+                "exceptions": ["^https?://(?:www\\.)?example\\.org/keep"]
+            },
+            "google": {
+                "urlPattern": "^https?://(?:www\\.)?google\\.com",
+                "rules": ["ved"],
+                "redirections": ["^https?://(?:www\\.)?google\\.com/url\\?.*?q=([^&]+)"]
+            },
+            "amazon": {
+                "urlPattern": "^https?://(?:www\\.)?amazon\\.com",
+                "rawRules": ["/ref=[^/?]*"],
+                "referralMarketing": ["tag"]
+            },
+            "lookbehind": {
+                "urlPattern": "(?<=a)b"
+            }
+        }
+    }"#;
+
+    fn rules() -> UrlCleaningRules {
+        UrlCleaningRules::parse(RULESET.as_bytes()).unwrap()
+    }
+
+    fn stripped(url: &str) -> Option<(String, Vec<String>)> {
+        match rules().clean(url)? {
+            CleanedUrl::Stripped { url, rules } => Some((url, rules)),
+            CleanedUrl::Unwrapped { .. } => None,
+        }
+    }
+
+    fn unwrapped(url: &str) -> Option<String> {
+        match rules().clean(url)? {
+            CleanedUrl::Unwrapped { url, .. } => Some(url),
+            CleanedUrl::Stripped { .. } => None,
+        }
+    }
+
+    fn stripped_url(url: &str) -> Option<String> {
+        stripped(url).map(|(url, _rules)| url)
+    }
+
+    #[test]
+    fn leaves_out_providers_that_do_not_compile() {
+        assert_eq!(rules().provider_count(), 3);
+        assert!(UrlCleaningRules::embedded().provider_count() > 0);
+    }
+
+    #[test]
+    fn strips_tracking_parameters() {
+        let (url, rules) =
+            stripped("https://a.com/p?utm_source=x&id=1&fbclid=y&UTM_MEDIUM=z").unwrap();
+
+        assert_eq!(url, "https://a.com/p?id=1");
+        assert_eq!(
+            rules,
+            [
+                "globalRules: utm_[a-z]+",
+                "globalRules: fbclid",
+                "globalRules: utm_[a-z]+"
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_parameter_names_whole() {
+        assert!(rules()
+            .clean("https://a.com/?myfbclid=1&utm_=2&id=fbclid")
+            .is_none());
+    }
+
+    #[test]
+    fn applies_raw_rules_and_referral_marketing() {
+        assert_eq!(
+            stripped_url("https://www.amazon.com/dp/1/ref=abc?tag=x&th=1").as_deref(),
+            Some("https://www.amazon.com/dp/1?th=1")
+        );
+    }
+
+    #[test]
+    fn unwraps_redirect_wrappers() {
+        assert_eq!(
+            unwrapped(
+                "https://www.google.com/url?sa=t&q=https%3A%2F%2Fexample.com%2Fpage%3Fa%3D1&ved=x"
+            )
+            .as_deref(),
+            Some("https://example.com/page?a=1")
+        );
+    }
+
+    #[test]
+    fn does_not_unwrap_to_other_schemes() {
+        let url = "https://www.google.com/url?q=javascript%3Aalert(1)&ved=x";
+
+        assert_eq!(unwrapped(url), None);
+        assert_eq!(
+            stripped_url(url).as_deref(),
+            Some("https://www.google.com/url?q=javascript%3Aalert(1)")
+        );
+    }
+
+    #[test]
+    fn skips_exceptions() {
+        assert!(rules()
+            .clean("https://example.org/keep?utm_source=x")
+            .is_none());
+        assert_eq!(
+            stripped_url("https://example.org/other?utm_source=x").as_deref(),
+            Some("https://example.org/other")
+        );
+    }
+
+    #[test]
+    fn keeps_fragments() {
+        assert_eq!(
+            stripped_url("https://a.com/?utm_source=x&a=1#utm_medium=y").as_deref(),
+            Some("https://a.com/?a=1#utm_medium=y")
+        );
+        assert_eq!(
+            stripped_url("https://a.com/?fbclid=x#top").as_deref(),
+            Some("https://a.com/#top")
+        );
+    }
+
+    #[test]
+    fn handles_parameters_without_values() {
+        assert_eq!(
+            stripped_url("https://a.com/?fbclid&flag&a=1").as_deref(),
+            Some("https://a.com/?flag&a=1")
+        );
+    }
+
+    #[test]
+    fn handles_empty_queries() {
+        assert!(rules().clean("https://a.com/?").is_none());
+        assert!(rules().clean("https://a.com/?#utm_source=x").is_none());
+        assert_eq!(
+            stripped_url("https://a.com/?utm_source=x").as_deref(),
+            Some("https://a.com/")
+        );
+    }
+}
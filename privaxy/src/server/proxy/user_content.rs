@@ -0,0 +1,104 @@
+use super::exclusions::WildMatchCollection;
+use crate::configuration::{RunAt, UserContent, UserContentKind};
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::sync::{Arc, RwLock};
+
+lazy_static! {
+    /// Closing tags which would end the element user content is injected in early.
+    static ref CLOSING_TAG: Regex = Regex::new(r"(?i)</(script|style)").unwrap();
+}
+
+#[derive(Debug)]
+struct CompiledUserContent {
+    include: Option<WildMatchCollection>,
+    exclude: WildMatchCollection,
+    run_at: RunAt,
+    /// The content, wrapped in the element it is injected in.
+    html: String,
+}
+
+impl CompiledUserContent {
+    fn new(user_content: UserContent) -> Self {
+        let content = CLOSING_TAG.replace_all(&user_content.content, "<\\/$1");
+
+        let html = match user_content.kind {
+            UserContentKind::Script => {
+                format!("\n<script type=\"application/javascript\">{content}</script>\n")
+            }
+            UserContentKind::Style => format!("\n<style>{content}</style>\n"),
+        };
+
+        Self {
+            include: (!user_content.include.is_empty())
+                .then(|| WildMatchCollection::new(user_content.include)),
+            exclude: WildMatchCollection::new(user_content.exclude),
+            run_at: user_content.run_at,
+            html,
+        }
+    }
+
+    fn is_match(&self, url: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(url))
+            && !self.exclude.is_match(url)
+    }
+}
+
+/// User content matching a document, ready to be injected.
+#[derive(Debug, Default)]
+pub(crate) struct MatchedUserContent {
+    pub document_start: String,
+    pub document_end: String,
+}
+
+impl MatchedUserContent {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.document_start.is_empty() && self.document_end.is_empty()
+    }
+}
+
+/// Enabled user scripts and user styles.
+#[derive(Debug, Clone)]
+pub struct UserContentStore(Arc<RwLock<Vec<CompiledUserContent>>>);
+
+impl UserContentStore {
+    pub fn new(user_content: Vec<UserContent>) -> Self {
+        Self(Arc::new(RwLock::new(Self::compile(user_content))))
+    }
+
+    pub fn replace_user_content(&self, user_content: Vec<UserContent>) {
+        *self.0.write().unwrap() = Self::compile(user_content);
+    }
+
+    fn compile(user_content: Vec<UserContent>) -> Vec<CompiledUserContent> {
+        user_content
+            .into_iter()
+            .filter(|user_content| user_content.enabled)
+            .map(CompiledUserContent::new)
+            .collect()
+    }
+
+    /// User content to inject into the document at `url`, in configuration order.
+    pub(crate) fn get_matching(&self, url: &str) -> MatchedUserContent {
+        let mut matched_user_content = MatchedUserContent::default();
+
+        for user_content in self.0.read().unwrap().iter() {
+            if !user_content.is_match(url) {
+                continue;
+            }
+
+            match user_content.run_at {
+                RunAt::DocumentStart => matched_user_content
+                    .document_start
+                    .push_str(&user_content.html),
+                RunAt::DocumentEnd => matched_user_content
+                    .document_end
+                    .push_str(&user_content.html),
+            }
+        }
+
+        matched_user_content
+    }
+}
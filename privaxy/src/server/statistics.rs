@@ -10,6 +10,32 @@ use uluru::LRUCache;
 
 const ENTRIES_PER_STATISTICS_TABLE: u8 = 50;
 
+fn increment_entry(entries: &Mutex<HashMap<String, u64>>, key: &str) {
+    let mut entries = entries.lock().unwrap();
+
+    match entries.get_mut(key) {
+        Some(count) => *count += 1,
+        None => {
+            entries.insert(key.to_string(), 1);
+        }
+    }
+}
+
+/// Entries with the highest counts, first to last.
+fn top_entries(entries: &Mutex<HashMap<String, u64>>) -> Vec<(String, u64)> {
+    let mut top_entries = entries
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(key, count)| (key.clone(), *count))
+        .collect::<Vec<_>>();
+
+    top_entries.sort_by_key(|(_key, count)| std::cmp::Reverse(*count));
+    top_entries.truncate(ENTRIES_PER_STATISTICS_TABLE.into());
+
+    top_entries
+}
+
 #[derive(Debug, Serialize)]
 pub struct SerializableStatistics {
     pub proxied_requests: u64,
@@ -19,6 +45,17 @@ pub struct SerializableStatistics {
     pub top_blocked_paths: Vec<(String, u64)>,
     #[serde(with = "tuple_vec_map")]
     pub top_clients: Vec<(String, u64)>,
+    #[serde(with = "tuple_vec_map")]
+    pub top_blocking_filter_lists: Vec<(String, u64)>,
+    pub cleaned_urls: u64,
+    #[serde(with = "tuple_vec_map")]
+    pub top_url_cleaning_rules: Vec<(String, u64)>,
+    #[serde(with = "tuple_vec_map")]
+    pub header_policy_hits: Vec<(String, u64)>,
+    pub rewriting_documents: u64,
+    pub rewriter_buffered_bytes: u64,
+    pub rewriter_peak_buffered_bytes: u64,
+    pub oversized_documents: u64,
 }
 
 #[derive(Debug, Clone)]
@@ -28,6 +65,19 @@ pub struct Statistics {
     pub modified_responses: Arc<Mutex<u64>>,
     pub top_blocked_paths: Arc<Mutex<LRUCache<(String, u64), 1_000>>>,
     pub top_clients: Arc<Mutex<HashMap<IpAddr, u64>>>,
+    pub top_blocking_filter_lists: Arc<Mutex<HashMap<String, u64>>>,
+    /// Requests whose URL was stripped of tracking parameters or unwrapped.
+    pub cleaned_urls: Arc<Mutex<u64>>,
+    pub top_url_cleaning_rules: Arc<Mutex<HashMap<String, u64>>>,
+    /// Times each header policy changed the headers of a request or of a response.
+    pub header_policy_hits: Arc<Mutex<HashMap<String, u64>>>,
+    /// Documents currently going through the HTML rewriter.
+    pub rewriting_documents: Arc<Mutex<u64>>,
+    /// Bytes of documents held between upstream servers, rewriters and clients.
+    pub rewriter_buffered_bytes: Arc<Mutex<u64>>,
+    pub rewriter_peak_buffered_bytes: Arc<Mutex<u64>>,
+    /// Documents too large to be rewritten, passed through untouched.
+    pub oversized_documents: Arc<Mutex<u64>>,
 }
 
 impl Default for Statistics {
@@ -44,6 +94,14 @@ impl Statistics {
             modified_responses: Arc::new(Mutex::new(0)),
             top_blocked_paths: Arc::new(Mutex::new(LRUCache::default())),
             top_clients: Arc::new(Mutex::new(HashMap::new())),
+            top_blocking_filter_lists: Arc::new(Mutex::new(HashMap::new())),
+            cleaned_urls: Arc::new(Mutex::new(0)),
+            top_url_cleaning_rules: Arc::new(Mutex::new(HashMap::new())),
+            header_policy_hits: Arc::new(Mutex::new(HashMap::new())),
+            rewriting_documents: Arc::new(Mutex::new(0)),
+            rewriter_buffered_bytes: Arc::new(Mutex::new(0)),
+            rewriter_peak_buffered_bytes: Arc::new(Mutex::new(0)),
+            oversized_documents: Arc::new(Mutex::new(0)),
         }
     }
 
@@ -64,6 +122,25 @@ impl Statistics {
         *self.top_clients.lock().unwrap().entry(client).or_insert(0) += 1;
     }
 
+    pub fn increment_top_blocking_filter_lists(&self, filter_list: &str) {
+        increment_entry(&self.top_blocking_filter_lists, filter_list);
+    }
+
+    pub fn increment_cleaned_urls(&self) -> u64 {
+        let mut cleaned_urls = self.cleaned_urls.lock().unwrap();
+
+        *cleaned_urls += 1;
+        *cleaned_urls
+    }
+
+    pub fn increment_top_url_cleaning_rules(&self, rule: &str) {
+        increment_entry(&self.top_url_cleaning_rules, rule);
+    }
+
+    pub fn increment_header_policy_hits(&self, header_policy: &str) {
+        increment_entry(&self.header_policy_hits, header_policy);
+    }
+
     pub fn increment_proxied_requests(&self) -> u64 {
         let mut proxied_requests = self.proxied_requests.lock().unwrap();
 
@@ -85,6 +162,34 @@ impl Statistics {
         *modified_responses
     }
 
+    pub fn increment_rewriting_documents(&self) {
+        *self.rewriting_documents.lock().unwrap() += 1;
+    }
+
+    pub fn decrement_rewriting_documents(&self) {
+        *self.rewriting_documents.lock().unwrap() -= 1;
+    }
+
+    pub fn add_rewriter_buffered_bytes(&self, bytes: u64) {
+        let mut rewriter_buffered_bytes = self.rewriter_buffered_bytes.lock().unwrap();
+        *rewriter_buffered_bytes += bytes;
+
+        let mut rewriter_peak_buffered_bytes = self.rewriter_peak_buffered_bytes.lock().unwrap();
+        *rewriter_peak_buffered_bytes =
+            (*rewriter_peak_buffered_bytes).max(*rewriter_buffered_bytes);
+    }
+
+    pub fn remove_rewriter_buffered_bytes(&self, bytes: u64) {
+        *self.rewriter_buffered_bytes.lock().unwrap() -= bytes;
+    }
+
+    pub fn increment_oversized_documents(&self) -> u64 {
+        let mut oversized_documents = self.oversized_documents.lock().unwrap();
+
+        *oversized_documents += 1;
+        *oversized_documents
+    }
+
     pub fn get_serialized(&self) -> SerializableStatistics {
         SerializableStatistics {
             proxied_requests: *self.proxied_requests.lock().unwrap(),
@@ -124,6 +229,14 @@ impl Statistics {
 
                 top_clients
             },
+            top_blocking_filter_lists: top_entries(&self.top_blocking_filter_lists),
+            cleaned_urls: *self.cleaned_urls.lock().unwrap(),
+            top_url_cleaning_rules: top_entries(&self.top_url_cleaning_rules),
+            header_policy_hits: top_entries(&self.header_policy_hits),
+            rewriting_documents: *self.rewriting_documents.lock().unwrap(),
+            rewriter_buffered_bytes: *self.rewriter_buffered_bytes.lock().unwrap(),
+            rewriter_peak_buffered_bytes: *self.rewriter_peak_buffered_bytes.lock().unwrap(),
+            oversized_documents: *self.oversized_documents.lock().unwrap(),
         }
     }
 }
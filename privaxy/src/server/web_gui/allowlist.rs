@@ -0,0 +1,72 @@
+use super::get_error_response;
+use crate::{configuration::Configuration, proxy::allowlist::AllowlistStore};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::mpsc::Sender;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::Filter as RouteFilter;
+
+async fn get_allowlist() -> Result<Box<dyn warp::Reply>, Infallible> {
+    let configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to get allowlist: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    let allowlist = Vec::from_iter(configuration.allowlist.into_iter()).join("\n");
+
+    Ok(Box::new(warp::reply::json(&allowlist)))
+}
+
+async fn put_allowlist(
+    allowlist: String,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    allowlist_store: AllowlistStore,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let _guard = configuration_save_lock.lock().await;
+
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to put allowlist: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    if let Err(err) = configuration
+        .set_allowlist(&allowlist, allowlist_store)
+        .await
+    {
+        return Ok(Box::new(get_error_response(err)));
+    }
+
+    configuration_updater_sender
+        .send(configuration.clone())
+        .await
+        .unwrap();
+
+    Ok(Box::new(StatusCode::ACCEPTED))
+}
+
+pub fn create_routes(
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    allowlist_store: AllowlistStore,
+) -> BoxedFilter<(impl warp::Reply,)> {
+    warp::get()
+        .and_then(self::get_allowlist)
+        .or(warp::put()
+            .and(warp::body::json())
+            .and(super::with_configuration_updater_sender(
+                configuration_updater_sender.clone(),
+            ))
+            .and(super::with_configuration_save_lock(
+                configuration_save_lock.clone(),
+            ))
+            .and(super::with_allowlist_store(allowlist_store))
+            .and_then(self::put_allowlist))
+        .boxed()
+}
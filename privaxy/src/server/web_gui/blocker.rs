@@ -0,0 +1,21 @@
+use crate::blocker::AdblockRequester;
+use std::convert::Infallible;
+use warp::filters::BoxedFilter;
+use warp::Filter as RouteFilter;
+
+pub async fn get_blocker_stats(
+    adblock_requester: AdblockRequester,
+) -> Result<impl warp::Reply, Infallible> {
+    Ok(warp::reply::json(&adblock_requester.stats()))
+}
+
+pub(super) fn create_routes(
+    adblock_requester: AdblockRequester,
+) -> BoxedFilter<(impl warp::Reply,)> {
+    warp::path("stats")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(super::with_adblock_requester(adblock_requester))
+        .and_then(self::get_blocker_stats)
+        .boxed()
+}
@@ -1,5 +1,5 @@
-use crate::blocker::BlockingDisabledStore;
-use serde::Deserialize;
+use crate::blocker::{BlockingDisabledStore, Pause, PauseScope};
+use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 use warp::filters::BoxedFilter;
 use warp::http::StatusCode;
@@ -8,6 +8,31 @@ use warp::Filter as RouteFilter;
 #[derive(Deserialize)]
 pub struct BlockingEnabled(bool);
 
+#[derive(Debug, Deserialize)]
+pub struct PauseRequest {
+    scope: PauseScope,
+    /// Pauses without a duration last until they are removed.
+    duration_seconds: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct PauseResponse {
+    #[serde(flatten)]
+    pause: Pause,
+    remaining_seconds: Option<i64>,
+}
+
+impl From<Pause> for PauseResponse {
+    fn from(pause: Pause) -> Self {
+        Self {
+            remaining_seconds: pause
+                .until
+                .map(|until| (until - chrono::Utc::now()).num_seconds().max(0)),
+            pause,
+        }
+    }
+}
+
 pub async fn get_blocking_enabled(
     blocking_disabled_store: BlockingDisabledStore,
 ) -> Result<impl warp::Reply, Infallible> {
@@ -18,19 +43,95 @@ pub async fn put_blocking_enabled(
     blocking_enabled: BlockingEnabled,
     blocking_disabled_store: BlockingDisabledStore,
 ) -> Result<impl warp::Reply, Infallible> {
-    blocking_disabled_store.set(!blocking_enabled.0);
+    blocking_disabled_store.set(blocking_enabled.0).await;
 
     Ok(StatusCode::NO_CONTENT)
 }
 
+pub async fn get_pauses(
+    blocking_disabled_store: BlockingDisabledStore,
+) -> Result<impl warp::Reply, Infallible> {
+    let pauses = blocking_disabled_store
+        .pauses()
+        .into_iter()
+        .map(PauseResponse::from)
+        .collect::<Vec<_>>();
+
+    Ok(warp::reply::json(&pauses))
+}
+
+pub async fn post_pause(
+    pause_request: PauseRequest,
+    blocking_disabled_store: BlockingDisabledStore,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let scope = match pause_request.scope {
+        PauseScope::Site(site) => {
+            let site = site.trim().trim_start_matches('.').to_lowercase();
+
+            if site.is_empty() {
+                return Ok(Box::new(StatusCode::BAD_REQUEST));
+            }
+
+            PauseScope::Site(site)
+        }
+        scope => scope,
+    };
+
+    let pause = blocking_disabled_store
+        .pause(
+            scope,
+            pause_request
+                .duration_seconds
+                .map(|duration_seconds| chrono::Duration::seconds(duration_seconds.into())),
+        )
+        .await;
+
+    Ok(Box::new(warp::reply::with_status(
+        warp::reply::json(&PauseResponse::from(pause)),
+        StatusCode::CREATED,
+    )))
+}
+
+pub async fn delete_pause(
+    id: u64,
+    blocking_disabled_store: BlockingDisabledStore,
+) -> Result<impl warp::Reply, Infallible> {
+    if blocking_disabled_store.resume(id).await {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
 pub(super) fn create_routes(
     blocking_disabled_store: BlockingDisabledStore,
 ) -> BoxedFilter<(impl warp::Reply,)> {
     let block_store = super::with_blocking_disabled_store(blocking_disabled_store);
-    warp::get()
-        .and(block_store.clone())
-        .and_then(self::get_blocking_enabled)
-        .or(warp::put()
+
+    let pauses_route = warp::path("pauses").and(
+        warp::path::end()
+            .and(warp::get())
+            .and(block_store.clone())
+            .and_then(self::get_pauses)
+            .or(warp::path::end()
+                .and(warp::post())
+                .and(warp::body::json())
+                .and(block_store.clone())
+                .and_then(self::post_pause))
+            .or(warp::path::param::<u64>()
+                .and(warp::path::end())
+                .and(warp::delete())
+                .and(block_store.clone())
+                .and_then(self::delete_pause)),
+    );
+
+    pauses_route
+        .or(warp::path::end()
+            .and(warp::get())
+            .and(block_store.clone())
+            .and_then(self::get_blocking_enabled))
+        .or(warp::path::end()
+            .and(warp::put())
             .and(warp::body::json())
             .and(block_store)
             .and_then(self::put_blocking_enabled))
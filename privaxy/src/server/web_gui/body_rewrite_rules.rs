@@ -0,0 +1,78 @@
+use super::{get_bad_request_response, get_error_response};
+use crate::configuration::{BodyRewriteRule, Configuration};
+use crate::proxy::body_rewrite::{check_rules, BodyRewriteStore};
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::mpsc::Sender;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::Filter as RouteFilter;
+
+async fn get_body_rewrite_rules() -> Result<Box<dyn warp::Reply>, Infallible> {
+    let configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to get body rewrite rules: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    Ok(Box::new(warp::reply::json(
+        &configuration.body_rewrite_rules,
+    )))
+}
+
+async fn put_body_rewrite_rules(
+    body_rewrite_rules: Vec<BodyRewriteRule>,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    body_rewrite_store: BodyRewriteStore,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    if let Err(err) = check_rules(&body_rewrite_rules) {
+        log::error!("Invalid body rewrite rules: {err}");
+        return Ok(Box::new(get_bad_request_response(err)));
+    }
+
+    let _guard = configuration_save_lock.lock().await;
+
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to put body rewrite rules: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    if let Err(err) = configuration
+        .set_body_rewrite_rules(body_rewrite_rules, body_rewrite_store)
+        .await
+    {
+        return Ok(Box::new(get_error_response(err)));
+    }
+
+    configuration_updater_sender
+        .send(configuration.clone())
+        .await
+        .unwrap();
+
+    Ok(Box::new(StatusCode::ACCEPTED))
+}
+
+pub fn create_routes(
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    body_rewrite_store: BodyRewriteStore,
+) -> BoxedFilter<(impl warp::Reply,)> {
+    warp::get()
+        .and_then(self::get_body_rewrite_rules)
+        .or(warp::put()
+            .and(warp::body::json())
+            .and(super::with_configuration_updater_sender(
+                configuration_updater_sender.clone(),
+            ))
+            .and(super::with_configuration_save_lock(
+                configuration_save_lock.clone(),
+            ))
+            .and(super::with_body_rewrite_store(body_rewrite_store))
+            .and_then(self::put_body_rewrite_rules))
+        .boxed()
+}
@@ -0,0 +1,134 @@
+use super::get_error_response;
+use crate::ca::{make_mobileconfig, make_pkcs7_bundle, sha256_fingerprint};
+use crate::configuration::Configuration;
+use serde::Serialize;
+use std::convert::Infallible;
+use warp::filters::BoxedFilter;
+use warp::http::Response;
+use warp::Filter as RouteFilter;
+
+/// File names the CA certificate can be downloaded as, relative to `/api/ca`.
+const DOWNLOADS: &[&str] = &[
+    "privaxy-ca.pem",
+    "privaxy-ca.crt",
+    "privaxy-ca.cer",
+    "privaxy-ca.p7b",
+    "privaxy-ca.mobileconfig",
+];
+
+#[derive(Debug, Serialize)]
+struct CaCertificateInfo {
+    sha256_fingerprint: String,
+    not_after: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CaInfo {
+    /// The current CA first, followed by the previous one while it is still in use.
+    certificates: Vec<CaCertificateInfo>,
+    downloads: Vec<String>,
+}
+
+async fn get_ca_info() -> Result<Box<dyn warp::Reply>, Infallible> {
+    let certificates = match load_trusted_ca_certificates().await {
+        Ok(certificates) => certificates,
+        Err(response) => return Ok(response),
+    };
+
+    Ok(Box::new(warp::reply::json(&CaInfo {
+        certificates: certificates
+            .iter()
+            .map(|certificate| CaCertificateInfo {
+                sha256_fingerprint: sha256_fingerprint(certificate),
+                not_after: certificate.not_after().to_string(),
+            })
+            .collect(),
+        downloads: DOWNLOADS
+            .iter()
+            .map(|file_name| format!("/api/ca/{file_name}"))
+            .collect(),
+    })))
+}
+
+async fn download_ca_certificate(file_name: String) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let certificates = match load_trusted_ca_certificates().await {
+        Ok(certificates) => certificates,
+        Err(response) => return Ok(response),
+    };
+
+    // Single certificate formats only carry the current CA, bundles carry the previous
+    // one as well so that freshly onboarded clients work during a rotation.
+    let (content_type, body) = match file_name.as_str() {
+        "privaxy-ca.pem" => (
+            "application/x-pem-file",
+            certificates
+                .iter()
+                .flat_map(|certificate| certificate.to_pem().unwrap())
+                .collect::<Vec<_>>(),
+        ),
+        "privaxy-ca.crt" | "privaxy-ca.cer" => (
+            "application/x-x509-ca-cert",
+            certificates[0].to_der().unwrap(),
+        ),
+        "privaxy-ca.p7b" => (
+            "application/x-pkcs7-certificates",
+            make_pkcs7_bundle(&certificates),
+        ),
+        "privaxy-ca.mobileconfig" => (
+            "application/x-apple-aspen-config",
+            make_mobileconfig(&certificates).into_bytes(),
+        ),
+        _ => {
+            return Ok(Box::new(
+                Response::builder()
+                    .status(http::StatusCode::NOT_FOUND)
+                    .body(""),
+            ))
+        }
+    };
+
+    Ok(Box::new(
+        Response::builder()
+            .header(
+                http::header::CONTENT_DISPOSITION,
+                format!("attachment; filename={file_name};"),
+            )
+            .header(http::header::CONTENT_TYPE, content_type)
+            .header(
+                "X-Certificate-Fingerprint-Sha256",
+                sha256_fingerprint(&certificates[0]),
+            )
+            .body(body),
+    ))
+}
+
+async fn load_trusted_ca_certificates() -> Result<Vec<openssl::x509::X509>, Box<dyn warp::Reply>> {
+    let configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to load config: {err}");
+            return Err(Box::new(get_error_response(err)));
+        }
+    };
+
+    match configuration.ca.get_trusted_ca_certificates().await {
+        Ok(certificates) => Ok(certificates),
+        Err(err) => {
+            log::error!("Failed to get CA certificates: {err}");
+            Err(Box::new(get_error_response(err)))
+        }
+    }
+}
+
+/// Read-only routes distributing the public CA certificate, the private key is never exposed.
+pub(crate) fn create_routes() -> BoxedFilter<(impl warp::Reply,)> {
+    warp::get()
+        .and(
+            warp::path::end()
+                .and_then(self::get_ca_info)
+                .or(warp::path::param::<String>()
+                    .and(warp::path::end())
+                    .and_then(self::download_ca_certificate)),
+        )
+        .boxed()
+}
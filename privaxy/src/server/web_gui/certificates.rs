@@ -0,0 +1,62 @@
+use crate::cert::CertCache;
+use std::convert::Infallible;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::Filter as RouteFilter;
+
+async fn get_certificates(cert_cache: CertCache) -> Result<impl warp::Reply, Infallible> {
+    Ok(warp::reply::json(&cert_cache.list().await))
+}
+
+async fn get_certificate(
+    authority: String,
+    cert_cache: CertCache,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    match cert_cache.inspect(&authority).await {
+        Some(certificate) => Ok(Box::new(warp::reply::json(&certificate))),
+        None => Ok(Box::new(StatusCode::NOT_FOUND)),
+    }
+}
+
+async fn purge_certificates(cert_cache: CertCache) -> Result<impl warp::Reply, Infallible> {
+    cert_cache.purge().await;
+
+    log::info!("Purged certificate cache");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn purge_certificate(
+    authority: String,
+    cert_cache: CertCache,
+) -> Result<impl warp::Reply, Infallible> {
+    if cert_cache.remove(&authority).await {
+        log::info!("Purged cached certificate for {authority}");
+
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Ok(StatusCode::NOT_FOUND)
+    }
+}
+
+pub(super) fn create_routes(cert_cache: CertCache) -> BoxedFilter<(impl warp::Reply,)> {
+    let cert_cache = super::with_cert_cache(cert_cache);
+
+    warp::path::end()
+        .and(warp::get())
+        .and(cert_cache.clone())
+        .and_then(self::get_certificates)
+        .or(warp::path::end()
+            .and(warp::delete())
+            .and(cert_cache.clone())
+            .and_then(self::purge_certificates))
+        .or(warp::path!(String)
+            .and(warp::get())
+            .and(cert_cache.clone())
+            .and_then(self::get_certificate))
+        .or(warp::path!(String)
+            .and(warp::delete())
+            .and(cert_cache)
+            .and_then(self::purge_certificate))
+        .boxed()
+}
@@ -0,0 +1,172 @@
+use super::{get_bad_request_response, get_unavailable_response};
+use crate::blocker::{
+    is_document_request_type, parse_request_type, AdblockRequester, BlockerError,
+    CosmeticBlockerResult, NetworkBlockerResult, NETWORK_REQUEST_TYPE,
+};
+use crate::proxy::url_cleaning::{CleanedUrl, UrlCleaner};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use warp::filters::BoxedFilter;
+use warp::Filter as RouteFilter;
+
+#[derive(Debug, Deserialize)]
+struct CheckQuery {
+    url: String,
+    /// Page the request is made from, defaults to `url` as for proxied requests without a referer.
+    source_url: Option<String>,
+    request_type: Option<String>,
+    /// Comma separated classes found in the page, generic cosmetic filters only apply to those.
+    #[serde(default)]
+    classes: String,
+    /// Comma separated ids found in the page, generic cosmetic filters only apply to those.
+    #[serde(default)]
+    ids: String,
+    /// Client the request is made by, defaults to the one asking.
+    client: Option<IpAddr>,
+}
+
+#[derive(Debug, Serialize)]
+struct CheckResponse {
+    url: String,
+    source_url: String,
+    request_type: &'static str,
+    client: IpAddr,
+    /// False when blocking is paused for the client or the site is allowlisted, the proxy
+    /// then lets the request through whatever the filters say.
+    blocking_enabled: bool,
+    /// Where the proxy redirects to when `url` is a redirect wrapper, nothing is checked then.
+    unwrapped_url: Option<String>,
+    /// `url` without its tracking parameters, as it is checked and requested.
+    stripped_url: Option<String>,
+    /// URL cleaning rules behind `unwrapped_url` or `stripped_url`.
+    url_cleaning_rules: Vec<String>,
+    blocked: bool,
+    network: Option<NetworkBlockerResult>,
+    /// Only given for document requests.
+    cosmetic: Option<CosmeticBlockerResult>,
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Explains what blocking does to a request, going through the same paths as proxied traffic.
+async fn check(
+    query: CheckQuery,
+    remote_address: Option<SocketAddr>,
+    adblock_requester: AdblockRequester,
+    url_cleaner: UrlCleaner,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let request_type = match parse_request_type(
+        query
+            .request_type
+            .as_deref()
+            .unwrap_or(NETWORK_REQUEST_TYPE),
+    ) {
+        Ok(request_type) => request_type,
+        Err(err) => return Ok(Box::new(get_bad_request_response(err))),
+    };
+
+    let source_url = query.source_url.unwrap_or_else(|| query.url.clone());
+
+    let site = match url::Url::parse(&query.url)
+        .and_then(|url| Ok((url, url::Url::parse(&source_url)?)))
+    {
+        // As for proxied traffic, page loads are made from the site they load.
+        Ok((url, _source_url)) if is_document_request_type(request_type) => {
+            url.host_str().unwrap_or_default().to_string()
+        }
+        Ok((_url, source_url)) => source_url.host_str().unwrap_or_default().to_string(),
+        Err(err) => return Ok(Box::new(get_bad_request_response(err))),
+    };
+
+    let client = query
+        .client
+        .or_else(|| remote_address.map(|remote_address| remote_address.ip()))
+        .unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let blocking_enabled = adblock_requester.is_blocking_enabled_for(client, &site);
+
+    let mut response = CheckResponse {
+        url: query.url.clone(),
+        source_url: source_url.clone(),
+        request_type,
+        client,
+        blocking_enabled,
+        unwrapped_url: None,
+        stripped_url: None,
+        url_cleaning_rules: Vec::new(),
+        blocked: false,
+        network: None,
+        cosmetic: None,
+    };
+
+    // URLs are cleaned before being checked, as proxied requests are.
+    let cleaned_url = if blocking_enabled {
+        url_cleaner.clean(&query.url)
+    } else {
+        None
+    };
+
+    let checked_url = match cleaned_url {
+        Some(CleanedUrl::Unwrapped { url, rule }) => {
+            response.unwrapped_url = Some(url);
+            response.url_cleaning_rules.push(rule);
+
+            return Ok(Box::new(warp::reply::json(&response)));
+        }
+        Some(CleanedUrl::Stripped { url, rules }) => {
+            response.stripped_url = Some(url.clone());
+            response.url_cleaning_rules = rules;
+            url
+        }
+        None => query.url.clone(),
+    };
+
+    let network = match adblock_requester
+        .check_network_url(checked_url.clone(), source_url, request_type)
+        .await
+    {
+        Ok(network) => network,
+        Err(err @ BlockerError::InvalidRequest(_)) => {
+            return Ok(Box::new(get_bad_request_response(err)))
+        }
+        Err(err @ BlockerError::Unavailable) => return Ok(Box::new(get_unavailable_response(err))),
+    };
+
+    if is_document_request_type(request_type) {
+        response.cosmetic = Some(
+            adblock_requester
+                .get_cosmetic_response(
+                    checked_url,
+                    split_list(&query.ids),
+                    split_list(&query.classes),
+                )
+                .await,
+        );
+    }
+
+    response.blocked = blocking_enabled && network.matched;
+    response.network = Some(network);
+
+    Ok(Box::new(warp::reply::json(&response)))
+}
+
+pub(super) fn create_routes(
+    adblock_requester: AdblockRequester,
+    url_cleaner: UrlCleaner,
+) -> BoxedFilter<(impl warp::Reply,)> {
+    warp::path("check")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(warp::query::<CheckQuery>())
+        .and(warp::addr::remote())
+        .and(super::with_adblock_requester(adblock_requester))
+        .and(super::with_url_cleaner(url_cleaner))
+        .and_then(self::check)
+        .boxed()
+}
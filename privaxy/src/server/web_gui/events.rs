@@ -10,6 +10,8 @@ pub struct Event {
     pub method: String,
     pub url: String,
     pub is_request_blocked: bool,
+    /// Filter list of the rule that matched the request.
+    pub filter_list: Option<String>,
 }
 
 pub(super) async fn events(websocket: WebSocket, events_sender: broadcast::Sender<Event>) {
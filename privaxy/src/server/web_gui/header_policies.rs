@@ -0,0 +1,71 @@
+use super::get_error_response;
+use crate::configuration::{Configuration, HeaderPolicy};
+use crate::proxy::header_policy::HeaderPolicyStore;
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::mpsc::Sender;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::Filter as RouteFilter;
+
+async fn get_header_policies() -> Result<Box<dyn warp::Reply>, Infallible> {
+    let configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to get header policies: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    Ok(Box::new(warp::reply::json(&configuration.header_policies)))
+}
+
+async fn put_header_policies(
+    header_policies: Vec<HeaderPolicy>,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    header_policy_store: HeaderPolicyStore,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let _guard = configuration_save_lock.lock().await;
+
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to put header policies: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    if let Err(err) = configuration
+        .set_header_policies(header_policies, header_policy_store)
+        .await
+    {
+        return Ok(Box::new(get_error_response(err)));
+    }
+
+    configuration_updater_sender
+        .send(configuration.clone())
+        .await
+        .unwrap();
+
+    Ok(Box::new(StatusCode::ACCEPTED))
+}
+
+pub fn create_routes(
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    header_policy_store: HeaderPolicyStore,
+) -> BoxedFilter<(impl warp::Reply,)> {
+    warp::get()
+        .and_then(self::get_header_policies)
+        .or(warp::put()
+            .and(warp::body::json())
+            .and(super::with_configuration_updater_sender(
+                configuration_updater_sender.clone(),
+            ))
+            .and(super::with_configuration_save_lock(
+                configuration_save_lock.clone(),
+            ))
+            .and(super::with_header_policy_store(header_policy_store))
+            .and_then(self::put_header_policies))
+        .boxed()
+}
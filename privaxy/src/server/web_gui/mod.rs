@@ -1,4 +1,11 @@
+use crate::blocker::AdblockRequester;
+use crate::cert::CertCache;
+use crate::proxy::allowlist::AllowlistStore;
+use crate::proxy::body_rewrite::BodyRewriteStore;
 use crate::proxy::exclusions::LocalExclusionStore;
+use crate::proxy::header_policy::HeaderPolicyStore;
+use crate::proxy::url_cleaning::UrlCleaner;
+use crate::proxy::user_content::UserContentStore;
 use crate::statistics::Statistics;
 use crate::WEBAPP_FRONTEND_DIR;
 use crate::{blocker::BlockingDisabledStore, configuration::Configuration};
@@ -11,19 +18,28 @@ use warp::http::Response;
 use warp::path::Tail;
 use warp::{http, Filter, Reply};
 
+pub(crate) mod allowlist;
+pub(crate) mod blocker;
 pub(crate) mod blocking_enabled;
+pub(crate) mod body_rewrite_rules;
+pub(crate) mod ca;
+pub(crate) mod certificates;
 pub(crate) mod custom_filters;
+mod debug;
 pub(crate) mod events;
 pub(crate) mod exclusions;
 mod filterlists;
 pub(crate) mod filters;
+pub(crate) mod header_policies;
 pub(crate) mod settings;
 pub(crate) mod statistics;
+pub(crate) mod user_content;
 
 #[derive(Debug, Serialize)]
 pub(crate) struct ApiError {
     error: String,
 }
+#[allow(clippy::too_many_arguments)]
 pub(crate) fn get_frontend(
     events_sender: broadcast::Sender<events::Event>,
     statistics: Statistics,
@@ -31,6 +47,13 @@ pub(crate) fn get_frontend(
     configuration_updater_sender: &Sender<Configuration>,
     configuration_save_lock: &Arc<tokio::sync::Mutex<()>>,
     local_exclusions_store: &LocalExclusionStore,
+    allowlist_store: &AllowlistStore,
+    user_content_store: &UserContentStore,
+    body_rewrite_store: &BodyRewriteStore,
+    header_policy_store: &HeaderPolicyStore,
+    url_cleaner: &UrlCleaner,
+    cert_cache: &CertCache,
+    adblock_requester: &AdblockRequester,
     tls: bool,
     notify_reload: Arc<Notify>,
 ) -> impl Filter<Extract = impl warp::Reply, Error = warp::Rejection> + Clone {
@@ -52,6 +75,13 @@ pub(crate) fn get_frontend(
         configuration_updater_sender,
         configuration_save_lock,
         local_exclusions_store,
+        allowlist_store,
+        user_content_store,
+        body_rewrite_store,
+        header_policy_store,
+        url_cleaner,
+        cert_cache,
+        adblock_requester,
         http_client,
         notify_reload,
     );
@@ -96,6 +126,7 @@ fn create_static_routes() -> BoxedFilter<(impl warp::Reply,)> {
         .boxed()
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_api_routes(
     events_sender: broadcast::Sender<events::Event>,
     statistics: Statistics,
@@ -103,6 +134,13 @@ fn create_api_routes(
     configuration_updater_sender: &Sender<Configuration>,
     configuration_save_lock: &Arc<tokio::sync::Mutex<()>>,
     local_exclusions_store: &LocalExclusionStore,
+    allowlist_store: &AllowlistStore,
+    user_content_store: &UserContentStore,
+    body_rewrite_store: &BodyRewriteStore,
+    header_policy_store: &HeaderPolicyStore,
+    url_cleaner: &UrlCleaner,
+    cert_cache: &CertCache,
+    adblock_requester: &AdblockRequester,
     http_client: reqwest::Client,
     notify_reload: Arc<Notify>,
 ) -> BoxedFilter<(impl Reply,)> {
@@ -141,9 +179,35 @@ fn create_api_routes(
         local_exclusions_store.clone(),
     ));
 
+    let allowlist_route = warp::path("allowlist").and(allowlist::create_routes(
+        configuration_updater_sender.clone(),
+        configuration_save_lock.clone(),
+        allowlist_store.clone(),
+    ));
+
+    let user_content_route = warp::path("user-content").and(user_content::create_routes(
+        configuration_updater_sender.clone(),
+        configuration_save_lock.clone(),
+        user_content_store.clone(),
+    ));
+
+    let body_rewrite_rules_route =
+        warp::path("body-rewrite-rules").and(body_rewrite_rules::create_routes(
+            configuration_updater_sender.clone(),
+            configuration_save_lock.clone(),
+            body_rewrite_store.clone(),
+        ));
+
+    let header_policies_route = warp::path("header-policies").and(header_policies::create_routes(
+        configuration_updater_sender.clone(),
+        configuration_save_lock.clone(),
+        header_policy_store.clone(),
+    ));
+
     let settings_route = warp::path("settings").and(settings::create_routes(
         configuration_updater_sender.clone(),
         configuration_save_lock.clone(),
+        cert_cache.clone(),
         notify_reload.clone(),
     ));
 
@@ -151,6 +215,19 @@ fn create_api_routes(
         blocking_enabled::create_routes(blocking_disabled_store.clone()),
     );
 
+    let certificates_route =
+        warp::path("certificates").and(certificates::create_routes(cert_cache.clone()));
+
+    let ca_route = warp::path("ca").and(ca::create_routes());
+
+    let blocker_route =
+        warp::path("blocker").and(blocker::create_routes(adblock_requester.clone()));
+
+    let debug_route = warp::path("debug").and(debug::create_routes(
+        adblock_requester.clone(),
+        url_cleaner.clone(),
+    ));
+
     let options_route = warp::options().map(|| "");
 
     let filterlists_route = warp::path("filterlists").and(filterlists::create_routes());
@@ -178,8 +255,16 @@ fn create_api_routes(
                 .or(filters_route)
                 .or(custom_filters_route)
                 .or(exclusions_route)
+                .or(allowlist_route)
+                .or(user_content_route)
+                .or(body_rewrite_rules_route)
+                .or(header_policies_route)
                 .or(blocking_enabled_route)
                 .or(settings_route)
+                .or(certificates_route)
+                .or(ca_route)
+                .or(blocker_route)
+                .or(debug_route)
                 .or(options_route)
                 .or(filterlists_route)
                 .or(not_found),
@@ -194,6 +279,30 @@ pub(crate) fn with_local_exclusions_store(
     warp::any().map(move || local_exclusions_store.clone())
 }
 
+pub(crate) fn with_allowlist_store(
+    allowlist_store: AllowlistStore,
+) -> impl Filter<Extract = (AllowlistStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || allowlist_store.clone())
+}
+
+pub(crate) fn with_user_content_store(
+    user_content_store: UserContentStore,
+) -> impl Filter<Extract = (UserContentStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || user_content_store.clone())
+}
+
+pub(crate) fn with_body_rewrite_store(
+    body_rewrite_store: BodyRewriteStore,
+) -> impl Filter<Extract = (BodyRewriteStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || body_rewrite_store.clone())
+}
+
+pub(crate) fn with_header_policy_store(
+    header_policy_store: HeaderPolicyStore,
+) -> impl Filter<Extract = (HeaderPolicyStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || header_policy_store.clone())
+}
+
 pub(crate) fn with_configuration_save_lock(
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
 ) -> impl Filter<Extract = (Arc<tokio::sync::Mutex<()>>,), Error = std::convert::Infallible> + Clone
@@ -201,6 +310,24 @@ pub(crate) fn with_configuration_save_lock(
     warp::any().map(move || configuration_save_lock.clone())
 }
 
+pub(crate) fn with_url_cleaner(
+    url_cleaner: UrlCleaner,
+) -> impl Filter<Extract = (UrlCleaner,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || url_cleaner.clone())
+}
+
+pub(crate) fn with_cert_cache(
+    cert_cache: CertCache,
+) -> impl Filter<Extract = (CertCache,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || cert_cache.clone())
+}
+
+pub(crate) fn with_adblock_requester(
+    adblock_requester: AdblockRequester,
+) -> impl Filter<Extract = (AdblockRequester,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || adblock_requester.clone())
+}
+
 fn with_blocking_disabled_store(
     blocking_disabled: BlockingDisabledStore,
 ) -> impl Filter<Extract = (BlockingDisabledStore,), Error = std::convert::Infallible> + Clone {
@@ -225,6 +352,32 @@ pub(self) fn with_notify_reload(
     warp::any().map(move || notify_reload.clone())
 }
 
+pub(crate) fn get_bad_request_response(err: impl std::error::Error) -> Response<String> {
+    Response::builder()
+        .status(http::StatusCode::BAD_REQUEST)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(
+            serde_json::to_string(&ApiError {
+                error: err.to_string(),
+            })
+            .unwrap(),
+        )
+        .unwrap()
+}
+
+pub(crate) fn get_unavailable_response(err: impl std::error::Error) -> Response<String> {
+    Response::builder()
+        .status(http::StatusCode::SERVICE_UNAVAILABLE)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(
+            serde_json::to_string(&ApiError {
+                error: err.to_string(),
+            })
+            .unwrap(),
+        )
+        .unwrap()
+}
+
 pub(crate) fn get_error_response(err: impl std::error::Error) -> Response<String> {
     log::debug!("Building error response: {:?}", err);
     Response::builder()
@@ -1,10 +1,15 @@
 use super::get_error_response;
+use crate::ca::{CaOptions, NameConstraints};
+use crate::cert::CertCache;
 use crate::configuration::Ca;
 use crate::configuration::Configuration;
+use crate::web_gui::get_bad_request_response;
+use crate::web_gui::with_cert_cache;
 use crate::web_gui::with_configuration_save_lock;
 use crate::web_gui::with_configuration_updater_sender;
 use crate::web_gui::with_notify_reload;
 use crate::web_gui::ApiError;
+use serde::Deserialize;
 use std::convert::Infallible;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
@@ -13,6 +18,35 @@ use warp::filters::BoxedFilter;
 use warp::http::Response;
 use warp::Filter as RouteFilter;
 
+#[derive(Debug, Deserialize)]
+struct PutCaRequest {
+    #[serde(flatten)]
+    ca: Ca,
+    /// Replaces the configured name constraints when set, empty ones clearing them.
+    #[serde(default)]
+    name_constraints: Option<NameConstraints>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GenerateCaRequest {
+    #[serde(flatten)]
+    options: CaOptions,
+    /// Replaces the configured name constraints when set.
+    #[serde(default)]
+    name_constraints: Option<NameConstraints>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RotateCaRequest {
+    #[serde(flatten)]
+    options: CaOptions,
+    /// Replaces the configured name constraints when set.
+    #[serde(default)]
+    name_constraints: Option<NameConstraints>,
+    /// For how many days the current CA keeps signing certificates.
+    overlap_days: u32,
+}
+
 async fn get_ca_certificates() -> Result<Box<dyn warp::Reply>, Infallible> {
     log::debug!("Getting CA certificates");
     let configuration = match Configuration::read_from_home().await {
@@ -65,9 +99,10 @@ async fn validate_ca_certificates(body: Ca) -> Result<Box<dyn warp::Reply>, Infa
 }
 
 async fn put_ca_certificates(
-    ca_cert_struct: Ca,
+    put_request: PutCaRequest,
     configuration_updater_sender: Sender<Configuration>,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    cert_cache: CertCache,
     notify_reload: Arc<Notify>,
 ) -> Result<Box<dyn warp::Reply>, Infallible> {
     let _guard = configuration_save_lock.lock().await;
@@ -80,11 +115,127 @@ async fn put_ca_certificates(
         }
     };
 
-    if let Err(err) = configuration.set_ca_settings(&ca_cert_struct).await {
+    if let Err(err) = configuration
+        .set_ca_settings(&put_request.ca, put_request.name_constraints)
+        .await
+    {
         log::error!("Failed to set CA certificate: {err}");
         return Ok(Box::new(get_error_response(err)));
     }
 
+    apply_ca_configuration(configuration, configuration_updater_sender, cert_cache).await
+}
+
+async fn generate_ca_certificate(
+    generate_request: GenerateCaRequest,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    cert_cache: CertCache,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let _guard = configuration_save_lock.lock().await;
+
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to load config: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    if let Some(name_constraints) = generate_request.name_constraints {
+        if let Err(err) = configuration.ca.set_name_constraints(name_constraints) {
+            log::error!("Invalid name constraints: {err}");
+            return Ok(Box::new(get_bad_request_response(err)));
+        }
+    }
+
+    if let Err(err) = configuration.ca.generate(&generate_request.options).await {
+        log::error!("Failed to generate CA certificate: {err}");
+        return Ok(Box::new(get_bad_request_response(err)));
+    }
+
+    log::info!(
+        "Generated a new {:?} CA certificate",
+        generate_request.options.key_type
+    );
+
+    apply_ca_configuration(configuration, configuration_updater_sender, cert_cache).await
+}
+
+async fn rotate_ca_certificate(
+    rotate_request: RotateCaRequest,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    cert_cache: CertCache,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let _guard = configuration_save_lock.lock().await;
+
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to load config: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    if let Some(name_constraints) = rotate_request.name_constraints {
+        if let Err(err) = configuration.ca.set_name_constraints(name_constraints) {
+            log::error!("Invalid name constraints: {err}");
+            return Ok(Box::new(get_bad_request_response(err)));
+        }
+    }
+
+    if let Err(err) = configuration
+        .ca
+        .rotate(&rotate_request.options, rotate_request.overlap_days)
+        .await
+    {
+        log::error!("Failed to rotate CA certificate: {err}");
+        return Ok(Box::new(get_bad_request_response(err)));
+    }
+
+    log::info!(
+        "Rotated the CA certificate, the previous one stays in use for {} days",
+        rotate_request.overlap_days
+    );
+
+    apply_ca_configuration(configuration, configuration_updater_sender, cert_cache).await
+}
+
+async fn get_ca_status() -> Result<Box<dyn warp::Reply>, Infallible> {
+    let configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to load config: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    match configuration.ca.status().await {
+        Ok(status) => Ok(Box::new(warp::reply::json(&status))),
+        Err(err) => {
+            log::error!("Failed to get CA status: {err}");
+            Ok(Box::new(get_error_response(err)))
+        }
+    }
+}
+
+/// Persists a configuration with a changed CA and starts signing certificates with it.
+async fn apply_ca_configuration(
+    configuration: Configuration,
+    configuration_updater_sender: Sender<Configuration>,
+    cert_cache: CertCache,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    if let Err(err) = configuration.save().await {
+        log::error!("Failed to save configuration: {err}");
+        return Ok(Box::new(get_error_response(err)));
+    }
+
+    if let Err(err) = cert_cache.sync_with(&configuration.ca).await {
+        log::error!("Failed to load new CA certificate: {err}");
+        return Ok(Box::new(get_error_response(err)));
+    }
+
     configuration_updater_sender
         .send(configuration.clone())
         .await
@@ -100,6 +251,7 @@ async fn put_ca_certificates(
 pub(super) fn create_routes(
     configuration_updater_sender: Sender<Configuration>,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    cert_cache: CertCache,
     notify_reload: Arc<Notify>,
 ) -> BoxedFilter<(impl warp::Reply,)> {
     warp::path::end()
@@ -114,9 +266,41 @@ pub(super) fn create_routes(
                     .and(with_configuration_save_lock(
                         configuration_save_lock.clone(),
                     ))
+                    .and(with_cert_cache(cert_cache.clone()))
                     .and(with_notify_reload(notify_reload.clone()))
                     .and_then(self::put_ca_certificates)),
         )
+        .or(warp::path("generate").and(
+            warp::path::end()
+                .and(warp::post())
+                .and(warp::body::json())
+                .and(with_configuration_updater_sender(
+                    configuration_updater_sender.clone(),
+                ))
+                .and(with_configuration_save_lock(
+                    configuration_save_lock.clone(),
+                ))
+                .and(with_cert_cache(cert_cache.clone()))
+                .and_then(self::generate_ca_certificate),
+        ))
+        .or(warp::path("rotate").and(
+            warp::path::end()
+                .and(warp::post())
+                .and(warp::body::json())
+                .and(with_configuration_updater_sender(
+                    configuration_updater_sender.clone(),
+                ))
+                .and(with_configuration_save_lock(
+                    configuration_save_lock.clone(),
+                ))
+                .and(with_cert_cache(cert_cache))
+                .and_then(self::rotate_ca_certificate),
+        ))
+        .or(warp::path("status").and(
+            warp::path::end()
+                .and(warp::get())
+                .and_then(self::get_ca_status),
+        ))
         .or(warp::path("validate").and(
             warp::path::end()
                 .and(warp::post())
@@ -1,4 +1,5 @@
 use super::get_error_response;
+use crate::cert::CertCache;
 use crate::configuration::Configuration;
 use std::sync::Arc;
 use tokio::sync::mpsc::Sender;
@@ -12,6 +13,7 @@ mod network;
 pub(crate) fn create_routes(
     configuration_updater_sender: Sender<Configuration>,
     configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    cert_cache: CertCache,
     notify_reload: Arc<Notify>,
 ) -> BoxedFilter<(impl warp::Reply,)> {
     let network_settings_route = warp::path("network").and(network::create_routes(
@@ -23,6 +25,7 @@ pub(crate) fn create_routes(
     let ca_cert_route = warp::path("ca-certificate").and(ca_certificate::create_routes(
         configuration_updater_sender.clone(),
         configuration_save_lock.clone(),
+        cert_cache,
         notify_reload.clone(),
     ));
 
@@ -0,0 +1,71 @@
+use super::get_error_response;
+use crate::configuration::{Configuration, UserContent};
+use crate::proxy::user_content::UserContentStore;
+use std::{convert::Infallible, sync::Arc};
+use tokio::sync::mpsc::Sender;
+use warp::filters::BoxedFilter;
+use warp::http::StatusCode;
+use warp::Filter as RouteFilter;
+
+async fn get_user_content() -> Result<Box<dyn warp::Reply>, Infallible> {
+    let configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to get user content: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    Ok(Box::new(warp::reply::json(&configuration.user_content)))
+}
+
+async fn put_user_content(
+    user_content: Vec<UserContent>,
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    user_content_store: UserContentStore,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let _guard = configuration_save_lock.lock().await;
+
+    let mut configuration = match Configuration::read_from_home().await {
+        Ok(configuration) => configuration,
+        Err(err) => {
+            log::error!("Failed to put user content: {err}");
+            return Ok(Box::new(get_error_response(err)));
+        }
+    };
+
+    if let Err(err) = configuration
+        .set_user_content(user_content, user_content_store)
+        .await
+    {
+        return Ok(Box::new(get_error_response(err)));
+    }
+
+    configuration_updater_sender
+        .send(configuration.clone())
+        .await
+        .unwrap();
+
+    Ok(Box::new(StatusCode::ACCEPTED))
+}
+
+pub fn create_routes(
+    configuration_updater_sender: Sender<Configuration>,
+    configuration_save_lock: Arc<tokio::sync::Mutex<()>>,
+    user_content_store: UserContentStore,
+) -> BoxedFilter<(impl warp::Reply,)> {
+    warp::get()
+        .and_then(self::get_user_content)
+        .or(warp::put()
+            .and(warp::body::json())
+            .and(super::with_configuration_updater_sender(
+                configuration_updater_sender.clone(),
+            ))
+            .and(super::with_configuration_save_lock(
+                configuration_save_lock.clone(),
+            ))
+            .and(super::with_user_content_store(user_content_store))
+            .and_then(self::put_user_content))
+        .boxed()
+}
@@ -1,9 +1,27 @@
 #![allow(clippy::unnecessary_operation)]
 #![allow(clippy::let_unit_value)]
+use gloo_timers::callback::Interval;
 use reqwasm::http::Request;
+use serde::Deserialize;
 use wasm_bindgen_futures::spawn_local;
+use web_sys::HtmlSelectElement;
+use yew::prelude::*;
 use yew::{classes, html, Component, Context, Html};
 
+/// Pause durations offered, in seconds. An empty value pauses until blocking is resumed.
+const PAUSE_DURATIONS: &[(&str, &str)] = &[
+    ("900", "15 minutes"),
+    ("3600", "1 hour"),
+    ("28800", "8 hours"),
+    ("", "Until resumed"),
+];
+
+#[derive(Deserialize)]
+struct Pause {
+    scope: serde_json::Value,
+    remaining_seconds: Option<i64>,
+}
+
 pub enum ButtonState {
     Loading,
     Ready,
@@ -12,15 +30,31 @@ pub enum ButtonState {
 pub struct BlockingEnabled {
     blocking_enabled: bool,
     button_state: ButtonState,
+    pause_duration: String,
+    /// Time left before blocking resumes by itself.
+    remaining_seconds: Option<i64>,
+    _countdown: Interval,
 }
 
 #[derive(Debug)]
 pub enum Message {
     EnableBlocking,
     DisableBlocking,
+    UpdatePauseDuration(String),
     BlockingEnabled,
-    BlockingDisabled,
+    BlockingDisabled(Option<i64>),
     SetCurrentBlockingState,
+    Tick,
+}
+
+fn format_remaining_time(seconds: i64) -> String {
+    let (hours, minutes, seconds) = (seconds / 3600, seconds % 3600 / 60, seconds % 60);
+
+    if hours > 0 {
+        format!("{hours}h {minutes:02}m")
+    } else {
+        format!("{minutes}m {seconds:02}s")
+    }
 }
 
 impl Component for BlockingEnabled {
@@ -30,9 +64,15 @@ impl Component for BlockingEnabled {
     fn create(ctx: &Context<Self>) -> Self {
         ctx.link().send_message(Message::SetCurrentBlockingState);
 
+        let link = ctx.link().clone();
+        let countdown = Interval::new(1_000, move || link.send_message(Message::Tick));
+
         Self {
             blocking_enabled: true,
             button_state: ButtonState::Loading,
+            pause_duration: PAUSE_DURATIONS[0].0.to_string(),
+            remaining_seconds: None,
+            _countdown: countdown,
         }
     }
 
@@ -57,50 +97,83 @@ impl Component for BlockingEnabled {
                                 return;
                             }
 
-                            message_callback.emit(Message::BlockingDisabled)
+                            message_callback.emit(Message::SetCurrentBlockingState)
                         }
-                        Err(_) => message_callback.emit(Message::BlockingDisabled),
+                        Err(_) => message_callback.emit(Message::SetCurrentBlockingState),
                     }
                 });
             }
             Message::DisableBlocking => {
                 self.button_state = ButtonState::Loading;
 
-                let request = base_request.body("false");
+                let duration_seconds = match self.pause_duration.parse::<u32>() {
+                    Ok(duration_seconds) => duration_seconds.to_string(),
+                    Err(_) => "null".to_string(),
+                };
+
+                let request = Request::post("/api/blocking-enabled/pauses")
+                    .header("Content-Type", "application/json")
+                    .body(format!(
+                        r#"{{"scope":"everything","duration_seconds":{duration_seconds}}}"#
+                    ));
 
                 spawn_local(async move {
                     if let Ok(response) = request.send().await {
                         if response.ok() {
-                            message_callback.emit(Message::BlockingDisabled);
+                            if let Ok(pause) = response.json::<Pause>().await {
+                                message_callback
+                                    .emit(Message::BlockingDisabled(pause.remaining_seconds));
+                            }
                         }
                     }
                 });
             }
+            Message::UpdatePauseDuration(pause_duration) => {
+                self.pause_duration = pause_duration;
+            }
             Message::BlockingEnabled => {
                 self.button_state = ButtonState::Ready;
                 self.blocking_enabled = true;
+                self.remaining_seconds = None;
             }
-            Message::BlockingDisabled => {
+            Message::BlockingDisabled(remaining_seconds) => {
                 self.button_state = ButtonState::Ready;
                 self.blocking_enabled = false;
+                self.remaining_seconds = remaining_seconds;
             }
             Message::SetCurrentBlockingState => {
-                let request = Request::get("/api/blocking-enabled");
+                let request = Request::get("/api/blocking-enabled/pauses");
 
                 spawn_local(async move {
                     if let Ok(response) = request.send().await {
                         if response.ok() {
-                            if let Ok(value) = response.json::<bool>().await {
-                                if value {
-                                    message_callback.emit(Message::BlockingEnabled)
-                                } else {
-                                    message_callback.emit(Message::BlockingDisabled)
+                            if let Ok(pauses) = response.json::<Vec<Pause>>().await {
+                                // Scoped pauses leave blocking enabled for everyone else.
+                                let global_pause =
+                                    pauses.into_iter().find(|pause| pause.scope == "everything");
+
+                                match global_pause {
+                                    Some(pause) => message_callback
+                                        .emit(Message::BlockingDisabled(pause.remaining_seconds)),
+                                    None => message_callback.emit(Message::BlockingEnabled),
                                 }
                             };
                         }
                     }
                 });
             }
+            Message::Tick => match self.remaining_seconds {
+                Some(remaining_seconds) if remaining_seconds > 1 => {
+                    self.remaining_seconds = Some(remaining_seconds - 1);
+                }
+                // The pause expired, the server has the final word on whether another applies.
+                Some(_) => {
+                    self.remaining_seconds = None;
+                    self.blocking_enabled = true;
+                    ctx.link().send_message(Message::SetCurrentBlockingState);
+                }
+                None => return false,
+            },
         }
 
         true
@@ -139,7 +212,18 @@ impl Component for BlockingEnabled {
         }
 
         if self.blocking_enabled {
+            let on_pause_duration_change = ctx.link().callback(|e: Event| {
+                let select: HtmlSelectElement = e.target_unchecked_into();
+                Message::UpdatePauseDuration(select.value())
+            });
+
             html! {
+            <span class="inline-flex items-center">
+            <select onchange={on_pause_duration_change} class="mr-2 shadow border rounded py-2 px-3 text-sm text-gray-700">
+                { for PAUSE_DURATIONS.iter().map(|(value, label)| html! {
+                    <option value={*value} selected={self.pause_duration == *value}>{ *label }</option>
+                }) }
+            </select>
             <button onclick={disable_blocking} type="button"
                 class={classes!(button_classes, "focus:ring-red-500", "bg-red-600", "hover:bg-red-700")}>
                 <svg xmlns="http://www.w3.org/2000/svg" class="-ml-0.5 mr-2 h-5 w-5" fill="none"
@@ -149,9 +233,20 @@ impl Component for BlockingEnabled {
                 </svg>
                 {"Pause blocking"}
             </button>
+            </span>
             }
         } else {
+            let remaining_time = match self.remaining_seconds {
+                Some(remaining_seconds) => format!(
+                    "Blocking resumes in {}",
+                    format_remaining_time(remaining_seconds)
+                ),
+                None => "Blocking is paused until resumed".to_string(),
+            };
+
             html! {
+            <span class="inline-flex items-center">
+            <span class="mr-3 text-sm text-gray-500">{ remaining_time }</span>
             <button onclick={enable_blocking} type="button"
                 class={classes!(button_classes, "focus:ring-green-500", "bg-green-600", "hover:bg-green-700")}>
                 <svg xmlns="http://www.w3.org/2000/svg" class="-ml-0.5 mr-2 h-5 w-5" fill="none" viewBox="0 0 24 24"
@@ -163,6 +258,7 @@ impl Component for BlockingEnabled {
                 </svg>
                 {"Resume blocking"}
             </button>
+            </span>
             }
         }
     }
@@ -0,0 +1,413 @@
+#![allow(clippy::unnecessary_operation)]
+#![allow(clippy::let_unit_value)]
+use crate::button::{ButtonColor, ButtonState, PrivaxyButton};
+use crate::ApiError;
+use reqwasm::http::Request;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen_futures::spawn_local;
+use web_sys::HtmlSelectElement;
+use yew::prelude::*;
+use yew::{html, Component, Context, Html};
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct CaStatus {
+    not_after: String,
+    days_remaining: i64,
+    expires_soon: bool,
+    expired: bool,
+    previous_ca_trusted_until: Option<String>,
+    name_constraints: NameConstraints,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NameConstraints {
+    permitted: Vec<String>,
+    excluded: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct CaCertificateInfo {
+    sha256_fingerprint: String,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct CaInfo {
+    certificates: Vec<CaCertificateInfo>,
+    downloads: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct RotateCaRequest {
+    key_type: String,
+    validity_days: u32,
+    overlap_days: u32,
+    name_constraints: NameConstraints,
+}
+
+fn parse_domain_list(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|domain| !domain.is_empty())
+        .map(|domain| domain.to_string())
+        .collect()
+}
+
+async fn fetch_ca_status() -> Option<CaStatus> {
+    match Request::get("/api/settings/ca-certificate/status")
+        .send()
+        .await
+    {
+        Ok(response) if response.ok() => response.json::<CaStatus>().await.ok(),
+        Ok(response) => {
+            log::error!("Failed to load CA status: {:?}", response.status());
+            None
+        }
+        Err(err) => {
+            log::error!("Request error: {:?}", err);
+            None
+        }
+    }
+}
+
+fn render_expiry_warning(status: &CaStatus) -> Html {
+    if status.expired {
+        html! {
+            <div class="rounded-md bg-red-50 p-4 mb-4">
+                <p class="text-sm font-medium text-red-800">
+                    {format!("The CA certificate expired on {}. Generate a new one from the settings.", status.not_after)}
+                </p>
+            </div>
+        }
+    } else if status.expires_soon {
+        html! {
+            <div class="rounded-md bg-yellow-50 p-4 mb-4">
+                <p class="text-sm font-medium text-yellow-800">
+                    {format!("The CA certificate expires in {} days. Consider rotating it from the settings.", status.days_remaining)}
+                </p>
+            </div>
+        }
+    } else {
+        html! {}
+    }
+}
+
+pub enum ExpiryWarningMessage {
+    Loaded(CaStatus),
+}
+
+/// Banner warning about an expired or soon to expire CA.
+pub struct CaExpiryWarning {
+    status: Option<CaStatus>,
+}
+
+impl Component for CaExpiryWarning {
+    type Message = ExpiryWarningMessage;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let link = ctx.link().clone();
+        spawn_local(async move {
+            if let Some(status) = fetch_ca_status().await {
+                link.send_message(ExpiryWarningMessage::Loaded(status));
+            }
+        });
+
+        Self { status: None }
+    }
+
+    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            ExpiryWarningMessage::Loaded(status) => self.status = Some(status),
+        }
+        true
+    }
+
+    fn view(&self, _ctx: &Context<Self>) -> Html {
+        match &self.status {
+            Some(status) => render_expiry_warning(status),
+            None => html! {},
+        }
+    }
+}
+
+pub enum Message {
+    Load,
+    Loaded(CaStatus),
+    LoadedInfo(CaInfo),
+    UpdateKeyType(String),
+    UpdateValidityDays(String),
+    UpdateOverlapDays(String),
+    UpdatePermittedDomains(String),
+    UpdateExcludedDomains(String),
+    Generate,
+    Rotate,
+    Done,
+    Failed(ApiError),
+}
+
+/// Status of the CA along with controls to generate a new one or to rotate it.
+pub struct CaLifecycle {
+    status: Option<CaStatus>,
+    info: Option<CaInfo>,
+    key_type: String,
+    validity_days: String,
+    overlap_days: String,
+    permitted_domains: String,
+    excluded_domains: String,
+    loading: bool,
+    error: Option<String>,
+}
+
+impl CaLifecycle {
+    fn parsed_request(&self) -> Option<RotateCaRequest> {
+        Some(RotateCaRequest {
+            key_type: self.key_type.clone(),
+            validity_days: self.validity_days.parse().ok().filter(|days| *days > 0)?,
+            overlap_days: self.overlap_days.parse().ok()?,
+            name_constraints: NameConstraints {
+                permitted: parse_domain_list(&self.permitted_domains),
+                excluded: parse_domain_list(&self.excluded_domains),
+            },
+        })
+    }
+
+    fn button_state(&self) -> ButtonState {
+        if self.loading {
+            ButtonState::Loading
+        } else if self.parsed_request().is_some() {
+            ButtonState::Enabled
+        } else {
+            ButtonState::Disabled
+        }
+    }
+
+    fn send(&mut self, ctx: &Context<Self>, url: &'static str) {
+        let request = match self.parsed_request() {
+            Some(request) => request,
+            None => return,
+        };
+
+        self.loading = true;
+        let link = ctx.link().clone();
+
+        spawn_local(async move {
+            let request = Request::post(url)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(&request).unwrap());
+
+            match request.send().await {
+                Ok(response) if response.ok() => link.send_message(Message::Done),
+                Ok(response) => match response.json::<ApiError>().await {
+                    Ok(err) => link.send_message(Message::Failed(err)),
+                    Err(err) => link.send_message(Message::Failed(ApiError {
+                        error: format!("{:?}", err),
+                    })),
+                },
+                Err(err) => link.send_message(Message::Failed(ApiError {
+                    error: format!("{:?}", err),
+                })),
+            }
+        });
+    }
+}
+
+impl Component for CaLifecycle {
+    type Message = Message;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        ctx.link().send_message(Message::Load);
+
+        Self {
+            status: None,
+            info: None,
+            key_type: "rsa2048".to_string(),
+            validity_days: "3650".to_string(),
+            overlap_days: "30".to_string(),
+            permitted_domains: String::new(),
+            excluded_domains: String::new(),
+            loading: false,
+            error: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Message::Load => {
+                let link = ctx.link().clone();
+                spawn_local(async move {
+                    if let Some(status) = fetch_ca_status().await {
+                        link.send_message(Message::Loaded(status));
+                    }
+
+                    match Request::get("/api/ca").send().await {
+                        Ok(response) => match response.json::<CaInfo>().await {
+                            Ok(info) => link.send_message(Message::LoadedInfo(info)),
+                            Err(err) => log::error!("Failed to decode CA info: {:?}", err),
+                        },
+                        Err(err) => log::error!("Request error: {:?}", err),
+                    }
+                });
+            }
+            Message::LoadedInfo(info) => {
+                self.info = Some(info);
+            }
+            Message::Loaded(status) => {
+                self.permitted_domains = status.name_constraints.permitted.join(", ");
+                self.excluded_domains = status.name_constraints.excluded.join(", ");
+                self.status = Some(status);
+            }
+            Message::UpdateKeyType(value) => self.key_type = value,
+            Message::UpdateValidityDays(value) => self.validity_days = value,
+            Message::UpdateOverlapDays(value) => self.overlap_days = value,
+            Message::UpdatePermittedDomains(value) => self.permitted_domains = value,
+            Message::UpdateExcludedDomains(value) => self.excluded_domains = value,
+            Message::Generate => self.send(ctx, "/api/settings/ca-certificate/generate"),
+            Message::Rotate => self.send(ctx, "/api/settings/ca-certificate/rotate"),
+            Message::Done => {
+                self.loading = false;
+                self.error = None;
+                ctx.link().send_message(Message::Load);
+            }
+            Message::Failed(err) => {
+                self.loading = false;
+                self.error = Some(err.error);
+            }
+        }
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let status = match &self.status {
+            Some(status) => html! {
+                <>
+                { render_expiry_warning(status) }
+                <p class="text-gray-600 text-sm">{format!("Current CA valid until {} ({} days remaining).", status.not_after, status.days_remaining)}</p>
+                if let Some(until) = &status.previous_ca_trusted_until {
+                    <p class="text-gray-600 text-sm">{format!("The previous CA keeps signing certificates until {}.", until)}</p>
+                }
+                </>
+            },
+            None => html! {<div>{"Loading..."}</div>},
+        };
+
+        let distribution = match &self.info {
+            Some(info) => html! {
+                <div class="mt-2">
+                    { for info.certificates.iter().map(|certificate| html! {
+                        <p class="text-gray-600 text-sm break-all">{format!("SHA-256 fingerprint: {}", certificate.sha256_fingerprint)}</p>
+                    }) }
+                    <p class="text-gray-600 text-sm mt-2">
+                        {"Download: "}
+                        { for info.downloads.iter().map(|download| html! {
+                            <a class="text-blue-600 hover:underline mr-3" href={download.clone()}>
+                                { download.rsplit('.').next().unwrap_or_default().to_string() }
+                            </a>
+                        }) }
+                    </p>
+                </div>
+            },
+            None => html! {},
+        };
+
+        let on_key_type_change = ctx.link().callback(|e: Event| {
+            let select: HtmlSelectElement = e.target_unchecked_into();
+            Message::UpdateKeyType(select.value())
+        });
+
+        let render_input = |name: &str,
+                            input_type: &str,
+                            value: String,
+                            oninput: Callback<InputEvent>,
+                            description: &str| {
+            html! {
+                <div class="mb-4" style="display: flex; flex-direction: column; width: 100%; padding: 2px 0;">
+                    <div style="display: flex; align-items: center; width: 100%;">
+                        <div class="text-gray-500" style="width: 200px; text-align: left; padding-right: 4px;">{ name }</div>
+                        <div style="flex-grow: 1;">
+                            <input value={value} {oninput} type={input_type.to_string()} class="shadow appearance-none border rounded w-80 py-2 px-3 text-gray-700 leading-tight focus:outline-none focus:shadow-outline" />
+                        </div>
+                    </div>
+                    <div style="margin-left: 200px;">
+                        <p class="text-gray-400 text-sm">{description}</p>
+                    </div>
+                </div>
+            }
+        };
+
+        html! {
+            <fieldset class="mb-8" style="width: 100%;">
+                <legend class="text-lg font-medium text-gray-900">{"Certificate authority"}</legend>
+                <div class="mt-4">{ status }{ distribution }</div>
+                <div class="mt-4 border-t border-b border-gray-200 divide-y divide-gray-200">
+                    <div class="mb-4" style="display: flex; align-items: center; width: 100%; padding: 2px 0;">
+                        <div class="text-gray-500" style="width: 200px; text-align: left; padding-right: 4px;">{"Key type"}</div>
+                        <select onchange={on_key_type_change} class="shadow border rounded w-80 py-2 px-3 text-gray-700">
+                            <option value="rsa2048" selected={self.key_type == "rsa2048"}>{"RSA 2048"}</option>
+                            <option value="rsa4096" selected={self.key_type == "rsa4096"}>{"RSA 4096"}</option>
+                            <option value="ecdsa_p256" selected={self.key_type == "ecdsa_p256"}>{"ECDSA P-256"}</option>
+                            <option value="ecdsa_p384" selected={self.key_type == "ecdsa_p384"}>{"ECDSA P-384"}</option>
+                        </select>
+                    </div>
+                    { render_input(
+                        "Validity (days)",
+                        "number",
+                        self.validity_days.clone(),
+                        ctx.link().callback(|e: InputEvent| {
+                            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                            Message::UpdateValidityDays(input.value())
+                        }),
+                        "How long the generated CA is valid for"
+                    ) }
+                    { render_input(
+                        "Overlap (days)",
+                        "number",
+                        self.overlap_days.clone(),
+                        ctx.link().callback(|e: InputEvent| {
+                            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                            Message::UpdateOverlapDays(input.value())
+                        }),
+                        "When rotating, how long the current CA keeps signing certificates while clients install the new one"
+                    ) }
+                    { render_input(
+                        "Permitted domains",
+                        "text",
+                        self.permitted_domains.clone(),
+                        ctx.link().callback(|e: InputEvent| {
+                            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                            Message::UpdatePermittedDomains(input.value())
+                        }),
+                        "Comma separated domains the CA may sign certificates for, including their subdomains. Leave empty to allow any domain. Other hosts are not filtered."
+                    ) }
+                    { render_input(
+                        "Excluded domains",
+                        "text",
+                        self.excluded_domains.clone(),
+                        ctx.link().callback(|e: InputEvent| {
+                            let input: web_sys::HtmlInputElement = e.target_unchecked_into();
+                            Message::UpdateExcludedDomains(input.value())
+                        }),
+                        "Comma separated domains the CA may never sign certificates for"
+                    ) }
+                </div>
+                if let Some(error) = &self.error {
+                    <p class="text-red-500 text-xs italic mt-2">{error}</p>
+                }
+                <div class="mt-5 space-x-3">
+                    <PrivaxyButton
+                        state={self.button_state()}
+                        onclick={ctx.link().callback(|_| Message::Rotate)}
+                        color={ButtonColor::Blue}
+                        button_text={"Rotate CA".to_string()}
+                    />
+                    <PrivaxyButton
+                        state={self.button_state()}
+                        onclick={ctx.link().callback(|_| Message::Generate)}
+                        color={ButtonColor::Red}
+                        button_text={"Generate new CA".to_string()}
+                    />
+                </div>
+            </fieldset>
+        }
+    }
+}
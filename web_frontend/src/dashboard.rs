@@ -1,6 +1,7 @@
 #![allow(clippy::unnecessary_operation)]
 #![allow(clippy::let_unit_value)]
 use crate::blocking_enabled::BlockingEnabled;
+use crate::ca_lifecycle::CaExpiryWarning;
 use futures::future::{AbortHandle, Abortable};
 use futures::StreamExt;
 use gloo_timers::future::TimeoutFuture;
@@ -20,6 +21,8 @@ pub struct Message {
     top_blocked_paths: Vec<(String, u64)>,
     #[serde(with = "tuple_vec_map")]
     top_clients: Vec<(String, u64)>,
+    #[serde(default, with = "tuple_vec_map")]
+    top_blocking_filter_lists: Vec<(String, u64)>,
 }
 
 pub struct Dashboard {
@@ -106,6 +109,7 @@ impl Component for Dashboard {
                 modified_responses: None,
                 top_blocked_paths: Vec::new(),
                 top_clients: Vec::new(),
+                top_blocking_filter_lists: Vec::new(),
             },
         }
     }
@@ -141,6 +145,7 @@ impl Component for Dashboard {
 
         html! {
             <>
+                <CaExpiryWarning />
                 <div class="md:flex md:justify-between md:space-x-5">
                     <div class="pt-1.5">
                         <h1 class="text-2xl font-bold text-gray-900">{ "Dashboard" }<div
@@ -149,7 +154,7 @@ impl Component for Dashboard {
                     </div>
                     <div
                         class="mt-6 flex flex-col-reverse justify-stretch space-y-4 space-y-reverse sm:flex-row-reverse sm:justify-end sm:space-x-reverse sm:space-y-0 sm:space-x-3 md:mt-0 md:flex-row md:space-x-3">
-                        <a href="/api/settings/ca-certificate"
+                        <a href="/api/ca/privaxy-ca.pem"
                         class="inline-flex items-center justify-center px-4 py-2 border border-gray-300 shadow-sm text-sm font-medium rounded-md text-white bg-gray-800 hover:bg-gray-900 focus:outline-none focus:ring-2 focus:ring-offset-2 focus:ring-offset-gray-100 focus:ring-gray-500">
                         <svg xmlns="http://www.w3.org/2000/svg" class="ml-0.5 mr-2 h-5 w-5" fill="none"
                             viewBox="0 0 24 24" stroke="currentColor">
@@ -221,6 +226,17 @@ impl Component for Dashboard {
                             </ol>
                         </div>
                     </div>
+                    <div class="mt-4 bg-white overflow-hidden shadow rounded-lg divide-y divide-gray-200">
+                        <div class="px-4 py-5 sm:px-6">
+                            <h3 class="text-lg font-medium">{"Top blocking filter lists"}</h3>
+                        </div>
+                        <div class="px-4 py-5 sm:p-6">
+                            <ol role="list" class="divide-y divide-gray-200">
+                                { for self.message.top_blocking_filter_lists.iter().map(|(filter_list,
+                                count)|render_list_element(filter_list, *count)) }
+                            </ol>
+                        </div>
+                    </div>
                 </div>
             </>
         }
@@ -2,6 +2,7 @@
 #![allow(clippy::let_unit_value)]
 use crate::button::ButtonState;
 use crate::button::{get_css, ButtonColor};
+use crate::ca_lifecycle::CaLifecycle;
 use crate::failure_banner;
 use crate::success_banner;
 use crate::{save_button, ApiError};
@@ -148,7 +149,7 @@ impl GeneralSettings {
             }
         }
     }
-    
+
     fn validate(&self) -> bool {
         match &self.network_settings {
             None => false,
@@ -538,6 +539,9 @@ impl Component for GeneralSettings {
                     {render_category("Certificate", SettingCategories::Certificate(self.ca_config.clone()))}
 
             {save_button!(save_callback, save_button_state)}
+            <div class="mt-8">
+                <CaLifecycle />
+            </div>
             </>
         }
     }
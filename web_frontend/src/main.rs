@@ -8,6 +8,7 @@ use yew_router::prelude::*;
 
 mod blocking_enabled;
 mod button;
+mod ca_lifecycle;
 mod dashboard;
 mod filterlists;
 mod filters;
@@ -15,6 +15,8 @@ pub struct Message {
     method: String,
     url: String,
     is_request_blocked: bool,
+    #[serde(default)]
+    filter_list: Option<String>,
 }
 
 pub struct Requests {
@@ -94,6 +96,9 @@ impl Component for Requests {
                 <td class="px-6 py-4 whitespace-nowrap text-sm text-gray-500">
                     {&element.url}
                 </td>
+                <td class="w-1/12 px-6 py-4 whitespace-nowrap text-sm text-gray-500">
+                    { element.filter_list.as_deref().unwrap_or_default() }
+                </td>
             </tr>
                 }
         }
@@ -123,6 +128,10 @@ impl Component for Requests {
                           class="px-6 py-3 text-left text-xs font-medium text-gray-500 uppercase tracking-wider">
                           {"Path"}
                         </th>
+                        <th scope="col"
+                          class="px-6 py-3 text-left text-xs font-medium text-gray-500 uppercase tracking-wider">
+                          {"Filter list"}
+                        </th>
                       </tr>
                     </thead>
                     <tbody class="w-full bg-white divide-y divide-gray-200">
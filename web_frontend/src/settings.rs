@@ -16,6 +16,8 @@ pub enum SettingsRoute {
     Filters,
     #[at("/settings/exclusions")]
     Exclusions,
+    #[at("/settings/allowlist")]
+    Allowlist,
     #[at("/settings/custom-filters")]
     CustomFilters,
 }
@@ -77,6 +79,22 @@ pub fn switch_settings(route: &SettingsRoute) -> Html {
 
             html! {<SettingsTextarea h1="Exclusions" {description} input_name="exclusions" {textarea_description} {resource_url} />}
         }
+        SettingsRoute::Allowlist => {
+            set_title("Settings - Allowlist");
+
+            let resource_url = "/api/allowlist";
+
+            let description = html! {<div class="text-gray-600">
+                    <p>
+                        {"Blocking is disabled on allowlisted sites, their traffic is still intercepted and shows up in the requests feed. "}
+                        {"Entries cover their subdomains and can contain wildcards."}
+                    </p>
+                </div>
+            };
+            let textarea_description = "Insert one entry per line";
+
+            html! {<SettingsTextarea h1="Allowlist" {description} input_name="allowlist" {textarea_description} {resource_url} />}
+        }
         SettingsRoute::CustomFilters => {
             set_title("Settings - Custom Filters");
 
@@ -99,6 +117,7 @@ pub fn switch_settings(route: &SettingsRoute) -> Html {
         <Link<SettingsRoute> classes={get_classes(*route, SettingsRoute::General)} to={SettingsRoute::General}> <span class="truncate">{ "General" }</span></Link<SettingsRoute>>
         <Link<SettingsRoute> classes={get_classes(*route, SettingsRoute::Filters)} to={SettingsRoute::Filters}> <span class="truncate">{ "Filters" }</span></Link<SettingsRoute>>
         <Link<SettingsRoute> classes={get_classes(*route, SettingsRoute::Exclusions)} to={SettingsRoute::Exclusions}> <span class="truncate">{ "Exclusions" }</span></Link<SettingsRoute>>
+        <Link<SettingsRoute> classes={get_classes(*route, SettingsRoute::Allowlist)} to={SettingsRoute::Allowlist}> <span class="truncate">{ "Allowlist" }</span></Link<SettingsRoute>>
         <Link<SettingsRoute> classes={get_classes(*route, SettingsRoute::CustomFilters)} to={SettingsRoute::CustomFilters}> <span class="truncate">{ "Custom filters" }</span></Link<SettingsRoute>>
     </nav>
         <div class="container mx-auto px-4 sm:px-6 lg:px-8 mt-4 sm:col-span-6">{ content }</div>